@@ -0,0 +1,42 @@
+CREATE TABLE admins(
+    telegram_id VARCHAR(50) PRIMARY KEY,
+    "name" VARCHAR(200) NOT NULL
+);
+CREATE TABLE authorizations(
+    command VARCHAR(50) NOT NULL,
+    chat_id VARCHAR(50) NOT NULL
+);
+CREATE TABLE bureau_polls(
+    poll_id VARCHAR(50) PRIMARY KEY,
+    chat_id VARCHAR(50) NOT NULL,
+    sent_at BIGINT NOT NULL
+);
+CREATE TABLE bureau_answers(
+    poll_id VARCHAR(50) NOT NULL REFERENCES bureau_polls(poll_id) ON DELETE CASCADE,
+    telegram_id VARCHAR(50) NOT NULL,
+    "name" VARCHAR(200) NOT NULL,
+    option_id BIGINT NOT NULL,
+    answered_at BIGINT NOT NULL,
+    PRIMARY KEY(poll_id, telegram_id)
+);
+CREATE TABLE permanence_slots(
+    id BIGSERIAL PRIMARY KEY,
+    chat_id VARCHAR(50) NOT NULL,
+    weekday BIGINT NOT NULL,
+    start_minute BIGINT NOT NULL,
+    duration BIGINT NOT NULL,
+    last_reminder BIGINT
+);
+CREATE TABLE permanence_signups(
+    slot_id BIGINT NOT NULL REFERENCES permanence_slots(id) ON DELETE CASCADE,
+    telegram_id VARCHAR(50) NOT NULL,
+    "name" VARCHAR(200) NOT NULL,
+    PRIMARY KEY(slot_id, telegram_id)
+);
+CREATE TABLE event_subscriptions(
+    chat_id VARCHAR(50) NOT NULL PRIMARY KEY
+);
+CREATE TABLE announced_events(
+    event_id INTEGER PRIMARY KEY,
+    reminded BOOLEAN NOT NULL DEFAULT FALSE
+);
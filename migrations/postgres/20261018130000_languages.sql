@@ -0,0 +1,4 @@
+CREATE TABLE languages(
+    id VARCHAR(50) NOT NULL PRIMARY KEY,
+    "language" VARCHAR(10) NOT NULL
+);
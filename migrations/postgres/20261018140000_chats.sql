@@ -0,0 +1,4 @@
+CREATE TABLE chats(
+    chat_id VARCHAR(50) NOT NULL PRIMARY KEY,
+    title VARCHAR(200) NOT NULL
+);
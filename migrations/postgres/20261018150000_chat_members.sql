@@ -0,0 +1,3 @@
+-- Empty when the chat was recorded from a message before the kind was stored
+ALTER TABLE chats ADD COLUMN kind VARCHAR(20) NOT NULL DEFAULT '';
+ALTER TABLE chats ADD COLUMN joined_at BIGINT;
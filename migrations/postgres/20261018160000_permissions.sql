@@ -0,0 +1,8 @@
+-- Rules of the users, an empty chat_id meaning every chat
+CREATE TABLE permissions(
+    user_id VARCHAR(50) NOT NULL,
+    chat_id VARCHAR(50) NOT NULL,
+    command VARCHAR(50) NOT NULL,
+    allowed BOOLEAN NOT NULL,
+    PRIMARY KEY(user_id, chat_id, command)
+);
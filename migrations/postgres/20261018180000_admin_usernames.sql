@@ -0,0 +1,2 @@
+-- @username of the admin when known, to remove them with /adminremove @username
+ALTER TABLE admins ADD COLUMN username VARCHAR(50);
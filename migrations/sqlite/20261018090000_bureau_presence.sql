@@ -0,0 +1,13 @@
+CREATE TABLE bureau_polls(
+    poll_id VARCHAR(50) PRIMARY KEY,
+    chat_id VARCHAR(50) NOT NULL,
+    sent_at INTEGER NOT NULL
+);
+CREATE TABLE bureau_answers(
+    poll_id VARCHAR(50) NOT NULL REFERENCES bureau_polls(poll_id) ON DELETE CASCADE,
+    telegram_id VARCHAR(50) NOT NULL,
+    "name" VARCHAR(200) NOT NULL,
+    option_id INTEGER NOT NULL,
+    answered_at INTEGER NOT NULL,
+    PRIMARY KEY(poll_id, telegram_id)
+);
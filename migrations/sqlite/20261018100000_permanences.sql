@@ -0,0 +1,14 @@
+CREATE TABLE permanence_slots(
+    id INTEGER PRIMARY KEY,
+    chat_id VARCHAR(50) NOT NULL,
+    weekday INTEGER NOT NULL,
+    start_minute INTEGER NOT NULL,
+    duration INTEGER NOT NULL,
+    last_reminder INTEGER
+);
+CREATE TABLE permanence_signups(
+    slot_id INTEGER NOT NULL REFERENCES permanence_slots(id) ON DELETE CASCADE,
+    telegram_id VARCHAR(50) NOT NULL,
+    "name" VARCHAR(200) NOT NULL,
+    PRIMARY KEY(slot_id, telegram_id)
+);
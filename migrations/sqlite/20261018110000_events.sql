@@ -0,0 +1,7 @@
+CREATE TABLE event_subscriptions(
+    chat_id VARCHAR(50) NOT NULL PRIMARY KEY
+);
+CREATE TABLE announced_events(
+    event_id INTEGER PRIMARY KEY,
+    reminded BOOLEAN NOT NULL DEFAULT FALSE
+);
@@ -0,0 +1,2 @@
+-- Unix timestamp at which the authorization is removed, NULL for the permanent ones
+ALTER TABLE authorizations ADD COLUMN expires_at INTEGER;
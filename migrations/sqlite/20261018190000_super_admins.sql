@@ -0,0 +1,2 @@
+-- Super admins, who cannot be removed by the other admins
+ALTER TABLE admins ADD COLUMN super_admin BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,308 @@
+//! Command line of the bot, which serves it by default and provides administrative commands
+//! working on the same storage, e.g. to recover access when no admin is left.
+
+use std::{
+    fs::File,
+    io::{self, BufReader, Write},
+    path::{Path, PathBuf},
+};
+
+use chrono::Utc;
+use clap::{Parser, Subcommand};
+
+use crate::{
+    cmd_authentication::{expiry_date, parse_duration},
+    cmd_backup::create_backup,
+    commands::RESTRICTED_COMMANDS,
+    directus::refresh_committee,
+    storage::{restore_database, Admin, AdminRemoval, Repositories, Snapshot},
+};
+
+/// Telegram bot of the CLIC.
+#[derive(Parser)]
+#[command(version, about)]
+pub struct Args {
+    /// Path of the config file, `config.toml` in `DATA_DIR` by default
+    #[arg(short, long, global = true)]
+    pub config: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: Option<CliCommand>,
+}
+
+#[derive(Subcommand)]
+pub enum CliCommand {
+    #[command(flatten)]
+    Storage(StorageCommand),
+    /// Replace the SQLite database by a backup, the bot must be stopped
+    Restore { backup: PathBuf },
+}
+
+/// Commands working on the storage, which is opened before running them.
+#[derive(Subcommand)]
+pub enum StorageCommand {
+    /// Run the bot (default)
+    Serve,
+    /// Manage the admins
+    #[command(subcommand)]
+    Admin(AdminCommand),
+    /// Authorize a chat to use a command
+    Authorize {
+        #[arg(allow_negative_numbers = true)]
+        chat_id: i64,
+        command: String,
+        /// Duration of a temporary authorization, e.g. 12h, 7d or 2w
+        duration: Option<String>,
+    },
+    /// Delete everything about a chat the bot was removed from: its authorizations, the rules of
+    /// the users in it, its permanence slots and its subscription to the events
+    ForgetChat {
+        #[arg(allow_negative_numbers = true)]
+        chat_id: i64,
+    },
+    /// Create the database if needed and run the migrations
+    Migrate,
+    /// Export the admins, authorizations, permanences, event subscriptions, languages, chats and
+    /// permissions of the users as JSON
+    Export {
+        /// File to write to, the standard output by default
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Import data exported with `export`, skipping the entries already present
+    Import { input: PathBuf },
+    /// Fetch the committee from Directus and print it
+    SyncCommittee,
+    /// Back the SQLite database up, as done periodically by the bot
+    Backup,
+}
+
+#[derive(Subcommand)]
+pub enum AdminCommand {
+    /// Add an admin
+    Add {
+        telegram_id: i64,
+        name: String,
+        /// Make them a super admin, who cannot be removed by the other admins (also promotes an
+        /// existing admin)
+        #[arg(long = "super")]
+        super_admin: bool,
+    },
+    /// List the admins
+    List,
+    /// Remove an admin, unless they are the last one
+    Remove {
+        telegram_id: i64,
+        /// Also remove a super admin
+        #[arg(long)]
+        force: bool,
+    },
+    /// Make a super admin a regular admin
+    Demote { telegram_id: i64 },
+}
+
+pub type CliResult = Result<(), Box<dyn std::error::Error + Send + Sync>>;
+
+pub async fn run(command: StorageCommand, repositories: Repositories) -> CliResult {
+    match command {
+        StorageCommand::Serve => crate::serve(repositories).await,
+        StorageCommand::Admin(AdminCommand::Add {
+            telegram_id,
+            name,
+            super_admin,
+        }) => {
+            let telegram_id = telegram_id.to_string();
+            if !repositories.admins.is_admin(&telegram_id).await? {
+                repositories
+                    .admins
+                    .add_admin(&Admin {
+                        telegram_id: telegram_id.clone(),
+                        name: name.clone(),
+                        username: None,
+                        super_admin,
+                    })
+                    .await?;
+                let tier = if super_admin {
+                    "a super admin"
+                } else {
+                    "an admin"
+                };
+                println!("{name} ({telegram_id}) is now {tier}");
+            } else if super_admin {
+                repositories
+                    .admins
+                    .set_super_admin(&telegram_id, true)
+                    .await?;
+                println!("{telegram_id} is now a super admin");
+            } else {
+                println!("{telegram_id} is already an admin");
+            }
+        }
+        StorageCommand::Admin(AdminCommand::List) => {
+            for admin in repositories.admins.list_admins().await? {
+                let tier = if admin.super_admin {
+                    "\tsuper admin"
+                } else {
+                    ""
+                };
+                println!("{}\t{}{tier}", admin.telegram_id, admin.name);
+            }
+        }
+        StorageCommand::Admin(AdminCommand::Remove { telegram_id, force }) => {
+            let telegram_id = telegram_id.to_string();
+            // As from Telegram, where only the super admins can remove a super admin
+            let super_admin = repositories
+                .admins
+                .list_admins()
+                .await?
+                .iter()
+                .any(|a| a.telegram_id == telegram_id && a.super_admin);
+            if super_admin && !force {
+                return Err(format!(
+                    "{telegram_id} is a super admin: use --force to remove them anyway"
+                )
+                .into());
+            }
+            match repositories.admins.remove_admin(&telegram_id).await? {
+                AdminRemoval::Removed => println!("{telegram_id} is no longer an admin"),
+                AdminRemoval::NotAdmin => {
+                    return Err(format!("{telegram_id} is not an admin").into())
+                }
+                AdminRemoval::LastAdmin => {
+                    return Err(format!(
+                        "{telegram_id} is the last admin: add another one with `admin add` first"
+                    )
+                    .into())
+                }
+            }
+        }
+        StorageCommand::Admin(AdminCommand::Demote { telegram_id }) => {
+            let telegram_id = telegram_id.to_string();
+            if !repositories
+                .admins
+                .set_super_admin(&telegram_id, false)
+                .await?
+            {
+                return Err(format!("{telegram_id} is not an admin").into());
+            }
+            println!("{telegram_id} is now a regular admin");
+        }
+        StorageCommand::Authorize {
+            chat_id,
+            command,
+            duration,
+        } => {
+            if !RESTRICTED_COMMANDS.iter().any(|c| c.shortand() == command) {
+                return Err(format!("Unknown command: {command}").into());
+            }
+            let expires_at = match duration {
+                Some(duration) => {
+                    let date = parse_duration(&duration)
+                        .and_then(|parsed| expiry_date(Utc::now(), parsed))
+                        .ok_or_else(|| format!("Invalid duration: {duration}"))?;
+                    Some(date)
+                }
+                None => None,
+            };
+
+            let added = repositories
+                .authorizations
+                .authorize(
+                    &chat_id.to_string(),
+                    &command,
+                    expires_at.map(|date| date.timestamp()),
+                )
+                .await?;
+            match expires_at {
+                Some(date) => println!(
+                    "Chat {chat_id} can use /{command} until {}",
+                    date.format("%Y-%m-%d %H:%M UTC")
+                ),
+                None if added => println!("Chat {chat_id} can now use /{command}"),
+                None => println!("Chat {chat_id} can already use /{command}"),
+            }
+        }
+        StorageCommand::ForgetChat { chat_id } => {
+            let chat = chat_id.to_string();
+            repositories.chats.remove_chat(&chat).await?;
+            let authorizations = repositories.authorizations.unauthorize_chat(&chat).await?;
+            let rules = repositories
+                .permissions
+                .remove_chat_permissions(&chat)
+                .await?;
+            let slots = repositories.permanences.remove_chat_slots(&chat).await?;
+            let subscribed = repositories.events.unsubscribe_events(&chat).await?;
+            println!(
+                "Deleted {authorizations} authorization(s), {rules} rule(s) and {slots} permanence \
+                 slot(s) of the chat {chat_id}{}",
+                if subscribed {
+                    ", and its subscription to the events"
+                } else {
+                    ""
+                }
+            );
+        }
+        // The migrations are run when connecting to the database
+        StorageCommand::Migrate => println!("The database is up to date"),
+        StorageCommand::Export { output } => {
+            let snapshot = Snapshot::export(&repositories).await?;
+            let mut writer: Box<dyn Write> = match &output {
+                Some(path) => Box::new(File::create(path)?),
+                None => Box::new(io::stdout()),
+            };
+            serde_json::to_writer_pretty(&mut writer, &snapshot)?;
+            writeln!(writer)?;
+            if let Some(path) = output {
+                eprintln!("Exported to {}", path.display());
+            }
+        }
+        StorageCommand::Import { input } => {
+            let snapshot: Snapshot = serde_json::from_reader(BufReader::new(File::open(input)?))?;
+            let report = snapshot.import(&repositories).await?;
+            println!(
+                "Imported {} admin(s), {} authorization(s), {} permanence slot(s) with {} signup(s), \
+                {} event subscription(s), {} language preference(s), {} chat(s) and {} permission(s)",
+                report.admins,
+                report.authorizations,
+                report.permanence_slots,
+                report.permanence_signups,
+                report.event_subscriptions,
+                report.languages,
+                report.chats,
+                report.permissions,
+            );
+        }
+        StorageCommand::Backup => {
+            let backups = repositories
+                .backups
+                .ok_or("only SQLite databases can be backed up")?;
+            let path = create_backup(backups.as_ref()).await?;
+            println!("Backed the database up to {}", path.display());
+        }
+        StorageCommand::SyncCommittee => {
+            for member in refresh_committee().await? {
+                println!(
+                    "{}\t{}\t{} poll(s)",
+                    member.id, member.name, member.poll_count
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Replaces the database by the backup. It does not need the storage to be opened, and must not
+/// be run while it is.
+pub async fn restore(backup: &Path) -> CliResult {
+    match restore_database(backup).await? {
+        Some(previous) => println!(
+            "Restored {}, the previous database was saved to {}",
+            backup.display(),
+            previous.display()
+        ),
+        None => println!("Restored {}", backup.display()),
+    }
+
+    Ok(())
+}
@@ -0,0 +1,432 @@
+//! Management of the admins from Telegram: the admins can promote a user by replying to one of
+//! their messages or by mentioning them, and remove an admin after a confirmation. The last admin
+//! cannot be removed, and the super admins can only be removed by other super admins, so that the
+//! bot always stays manageable; an admin removing themselves is asked a second time.
+
+use std::{fmt::Display, str::FromStr, sync::Arc};
+
+use teloxide::{
+    payloads::{AnswerCallbackQuerySetters, EditMessageTextSetters, SendMessageSetters},
+    requests::Requester,
+    types::{
+        CallbackQuery, ChatId, InlineKeyboardButton, InlineKeyboardMarkup, Message,
+        MessageEntityKind, User,
+    },
+    Bot,
+};
+
+use crate::{
+    cmd_help::update_chat_menu,
+    i18n::Lang,
+    storage::{
+        Admin, AdminRemoval, AdminRepository, AuthorizationRepository, PermissionRepository,
+    },
+    t, HandlerResult,
+};
+
+pub const ADMIN_CALLBACK_PREFIX: &str = "admin:";
+
+/// Button of the confirmation asked by /adminremove.
+#[derive(Debug, Clone)]
+pub enum AdminCallback {
+    /// Removes the admin with this Telegram id, or asks for a second confirmation if it is the
+    /// user pressing the button
+    Remove(String),
+    /// Removes the admin with this Telegram id once they confirmed it, only they can press it
+    RemoveSelf(String),
+    Cancel,
+}
+
+impl FromStr for AdminCallback {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split(':').collect::<Vec<_>>()[..] {
+            ["remove", id] => Ok(Self::Remove(id.to_owned())),
+            ["removeself", id] => Ok(Self::RemoveSelf(id.to_owned())),
+            ["cancel"] => Ok(Self::Cancel),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Display for AdminCallback {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Remove(id) => write!(f, "{ADMIN_CALLBACK_PREFIX}remove:{id}"),
+            Self::RemoveSelf(id) => write!(f, "{ADMIN_CALLBACK_PREFIX}removeself:{id}"),
+            Self::Cancel => write!(f, "{ADMIN_CALLBACK_PREFIX}cancel"),
+        }
+    }
+}
+
+/// Name of the admin followed by their @username if known, and their id.
+fn admin_label(admin: &Admin) -> String {
+    match &admin.username {
+        Some(username) => format!("{} (@{username}, {})", admin.name, admin.telegram_id),
+        None => format!("{} ({})", admin.name, admin.telegram_id),
+    }
+}
+
+/// Why the admin with the id `remover` cannot remove `target`, as a key of the `auth` catalog
+/// section, or `None` if they can.
+fn removal_refusal(remover: &str, target: &Admin, admins: &[Admin]) -> Option<&'static str> {
+    let remover_is_super = admins
+        .iter()
+        .any(|a| a.telegram_id == remover && a.super_admin);
+
+    if target.super_admin && !remover_is_super {
+        Some("auth.super_admin_protected")
+    } else if admins.len() <= 1 {
+        Some("auth.last_admin")
+    } else {
+        None
+    }
+}
+
+/// User the command is about: the author of the message it replies to, or the first user
+/// mentioned without an @username (Telegram only gives the id of the users picked from the
+/// mention list who have no username).
+fn target_user(msg: &Message) -> Option<User> {
+    if let Some(user) = msg
+        .reply_to_message()
+        .and_then(|reply| reply.from.as_ref())
+        .filter(|user| !user.is_bot)
+    {
+        return Some(user.clone());
+    }
+
+    msg.parse_entities()?
+        .into_iter()
+        .find_map(|entity| match entity.kind() {
+            MessageEntityKind::TextMention { user } => Some(user.clone()),
+            _ => None,
+        })
+}
+
+pub async fn admin_list(
+    bot: Bot,
+    msg: Message,
+    lang: Lang,
+    admins: Arc<dyn AdminRepository>,
+) -> HandlerResult {
+    let admins = admins.list_admins().await?;
+
+    bot.send_message(
+        msg.chat.id,
+        t!(
+            lang,
+            "auth.admins",
+            admins = admins
+                .iter()
+                .map(|a| if a.super_admin {
+                    format!(" - ⭐ {}", admin_label(a))
+                } else {
+                    format!(" - {}", admin_label(a))
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+        ),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Handles /adminadd, which makes a user an admin. The user is the author of the message the
+/// command replies to, a user mentioned in it, or given by their id; an optional name replaces
+/// their Telegram name.
+pub async fn admin_add(
+    bot: Bot,
+    msg: Message,
+    args: String,
+    lang: Lang,
+    admins: Arc<dyn AdminRepository>,
+    authorizations: Arc<dyn AuthorizationRepository>,
+    permissions: Arc<dyn PermissionRepository>,
+) -> HandlerResult {
+    let args = args.trim();
+    let admin = if let Some(user) = target_user(&msg) {
+        // The mention is part of the arguments, and is not a name
+        let mentioned = msg.reply_to_message().is_none();
+        Admin {
+            telegram_id: user.id.to_string(),
+            name: if args.is_empty() || mentioned {
+                user.full_name()
+            } else {
+                args.to_owned()
+            },
+            username: user.username.clone(),
+            super_admin: false,
+        }
+    } else if args.starts_with('@') {
+        bot.send_message(msg.chat.id, t!(lang, "auth.unknown_username"))
+            .await?;
+        return Ok(());
+    } else {
+        let (id, name) = args.split_once(' ').unwrap_or((args, ""));
+        let Ok(id) = id.parse::<u64>() else {
+            bot.send_message(msg.chat.id, t!(lang, "usage.adminadd"))
+                .await?;
+            return Ok(());
+        };
+        Admin {
+            telegram_id: id.to_string(),
+            name: match name.trim() {
+                "" => id.to_string(),
+                name => name.to_owned(),
+            },
+            username: None,
+            super_admin: false,
+        }
+    };
+
+    if admins.is_admin(&admin.telegram_id).await? {
+        bot.send_message(
+            msg.chat.id,
+            t!(lang, "auth.already_admin", name = admin_label(&admin)),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    admins.add_admin(&admin).await?;
+    bot.send_message(
+        msg.chat.id,
+        t!(lang, "auth.admin_added", name = admin_label(&admin)),
+    )
+    .await?;
+    if let Ok(id) = admin.telegram_id.parse::<i64>() {
+        update_chat_menu(
+            &bot,
+            ChatId(id),
+            admins.as_ref(),
+            authorizations.as_ref(),
+            permissions.as_ref(),
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Handles /adminremove, which asks to confirm the removal of the admins matching the message:
+/// the author of the message it replies to, a mentioned user, an @username, an id or a name. The
+/// admins the sender cannot remove are left out (see [`removal_refusal`]).
+pub async fn admin_remove(
+    bot: Bot,
+    msg: Message,
+    args: String,
+    lang: Lang,
+    admins: Arc<dyn AdminRepository>,
+) -> HandlerResult {
+    let Some(sender) = &msg.from else {
+        return Ok(());
+    };
+    let sender = sender.id.to_string();
+    let args = args.trim();
+    let target = target_user(&msg).map(|user| user.id.to_string());
+    if target.is_none() && args.is_empty() {
+        bot.send_message(msg.chat.id, t!(lang, "usage.adminremove"))
+            .await?;
+        return Ok(());
+    }
+    let matches = |admin: &&Admin| match (&target, args.strip_prefix('@')) {
+        (Some(id), _) => admin.telegram_id == *id,
+        (None, Some(username)) => admin
+            .username
+            .as_ref()
+            .is_some_and(|u| u.eq_ignore_ascii_case(username)),
+        (None, None) => admin.telegram_id == args || admin.name == args,
+    };
+
+    let all = admins.list_admins().await?;
+    let (candidates, refused): (Vec<_>, Vec<_>) = all
+        .iter()
+        .filter(matches)
+        .partition(|a| removal_refusal(&sender, a, &all).is_none());
+    if let Some(admin) = refused.first().filter(|_| candidates.is_empty()) {
+        let key = removal_refusal(&sender, admin, &all).unwrap_or("auth.not_admin");
+        bot.send_message(msg.chat.id, t!(lang, key, name = admin_label(admin)))
+            .await?;
+        return Ok(());
+    }
+    if candidates.is_empty() {
+        bot.send_message(msg.chat.id, t!(lang, "auth.not_admin", name = args))
+            .await?;
+        return Ok(());
+    }
+
+    let mut rows = candidates
+        .iter()
+        .map(|admin| {
+            vec![InlineKeyboardButton::callback(
+                t!(lang, "auth.remove_button", name = admin_label(admin)),
+                AdminCallback::Remove(admin.telegram_id.clone()).to_string(),
+            )]
+        })
+        .collect::<Vec<_>>();
+    rows.push(vec![InlineKeyboardButton::callback(
+        t!(lang, "auth.cancel_button"),
+        AdminCallback::Cancel.to_string(),
+    )]);
+
+    bot.send_message(msg.chat.id, t!(lang, "auth.confirm_removal"))
+        .reply_markup(InlineKeyboardMarkup::new(rows))
+        .await?;
+
+    Ok(())
+}
+
+/// Handles the buttons of the confirmation of /adminremove, which only the admins can use. The
+/// rules of [`removal_refusal`] are checked again, as the admins may have changed since.
+pub async fn admin_callback(
+    bot: Bot,
+    callback_query: CallbackQuery,
+    action: AdminCallback,
+    lang: Lang,
+    admins: Arc<dyn AdminRepository>,
+    authorizations: Arc<dyn AuthorizationRepository>,
+    permissions: Arc<dyn PermissionRepository>,
+) -> HandlerResult {
+    let Some(message) = callback_query.message.as_ref() else {
+        return Ok(());
+    };
+    let presser = callback_query.from.id.to_string();
+    let all = admins.list_admins().await?;
+    if !all.iter().any(|a| a.telegram_id == presser) {
+        bot.answer_callback_query(callback_query.id)
+            .text(t!(lang, "auth.manage_admins_only"))
+            .await?;
+        return Ok(());
+    }
+    if matches!(&action, AdminCallback::RemoveSelf(id) if *id != presser) {
+        bot.answer_callback_query(callback_query.id)
+            .text(t!(lang, "auth.self_removal_only"))
+            .await?;
+        return Ok(());
+    }
+    bot.answer_callback_query(callback_query.id).await?;
+
+    let id = match action {
+        AdminCallback::Cancel => {
+            bot.edit_message_text(
+                message.chat().id,
+                message.id(),
+                t!(lang, "auth.removal_cancelled"),
+            )
+            .await?;
+            return Ok(());
+        }
+        AdminCallback::Remove(id) if id == presser => {
+            bot.edit_message_text(
+                message.chat().id,
+                message.id(),
+                t!(lang, "auth.confirm_self_removal"),
+            )
+            .reply_markup(InlineKeyboardMarkup::new([
+                [InlineKeyboardButton::callback(
+                    t!(lang, "auth.remove_self_button"),
+                    AdminCallback::RemoveSelf(id).to_string(),
+                )],
+                [InlineKeyboardButton::callback(
+                    t!(lang, "auth.cancel_button"),
+                    AdminCallback::Cancel.to_string(),
+                )],
+            ]))
+            .await?;
+            return Ok(());
+        }
+        AdminCallback::Remove(id) | AdminCallback::RemoveSelf(id) => id,
+    };
+
+    let mut removed = false;
+    let text = match all.iter().find(|a| a.telegram_id == id) {
+        // Already removed from another confirmation
+        None => t!(lang, "auth.not_admin", name = id),
+        Some(admin) => {
+            let name = admin_label(admin);
+            if let Some(key) = removal_refusal(&presser, admin, &all) {
+                t!(lang, key, name = name)
+            } else {
+                // The repository checks again that the admin is not the last one, as another
+                // admin may have been removed since the list was read
+                match admins.remove_admin(&id).await? {
+                    AdminRemoval::Removed => {
+                        removed = true;
+                        t!(lang, "auth.admin_removed", name = name)
+                    }
+                    // Already removed from another confirmation
+                    AdminRemoval::NotAdmin => t!(lang, "auth.not_admin", name = name),
+                    AdminRemoval::LastAdmin => t!(lang, "auth.last_admin", name = name),
+                }
+            }
+        }
+    };
+
+    // Also removes the buttons
+    bot.edit_message_text(message.chat().id, message.id(), text)
+        .await?;
+
+    // Once the removal is confirmed, which must not depend on the menu
+    if let Some(chat) = id.parse::<i64>().ok().filter(|_| removed) {
+        update_chat_menu(
+            &bot,
+            ChatId(chat),
+            admins.as_ref(),
+            authorizations.as_ref(),
+            permissions.as_ref(),
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn admin(telegram_id: &str, super_admin: bool) -> Admin {
+        Admin {
+            telegram_id: telegram_id.to_owned(),
+            name: format!("Admin {telegram_id}"),
+            username: None,
+            super_admin,
+        }
+    }
+
+    #[test]
+    fn refuses_to_remove_protected_admins() {
+        let admins = [admin("1", true), admin("2", false), admin("3", false)];
+
+        assert_eq!(removal_refusal("2", &admins[2], &admins), None);
+        assert_eq!(removal_refusal("2", &admins[1], &admins), None);
+        assert_eq!(removal_refusal("1", &admins[2], &admins), None);
+        assert_eq!(
+            removal_refusal("2", &admins[0], &admins),
+            Some("auth.super_admin_protected")
+        );
+        assert_eq!(removal_refusal("1", &admins[0], &admins), None);
+        // Not an admin anymore
+        assert_eq!(
+            removal_refusal("4", &admins[0], &admins),
+            Some("auth.super_admin_protected")
+        );
+    }
+
+    #[test]
+    fn refuses_to_remove_the_last_admin() {
+        let admins = [admin("1", false)];
+        assert_eq!(
+            removal_refusal("1", &admins[0], &admins),
+            Some("auth.last_admin")
+        );
+
+        let admins = [admin("1", true)];
+        assert_eq!(
+            removal_refusal("1", &admins[0], &admins),
+            Some("auth.last_admin")
+        );
+    }
+}
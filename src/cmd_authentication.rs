@@ -1,214 +1,596 @@
-use crate::{commands::RESTRICTED_COMMANDS, config::config, HandlerResult};
-use sqlx::SqlitePool;
-use std::sync::Arc;
-use teloxide::{requests::Requester, types::Message, Bot};
+use crate::{
+    cmd_chats::{chat_name, known_chat, send_chat_list, send_checklist},
+    cmd_help::update_chat_menu,
+    commands::{expand_restricted_commands, restricted_command_names},
+    config::config,
+    i18n::{chat_language, Lang},
+    storage::{
+        Admin, AdminRepository, Authorization, AuthorizationRepository, ChatRepository,
+        LanguageRepository, PermissionRepository,
+    },
+    t, HandlerResult,
+};
+use chrono::{DateTime, TimeDelta, Utc};
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+use teloxide::{
+    requests::Requester,
+    types::{ChatId, Message, UserId},
+    Bot, RequestError,
+};
+
+/// Delay between two checks of the expired authorizations.
+const EXPIRY_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+/// Longest duration (in days) of a temporary authorization, beyond which it should be permanent.
+const MAX_DURATION_DAYS: i64 = 10 * 365;
 
 pub async fn authenticate(
     bot: Bot,
     msg: Message,
     (token, name): (String, String),
-    db: Arc<SqlitePool>,
+    lang: Lang,
+    admins: Arc<dyn AdminRepository>,
+    authorizations: Arc<dyn AuthorizationRepository>,
+    permissions: Arc<dyn PermissionRepository>,
 ) -> HandlerResult {
     if token == config().admin_token {
-        let id = msg.chat.id.to_string();
-        sqlx::query!(
-            r#"INSERT INTO admins(telegram_id, "name") VALUES($1, $2)"#,
-            id,
-            name
+        admins
+            .add_admin(&Admin {
+                telegram_id: msg.chat.id.to_string(),
+                name,
+                username: msg.from.as_ref().and_then(|u| u.username.clone()),
+                super_admin: false,
+            })
+            .await?;
+        bot.send_message(msg.chat.id, t!(lang, "auth.success"))
+            .await?;
+        update_chat_menu(
+            &bot,
+            msg.chat.id,
+            admins.as_ref(),
+            authorizations.as_ref(),
+            permissions.as_ref(),
         )
-        .execute(db.as_ref())
         .await?;
-        bot.send_message(msg.chat.id, "Authentification réussie !")
-            .await?;
     } else {
-        bot.send_message(msg.chat.id, "Le token est incorrect")
+        bot.send_message(msg.chat.id, t!(lang, "auth.wrong_token"))
             .await?;
     }
 
     Ok(())
 }
 
-pub async fn admin_list(bot: Bot, msg: Message, db: Arc<SqlitePool>) -> HandlerResult {
-    let admins = sqlx::query!(r#"SELECT "name" FROM admins"#)
-        .fetch_all(db.as_ref())
-        .await?;
+/// Whether the user can manage the authorizations of the group as one of its Telegram
+/// administrators, which requires `GROUP_ADMINS_MANAGE_AUTHORIZATIONS`.
+pub async fn is_group_manager(bot: &Bot, chat: ChatId, user: UserId) -> Result<bool, RequestError> {
+    if !config().group_admins_manage_authorizations || chat.is_user() {
+        return Ok(false);
+    }
 
-    bot.send_message(
-        msg.chat.id,
-        format!(
-            "Admin(s) actuel(s):\n{}",
-            admins
-                .into_iter()
-                .map(|r| format!(" - {}", r.name))
-                .collect::<Vec<_>>()
-                .join("\n"),
-        ),
-    )
-    .await?;
+    Ok(bot
+        .get_chat_administrators(chat)
+        .await?
+        .iter()
+        .any(|member| member.user.id == user))
+}
 
-    Ok(())
+/// Whether the message was sent by a Telegram administrator of the group who can manage its
+/// authorizations (see [`is_group_manager`]), including an anonymous one.
+pub async fn sent_by_group_manager(bot: &Bot, msg: &Message) -> bool {
+    if !config().group_admins_manage_authorizations || msg.chat.is_private() {
+        return false;
+    }
+    // Only the administrators can send messages on behalf of the group
+    if msg
+        .sender_chat
+        .as_ref()
+        .is_some_and(|c| c.id == msg.chat.id)
+    {
+        return true;
+    }
+    let Some(user) = &msg.from else {
+        return false;
+    };
+
+    match is_group_manager(bot, msg.chat.id, user.id).await {
+        Ok(is_manager) => is_manager,
+        Err(e) => {
+            log::error!("Could not get the administrators of {}: {e}", msg.chat.id);
+            false
+        }
+    }
 }
 
-pub async fn admin_remove(
-    bot: Bot,
-    msg: Message,
-    name: String,
-    db: Arc<SqlitePool>,
-) -> HandlerResult {
-    let mut tx = db.begin().await?;
+/// Chat targeted by /authorize, /unauthorize or /authorizations, and the names of the commands
+/// if any.
+struct Target {
+    chat: ChatId,
+    /// Name of the chat, when it is not the one the command was sent in
+    remote: Option<String>,
+    names: Vec<String>,
+}
 
-    if sqlx::query!("SELECT COUNT(*) AS count FROM admins WHERE name = $1", name)
-        .fetch_one(tx.as_mut())
-        .await?
-        .count
-        == 0
-    {
-        bot.send_message(msg.chat.id, format!("{} n'est pas admin", name))
+/// Parses `[<chat id>] [<command>...]`, the current chat being targeted without a chat id.
+/// Returns `None` once the user has been told what is wrong.
+async fn target(
+    bot: &Bot,
+    msg: &Message,
+    args: &str,
+    lang: Lang,
+    chats: &dyn ChatRepository,
+) -> Result<Option<Target>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut names = args
+        .split_whitespace()
+        .map(str::to_owned)
+        .collect::<Vec<_>>();
+    let chat = match names.first().map(|arg| arg.parse::<i64>()) {
+        Some(Ok(id)) => {
+            names.remove(0);
+            Some(ChatId(id))
+        }
+        _ => None,
+    };
+
+    let target = match chat.filter(|chat| *chat != msg.chat.id) {
+        Some(chat) => {
+            // Also checks that the bot is in the chat, which is then listed by /authorize
+            let remote = match bot.get_chat(chat).await {
+                Ok(remote) => remote,
+                Err(e) => {
+                    log::warn!("Could not get the chat {chat}: {e}");
+                    bot.send_message(msg.chat.id, t!(lang, "auth.unknown_chat", chat = chat))
+                        .await?;
+                    return Ok(None);
+                }
+            };
+            if !chat.is_user() {
+                chats.save_chat(&known_chat(&remote, None)).await?;
+            }
+            Target {
+                chat,
+                remote: Some(chat_name(&remote)),
+                names,
+            }
+        }
+        None => Target {
+            chat: msg.chat.id,
+            remote: None,
+            names,
+        },
+    };
+
+    Ok(Some(target))
+}
+
+/// Expands the names given to /authorize or /unauthorize into restricted commands. Returns `None`
+/// once the user has been told which names are unknown.
+async fn target_commands(
+    bot: &Bot,
+    msg: &Message,
+    target: &Target,
+    lang: Lang,
+) -> Result<Option<Vec<String>>, Box<dyn std::error::Error + Send + Sync>> {
+    match expand_restricted_commands(&target.names) {
+        Ok(commands) => Ok(Some(commands)),
+        Err(unknown) => {
+            bot.send_message(
+                msg.chat.id,
+                t!(
+                    lang,
+                    "auth.unknown_commands",
+                    commands = unknown.join(", "),
+                    available = restricted_command_names().join(", ")
+                ),
+            )
             .await?;
-        return Ok(());
+            Ok(None)
+        }
     }
+}
 
-    sqlx::query!("DELETE FROM admins WHERE name = $1", name)
-        .execute(tx.as_mut())
-        .await?;
-    tx.commit().await?;
+/// Answers /authorize and /unauthorize without a command with the checklist of the chat, or with
+/// the list of the chats in a private chat with the bot.
+async fn send_keyboard(
+    bot: &Bot,
+    msg: &Message,
+    target: &Target,
+    lang: Lang,
+    authorizations: &dyn AuthorizationRepository,
+    chats: &dyn ChatRepository,
+) -> HandlerResult {
+    if target.remote.is_none() && msg.chat.is_private() {
+        send_chat_list(bot, msg.chat.id, lang, authorizations, chats).await
+    } else {
+        send_checklist(bot, msg.chat.id, target.chat, lang, authorizations, chats).await
+    }
+}
 
-    bot.send_message(msg.chat.id, format!("{} a été retiré(e) des admins", name))
-        .await?;
+/// Localizes the answer of an authorization command for one of its commands, naming the chat
+/// when it is not the current one. `time` is the duration of a temporary authorization.
+fn answer(lang: Lang, key: &str, target: &Target, command: &str, time: &str) -> String {
+    match &target.remote {
+        None => t!(lang, &format!("auth.{key}"), command = command, time = time),
+        Some(chat) => t!(
+            lang,
+            &format!("auth.chat_{key}"),
+            chat = chat,
+            command = command,
+            time = time
+        ),
+    }
+}
 
-    Ok(())
+/// Parses the duration of a temporary authorization: a number of minutes (`30m`), hours (`12h`),
+/// days (`7d`) or weeks (`2w`), of at most [`MAX_DURATION_DAYS`].
+pub fn parse_duration(arg: &str) -> Option<TimeDelta> {
+    let (amount, unit) = arg.split_at(arg.find(|c: char| !c.is_ascii_digit())?);
+    let amount = amount.parse::<i64>().ok().filter(|amount| *amount > 0)?;
+    match unit {
+        "m" | "min" => TimeDelta::try_minutes(amount),
+        "h" => TimeDelta::try_hours(amount),
+        "d" => TimeDelta::try_days(amount),
+        "w" => TimeDelta::try_weeks(amount),
+        _ => None,
+    }
+    .filter(|duration| duration.num_days() <= MAX_DURATION_DAYS)
+}
+
+/// Date at which a temporary authorization given at `now` expires, `None` if it is too far away
+/// to be represented.
+pub fn expiry_date(now: DateTime<Utc>, duration: TimeDelta) -> Option<DateTime<Utc>> {
+    now.checked_add_signed(duration)
 }
 
+/// Formats a duration in seconds with its two largest units, e.g. `6 d 23 h`.
+pub fn format_duration(lang: Lang, seconds: i64) -> String {
+    // Rounded up, so that a fresh authorization for 7d shows 7 d and not 6 d 23 h
+    let minutes = (seconds.max(0) + 59) / 60;
+    let units = [
+        ("duration.days", minutes / (24 * 60)),
+        ("duration.hours", minutes / 60 % 24),
+        ("duration.minutes", minutes % 60),
+    ];
+    let first = units
+        .iter()
+        .position(|(_, amount)| *amount > 0)
+        .unwrap_or(units.len() - 1);
+    units[first..]
+        .iter()
+        .take(2)
+        .filter(|(_, amount)| *amount > 0 || first == units.len() - 1)
+        .map(|(key, amount)| t!(lang, key, amount = amount))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Names the command of the authorization, with the time left if it is temporary.
+pub fn authorization_label(lang: Lang, authorization: &Authorization, now: i64) -> String {
+    match authorization.expires_at {
+        Some(expires_at) => t!(
+            lang,
+            "auth.temporary",
+            command = authorization.command,
+            time = format_duration(lang, expires_at - now)
+        ),
+        None => authorization.command.clone(),
+    }
+}
+
+/// Authorizes the chat to use the commands, given by name, by group of commands (from the config
+/// file) or with `all`, for the given duration if the last argument is one.
+#[allow(clippy::too_many_arguments)]
 pub async fn authorize(
     bot: Bot,
     msg: Message,
-    command: String,
-    db: Arc<SqlitePool>,
+    args: String,
+    lang: Lang,
+    admins: Arc<dyn AdminRepository>,
+    authorizations: Arc<dyn AuthorizationRepository>,
+    permissions: Arc<dyn PermissionRepository>,
+    chats: Arc<dyn ChatRepository>,
 ) -> HandlerResult {
-    let mut tx = db.begin().await?;
-
-    let chat_id_str = msg.chat.id.to_string();
-
-    if !RESTRICTED_COMMANDS.iter().any(|c| c.shortand() == command) {
-        bot.send_message(msg.chat.id, "Cette commande n'existe pas")
-            .await?;
+    let Some(mut target) = target(&bot, &msg, &args, lang, chats.as_ref()).await? else {
         return Ok(());
+    };
+    let duration = target.names.last().and_then(|name| parse_duration(name));
+    if duration.is_some() {
+        target.names.pop();
+        if target.names.is_empty() {
+            bot.send_message(msg.chat.id, t!(lang, "usage.authorize"))
+                .await?;
+            return Ok(());
+        }
     }
+    if target.names.is_empty() {
+        return send_keyboard(
+            &bot,
+            &msg,
+            &target,
+            lang,
+            authorizations.as_ref(),
+            chats.as_ref(),
+        )
+        .await;
+    }
+    let Some(commands) = target_commands(&bot, &msg, &target, lang).await? else {
+        return Ok(());
+    };
 
-    let already_authorized = sqlx::query!(
-        r#"SELECT COUNT(*) AS count FROM authorizations WHERE chat_id = $1 AND command = $2"#,
-        chat_id_str,
-        command
+    let now = Utc::now().timestamp();
+    let expires_at = duration.map(|duration| now + duration.num_seconds());
+    let time = duration
+        .map(|duration| format_duration(lang, duration.num_seconds()))
+        .unwrap_or_default();
+    let chat_id_str = target.chat.to_string();
+    let existing = authorizations.list_authorizations(&chat_id_str).await?;
+    let mut lines = Vec::new();
+    for command in &commands {
+        let was_temporary = existing
+            .iter()
+            .any(|a| &a.command == command && a.expires_at.is_some());
+        let added = authorizations
+            .authorize(&chat_id_str, command, expires_at)
+            .await?;
+        let key = match expires_at {
+            Some(_) => "authorized_for",
+            // A temporary authorization became permanent
+            None if added || was_temporary => "authorized",
+            None => "already_authorized",
+        };
+        lines.push(answer(lang, key, &target, command, &time));
+    }
+    bot.send_message(msg.chat.id, lines.join("\n")).await?;
+    update_chat_menu(
+        &bot,
+        target.chat,
+        admins.as_ref(),
+        authorizations.as_ref(),
+        permissions.as_ref(),
     )
-    .fetch_one(tx.as_mut())
     .await?;
 
-    if already_authorized.count == 0 {
-        sqlx::query!(
-            r#"INSERT INTO authorizations(command, chat_id) VALUES($1, $2)"#,
-            command,
-            chat_id_str
+    Ok(())
+}
+
+/// Revokes the authorizations of the chat to use the commands, given like for [`authorize`].
+#[allow(clippy::too_many_arguments)]
+pub async fn unauthorize(
+    bot: Bot,
+    msg: Message,
+    args: String,
+    lang: Lang,
+    admins: Arc<dyn AdminRepository>,
+    authorizations: Arc<dyn AuthorizationRepository>,
+    permissions: Arc<dyn PermissionRepository>,
+    chats: Arc<dyn ChatRepository>,
+) -> HandlerResult {
+    let Some(target) = target(&bot, &msg, &args, lang, chats.as_ref()).await? else {
+        return Ok(());
+    };
+    if target.names.is_empty() {
+        return send_keyboard(
+            &bot,
+            &msg,
+            &target,
+            lang,
+            authorizations.as_ref(),
+            chats.as_ref(),
         )
-        .execute(tx.as_mut())
-        .await?;
+        .await;
     }
+    let Some(commands) = target_commands(&bot, &msg, &target, lang).await? else {
+        return Ok(());
+    };
 
-    tx.commit().await?;
-
-    if already_authorized.count > 0 {
-        bot.send_message(
-            msg.chat.id,
-            format!("Ce groupe peut déjà utiliser la commande /{}", command),
-        )
-        .await?;
-    } else {
-        bot.send_message(
-            msg.chat.id,
-            format!("Ce groupe peut désormais utiliser la commande /{}", command),
-        )
-        .await?;
+    let chat_id_str = target.chat.to_string();
+    let mut lines = Vec::new();
+    for command in &commands {
+        let removed = authorizations.unauthorize(&chat_id_str, command).await?;
+        lines.push(
+            if config().chat_authorizations(target.chat).contains(command) {
+                answer(lang, "kept_by_config", &target, command, "")
+            } else if removed {
+                answer(lang, "unauthorized", &target, command, "")
+            } else {
+                answer(lang, "already_unauthorized", &target, command, "")
+            },
+        );
     }
+    bot.send_message(msg.chat.id, lines.join("\n")).await?;
+    update_chat_menu(
+        &bot,
+        target.chat,
+        admins.as_ref(),
+        authorizations.as_ref(),
+        permissions.as_ref(),
+    )
+    .await?;
 
     Ok(())
 }
 
-pub async fn unauthorize(
+pub async fn authorizations(
     bot: Bot,
     msg: Message,
-    command: String,
-    db: Arc<SqlitePool>,
+    args: String,
+    lang: Lang,
+    authorizations: Arc<dyn AuthorizationRepository>,
+    chats: Arc<dyn ChatRepository>,
 ) -> HandlerResult {
-    let mut tx = db.begin().await?;
+    let Some(target) = target(&bot, &msg, &args, lang, chats.as_ref()).await? else {
+        return Ok(());
+    };
+    if !target.names.is_empty() {
+        bot.send_message(msg.chat.id, t!(lang, "usage.authorizations"))
+            .await?;
+        return Ok(());
+    }
+
+    let now = Utc::now().timestamp();
+    let chat_id_str = target.chat.to_string();
+    let stored = authorizations.list_authorizations(&chat_id_str).await?;
+    let configured = config().chat_authorizations(target.chat);
+    // The authorizations of the config file are permanent, whatever the stored ones
+    let mut commands = stored
+        .iter()
+        .filter(|a| !configured.contains(&a.command))
+        .map(|a| authorization_label(lang, a, now))
+        .chain(configured.iter().cloned())
+        .collect::<Vec<_>>();
+    commands.sort();
 
-    let chat_id_str = msg.chat.id.to_string();
-    let already_authorized = sqlx::query!(
-        r#"SELECT COUNT(*) AS count FROM authorizations WHERE chat_id = $1 AND command = $2"#,
-        chat_id_str,
-        command
+    let commands = commands
+        .into_iter()
+        .map(|command| format!(" - {}", command))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let key = match (&target.remote, commands.is_empty()) {
+        (None, true) => "auth.no_authorizations",
+        (None, false) => "auth.authorizations",
+        (Some(_), true) => "auth.chat_no_authorizations",
+        (Some(_), false) => "auth.chat_authorizations",
+    };
+    bot.send_message(
+        msg.chat.id,
+        t!(
+            lang,
+            key,
+            chat = target.remote.unwrap_or_default(),
+            commands = commands
+        ),
     )
-    .fetch_one(tx.as_mut())
     .await?;
 
-    if already_authorized.count > 0 {
-        sqlx::query!(
-            r#"DELETE FROM authorizations WHERE command = $1 AND chat_id = $2"#,
-            command,
-            chat_id_str
+    Ok(())
+}
+
+/// Background task removing the expired authorizations, and telling the chats which lost them.
+pub async fn authorization_expiry(
+    bot: Bot,
+    admins: Arc<dyn AdminRepository>,
+    authorizations: Arc<dyn AuthorizationRepository>,
+    permissions: Arc<dyn PermissionRepository>,
+    languages: Arc<dyn LanguageRepository>,
+) {
+    let mut interval = tokio::time::interval(EXPIRY_CHECK_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        if let Err(e) = expire_authorizations(
+            &bot,
+            admins.as_ref(),
+            authorizations.as_ref(),
+            permissions.as_ref(),
+            languages.as_ref(),
         )
-        .execute(tx.as_mut())
+        .await
+        {
+            log::error!("Could not expire the authorizations: {e:#?}");
+        }
+    }
+}
+
+async fn expire_authorizations(
+    bot: &Bot,
+    admins: &dyn AdminRepository,
+    authorizations: &dyn AuthorizationRepository,
+    permissions: &dyn PermissionRepository,
+    languages: &dyn LanguageRepository,
+) -> HandlerResult {
+    let expired = authorizations
+        .expire_authorizations(Utc::now().timestamp())
         .await?;
+
+    let mut by_chat = BTreeMap::<String, Vec<String>>::new();
+    for authorization in expired {
+        by_chat
+            .entry(authorization.chat_id)
+            .or_default()
+            .push(authorization.command);
     }
 
-    tx.commit().await?;
+    for (chat_id, commands) in by_chat {
+        let Ok(chat) = chat_id.parse::<i64>().map(ChatId) else {
+            continue;
+        };
+        log::info!(
+            "The authorizations of the chat {chat} to use {} expired",
+            commands.join(", ")
+        );
 
-    if already_authorized.count == 0 {
-        bot.send_message(
-            msg.chat.id,
-            format!(
-                "Ce groupe ne peut déjà pas utiliser la commande /{}",
-                command
-            ),
-        )
-        .await?;
-    } else {
-        bot.send_message(
-            msg.chat.id,
-            format!(
-                "Ce groupe ne peut désormais plus utiliser la commande /{}",
-                command
-            ),
-        )
-        .await?;
+        // The commands authorized by the config file can still be used
+        let configured = config().chat_authorizations(chat);
+        let lost = commands
+            .iter()
+            .filter(|command| !configured.contains(command))
+            .map(|command| format!("/{command}"))
+            .collect::<Vec<_>>();
+        if !lost.is_empty() {
+            let lang = chat_language(languages, chat).await;
+            let text = t!(lang, "auth.expired", commands = lost.join(", "));
+            // The bot may have been removed from the chat in the meantime
+            if let Err(e) = bot.send_message(chat, text).await {
+                log::warn!("Could not tell the chat {chat} about its expired authorizations: {e}");
+            }
+        }
+
+        update_chat_menu(bot, chat, admins, authorizations, permissions).await?;
     }
 
     Ok(())
 }
 
-pub async fn authorizations(bot: Bot, msg: Message, db: Arc<SqlitePool>) -> HandlerResult {
-    let chat_id_str = msg.chat.id.to_string();
-    let authorizations = sqlx::query!(
-        r#"SELECT command FROM authorizations WHERE chat_id = $1"#,
-        chat_id_str
-    )
-    .fetch_all(db.as_ref())
-    .await?;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    bot.send_message(
-        msg.chat.id,
-        if authorizations.is_empty() {
-            "Ce groupe ne peut utiliser aucune commande".to_owned()
-        } else {
-            format!(
-                "Ce groupe peut utiliser les commandes suivantes:\n{}",
-                authorizations
-                    .into_iter()
-                    .map(|s| format!(" - {}", s.command))
-                    .collect::<Vec<_>>()
-                    .join("\n")
-            )
-        },
-    )
-    .await?;
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("30m"), TimeDelta::try_minutes(30));
+        assert_eq!(parse_duration("30min"), TimeDelta::try_minutes(30));
+        assert_eq!(parse_duration("12h"), TimeDelta::try_hours(12));
+        assert_eq!(parse_duration("7d"), TimeDelta::try_days(7));
+        assert_eq!(parse_duration("2w"), TimeDelta::try_weeks(2));
+        assert_eq!(parse_duration("3650d"), TimeDelta::try_days(3650));
 
-    Ok(())
+        for invalid in [
+            "",
+            "30",
+            "h",
+            "0d",
+            "-1d",
+            "1.5h",
+            "3y",
+            "1d2h",
+            "99999999999999999999d",
+            "3651d",
+            "100000000w",
+        ] {
+            assert_eq!(parse_duration(invalid), None, "{invalid:?}");
+        }
+    }
+
+    #[test]
+    fn computes_expiry_dates() {
+        let now = Utc::now();
+        let duration = parse_duration("520w").unwrap();
+
+        assert_eq!(expiry_date(now, duration), Some(now + duration));
+        assert_eq!(expiry_date(DateTime::<Utc>::MAX_UTC, duration), None);
+    }
+
+    #[test]
+    fn formats_durations() {
+        let format = |seconds| format_duration(Lang::En, seconds);
+
+        assert_eq!(format(7 * 24 * 3600), "7 d");
+        assert_eq!(format(7 * 24 * 3600 - 30), "7 d");
+        assert_eq!(format(7 * 24 * 3600 - 3600), "6 d 23 h");
+        assert_eq!(format(26 * 3600 + 5 * 60), "1 d 2 h");
+        assert_eq!(format(3600 + 60), "1 h 1 min");
+        assert_eq!(format(45 * 60), "45 min");
+        assert_eq!(format(1), "1 min");
+        assert_eq!(format(0), "0 min");
+        assert_eq!(format(-10), "0 min");
+    }
 }
@@ -0,0 +1,158 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use chrono::Utc;
+use teloxide::{
+    payloads::SendDocumentSetters,
+    requests::Requester,
+    types::{ChatId, InputFile, Message},
+    Bot,
+};
+
+use crate::{
+    config::config,
+    i18n::{chat_language, Lang},
+    storage::{BackupRepository, Error, LanguageRepository},
+    t, HandlerResult,
+};
+
+/// Prefix of the names of the backup files, followed by their UTC date.
+const BACKUP_PREFIX: &str = "db-";
+const BACKUP_EXTENSION: &str = ".sqlite";
+
+/// Backs the database up and sends the backup to the admin in private.
+pub async fn backup(
+    bot: Bot,
+    msg: Message,
+    lang: Lang,
+    backups: Option<Arc<dyn BackupRepository>>,
+    languages: Arc<dyn LanguageRepository>,
+) -> HandlerResult {
+    let Some(user) = msg.from.as_ref() else {
+        return Ok(());
+    };
+    let Some(backups) = backups else {
+        bot.send_message(msg.chat.id, t!(lang, "backup.unavailable"))
+            .await?;
+        return Ok(());
+    };
+
+    let path = create_backup(backups.as_ref()).await?;
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let private_chat = ChatId::from(user.id);
+    let private_lang = chat_language(languages.as_ref(), private_chat).await;
+    if let Err(e) = bot
+        .send_document(private_chat, InputFile::file(&path))
+        .caption(t!(private_lang, "backup.caption", name = name))
+        .await
+    {
+        log::warn!("Could not send the backup to {}: {e}", user.id);
+        bot.send_message(msg.chat.id, t!(lang, "backup.cannot_send"))
+            .await?;
+        return Ok(());
+    }
+
+    if msg.chat.id != private_chat {
+        bot.send_message(msg.chat.id, t!(lang, "backup.sent"))
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Backs the database up every `BACKUP_INTERVAL` hours, keeping the last `BACKUP_RETENTION`
+/// backups.
+pub async fn periodic_backups(backups: Arc<dyn BackupRepository>) {
+    if config().backup_interval == 0 {
+        log::info!("BACKUP_INTERVAL is 0, the periodic backups are disabled");
+        return;
+    }
+
+    let period = Duration::from_secs(config().backup_interval * 3600);
+    let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+
+    loop {
+        interval.tick().await;
+
+        match create_backup(backups.as_ref()).await {
+            Ok(path) => log::info!("Backed the database up to {}", path.display()),
+            Err(e) => log::error!("Could not back the database up: {e}"),
+        }
+    }
+}
+
+/// Creates a backup in the backup directory, and removes the oldest ones beyond the retention.
+pub async fn create_backup(backups: &dyn BackupRepository) -> Result<PathBuf, Error> {
+    let dir = backup_dir();
+    std::fs::create_dir_all(&dir)?;
+
+    // With the microseconds, so that two backups started in the same second have different names
+    let name = format!(
+        "{BACKUP_PREFIX}{}{BACKUP_EXTENSION}",
+        Utc::now().format("%Y%m%d-%H%M%S-%6f")
+    );
+    let path = dir.join(&name);
+    // Written under another name first, so that an interrupted backup is never the latest one
+    let partial = dir.join(format!("{name}.partial"));
+    let written = match backups.backup(&partial).await {
+        Ok(()) => std::fs::rename(&partial, &path).map_err(Error::from),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = written {
+        if let Err(e) = std::fs::remove_file(&partial) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                log::warn!("Could not remove {}: {e}", partial.display());
+            }
+        }
+        return Err(e);
+    }
+
+    let existing = list_backups()?;
+    let outdated = existing.len().saturating_sub(config().backup_retention);
+    for old in &existing[..outdated] {
+        if let Err(e) = std::fs::remove_file(old) {
+            log::warn!("Could not remove the old backup {}: {e}", old.display());
+        }
+    }
+
+    Ok(path)
+}
+
+/// Lists the backups, from the oldest to the latest.
+fn list_backups() -> Result<Vec<PathBuf>, Error> {
+    let dir = backup_dir();
+    if !dir.exists() {
+        return Ok(vec![]);
+    }
+
+    let mut backups = std::fs::read_dir(&dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| is_backup(path))
+        .collect::<Vec<_>>();
+    // The names contain the date, so they sort chronologically
+    backups.sort();
+
+    Ok(backups)
+}
+
+fn is_backup(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|n| n.to_str())
+        .is_some_and(|n| n.starts_with(BACKUP_PREFIX) && n.ends_with(BACKUP_EXTENSION))
+}
+
+fn backup_dir() -> PathBuf {
+    config()
+        .backup_dir
+        .as_ref()
+        .map(PathBuf::from)
+        .unwrap_or_else(|| Path::new(&config().data_dir).join("backups"))
+}
@@ -1,21 +1,297 @@
-use teloxide::{payloads::SendPollSetters, requests::Requester, types::Message, Bot};
+use std::{collections::HashMap, sync::Arc};
 
-use crate::HandlerResult;
+use chrono::{Datelike, TimeZone, Timelike, Utc};
+use teloxide::{
+    payloads::SendPollSetters,
+    requests::Requester,
+    types::{InputFile, Message, PollAnswer},
+    Bot,
+};
 
-pub async fn bureau(bot: Bot, msg: Message) -> HandlerResult {
-    bot.send_poll(
+use crate::{config::config, i18n::Lang, storage::BureauRepository, t, HandlerResult};
+
+/// Number of options of the /bureau poll, whose texts are `bureau.options` in the catalogs. The
+/// index of each option is stored with the answers.
+const BUREAU_OPTIONS_COUNT: usize = 6;
+/// Index of the option meaning that the user is in the office.
+const PRESENT_OPTION: i64 = 0;
+/// Period (in days) covered by /bureau stats and /bureau export when none is given.
+const DEFAULT_PERIOD_DAYS: i64 = 30;
+/// Longest period (in days) covered by /bureau stats and /bureau export, which keeps the
+/// computation of its start from overflowing.
+const MAX_PERIOD_DAYS: i64 = 100 * 365;
+/// Number of attendees displayed by /bureau stats.
+const TOP_ATTENDEES_COUNT: usize = 5;
+/// Length of the longest bar of the histograms.
+const HISTOGRAM_WIDTH: usize = 10;
+
+/// Dispatches /bureau according to its arguments:
+/// - no argument: sends the poll,
+/// - `stats [days]`: displays the occupancy of the office,
+/// - `export [days]`: sends the answers as a CSV file.
+pub async fn bureau(
+    bot: Bot,
+    msg: Message,
+    args: String,
+    lang: Lang,
+    bureau: Arc<dyn BureauRepository>,
+) -> HandlerResult {
+    let mut args = args.split_whitespace();
+
+    match args.next() {
+        None => send_bureau_poll(bot, msg, lang, bureau).await,
+        Some(sub @ ("stats" | "export")) => {
+            let days = match args.next().map(str::parse::<i64>) {
+                None => DEFAULT_PERIOD_DAYS,
+                Some(Ok(days)) if (1..=MAX_PERIOD_DAYS).contains(&days) => days,
+                Some(_) => {
+                    bot.send_message(
+                        msg.chat.id,
+                        t!(lang, "bureau.invalid_period", max = MAX_PERIOD_DAYS),
+                    )
+                    .await?;
+                    return Ok(());
+                }
+            };
+
+            if sub == "stats" {
+                bureau_stats(bot, msg, days, lang, bureau).await
+            } else {
+                bureau_export(bot, msg, days, lang, bureau).await
+            }
+        }
+        Some(_) => {
+            bot.send_message(msg.chat.id, t!(lang, "bureau.usage"))
+                .await?;
+            Ok(())
+        }
+    }
+}
+
+async fn send_bureau_poll(
+    bot: Bot,
+    msg: Message,
+    lang: Lang,
+    bureau: Arc<dyn BureauRepository>,
+) -> HandlerResult {
+    let poll = bot
+        .send_poll(
+            msg.chat.id,
+            t!(lang, "bureau.question"),
+            (0..BUREAU_OPTIONS_COUNT).map(|i| bureau_option(lang, i)),
+        )
+        .is_anonymous(false)
+        .await?;
+
+    if let Some(poll) = poll.poll() {
+        bureau
+            .add_bureau_poll(&poll.id, &msg.chat.id.to_string(), Utc::now().timestamp())
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Records the answer of a user to a /bureau poll. Answers to other polls are ignored.
+pub async fn record_bureau_answer(
+    answer: PollAnswer,
+    bureau: Arc<dyn BureauRepository>,
+) -> HandlerResult {
+    let Some(user) = answer.voter.user() else {
+        return Ok(());
+    };
+
+    bureau
+        .record_bureau_answer(
+            &answer.poll_id,
+            &user.id.to_string(),
+            &user.full_name(),
+            // Empty if the user retracted their vote
+            answer.option_ids.first().map(|&o| o as i64),
+            Utc::now().timestamp(),
+        )
+        .await?;
+
+    Ok(())
+}
+
+async fn bureau_stats(
+    bot: Bot,
+    msg: Message,
+    days: i64,
+    lang: Lang,
+    bureau: Arc<dyn BureauRepository>,
+) -> HandlerResult {
+    let since = Utc::now().timestamp() - days * 24 * 3600;
+    let presences = bureau
+        .list_bureau_answers(&msg.chat.id.to_string(), since)
+        .await?
+        .into_iter()
+        .filter(|a| a.option_id == PRESENT_OPTION)
+        .collect::<Vec<_>>();
+
+    if presences.is_empty() {
+        bot.send_message(msg.chat.id, t!(lang, "bureau.no_presence", days = days))
+            .await?;
+        return Ok(());
+    }
+
+    let mut per_weekday = [0usize; 7];
+    let mut per_hour = [0usize; 24];
+    // Maps the telegram id to the last known name and the number of presences
+    let mut attendees = HashMap::<String, (String, usize)>::new();
+
+    for presence in presences {
+        let Some(time) = config()
+            .timezone
+            .timestamp_opt(presence.answered_at, 0)
+            .single()
+        else {
+            continue;
+        };
+
+        per_weekday[time.weekday().num_days_from_monday() as usize] += 1;
+        per_hour[time.hour() as usize] += 1;
+
+        let entry = attendees.entry(presence.telegram_id).or_default();
+        entry.0 = presence.name;
+        entry.1 += 1;
+    }
+
+    let mut attendees = attendees.into_values().collect::<Vec<_>>();
+    attendees.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+    bot.send_message(
         msg.chat.id,
-        "Qui est au bureau ?",
-        [
-            "Je suis actuellement au bureau".to_owned(),
-            "Je suis à proximité du bureau".to_owned(),
-            "Je compte m'y rendre bientôt".to_owned(),
-            "J'y suis pas".to_owned(),
-            "Je suis à Satellite".to_owned(),
-            "Je suis pas en Suisse".to_owned(),
-        ],
+        t!(
+            lang,
+            "bureau.stats",
+            days = days,
+            per_weekday = histogram((0..7).map(|d| lang.weekday(d)).zip(per_weekday)),
+            per_hour = histogram(
+                (0..24)
+                    .map(|h| format!("{:02}h", h))
+                    .zip(per_hour)
+                    .filter(|(_, count)| *count > 0)
+            ),
+            attendees = attendees
+                .into_iter()
+                .take(TOP_ATTENDEES_COUNT)
+                .map(|(name, count)| format!(" - {} ({})", name, count))
+                .collect::<Vec<_>>()
+                .join("\n"),
+        ),
     )
-    .is_anonymous(false)
     .await?;
+
+    Ok(())
+}
+
+async fn bureau_export(
+    bot: Bot,
+    msg: Message,
+    days: i64,
+    lang: Lang,
+    bureau: Arc<dyn BureauRepository>,
+) -> HandlerResult {
+    let since = Utc::now().timestamp() - days * 24 * 3600;
+    let answers = bureau
+        .list_bureau_answers(&msg.chat.id.to_string(), since)
+        .await?;
+
+    let format_time = |timestamp: i64| {
+        config()
+            .timezone
+            .timestamp_opt(timestamp, 0)
+            .single()
+            .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_default()
+    };
+
+    let mut csv = String::from("poll_sent_at,answered_at,telegram_id,name,answer\n");
+    for answer in answers {
+        let option = usize::try_from(answer.option_id)
+            .ok()
+            .filter(|i| *i < BUREAU_OPTIONS_COUNT)
+            .map(|i| bureau_option(lang, i))
+            .unwrap_or_default();
+        csv.push_str(&format!(
+            "{},{},{},{},{}\n",
+            format_time(answer.poll_sent_at),
+            format_time(answer.answered_at),
+            answer.telegram_id,
+            csv_field(&answer.name),
+            csv_field(&option),
+        ));
+    }
+
+    bot.send_document(
+        msg.chat.id,
+        InputFile::memory(csv.into_bytes()).file_name("bureau.csv"),
+    )
+    .await?;
+
     Ok(())
 }
+
+fn bureau_option(lang: Lang, index: usize) -> String {
+    t!(lang, &format!("bureau.options.{index}"))
+}
+
+/// Renders one line per entry, with a bar proportional to the count.
+fn histogram(entries: impl Iterator<Item = (String, usize)>) -> String {
+    let entries = entries.collect::<Vec<_>>();
+    let max = entries.iter().map(|(_, c)| *c).max().unwrap_or(0).max(1);
+
+    entries
+        .into_iter()
+        .map(|(label, count)| {
+            format!(
+                "{} {} {}",
+                label,
+                "█".repeat((count * HISTOGRAM_WIDTH).div_ceil(max)),
+                count
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Quotes a CSV field if needed. The fields which a spreadsheet would run as a formula (e.g. a
+/// Telegram name starting with `=`) are prefixed with `'`, so that they are displayed as text.
+fn csv_field(value: &str) -> String {
+    let value = if value.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        format!("'{value}")
+    } else {
+        value.to_owned()
+    };
+
+    if value.contains([',', '"', '\n', '\r']) {
+        format!(r#""{}""#, value.replace('"', r#""""#))
+    } else {
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_csv_fields() {
+        assert_eq!(csv_field("Alex"), "Alex");
+        assert_eq!(csv_field("Alex, Jr."), r#""Alex, Jr.""#);
+        assert_eq!(csv_field(r#"Alex "AJ""#), r#""Alex ""AJ""""#);
+        assert_eq!(csv_field("Alex\nAJ"), "\"Alex\nAJ\"");
+        assert_eq!(csv_field("=1+1"), "'=1+1");
+        assert_eq!(csv_field("+33 6"), "'+33 6");
+        assert_eq!(csv_field("-Alex"), "'-Alex");
+        assert_eq!(csv_field("@Alex"), "'@Alex");
+        assert_eq!(csv_field("\tAlex"), "'\tAlex");
+        assert_eq!(csv_field("\rAlex"), "\"'\rAlex\"");
+        assert_eq!(
+            csv_field(r#"=HYPERLINK("http://x", "y")"#),
+            r#""'=HYPERLINK(""http://x"", ""y"")""#
+        );
+    }
+}
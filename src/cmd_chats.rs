@@ -0,0 +1,569 @@
+//! Tracking of the chats the bot is a member of, and management of their authorizations from a
+//! private chat with the bot: the admins pick a chat (from a list, by id or by forwarding a
+//! message from it) and toggle its commands with an inline keyboard.
+
+use chrono::{DateTime, Utc};
+use std::{collections::BTreeMap, fmt::Display, str::FromStr, sync::Arc, time::Duration};
+
+use teloxide::{
+    payloads::{AnswerCallbackQuerySetters, EditMessageTextSetters, SendMessageSetters},
+    requests::Requester,
+    types::{
+        CallbackQuery, Chat, ChatId, ChatMemberUpdated, InlineKeyboardButton, InlineKeyboardMarkup,
+        Message, MessageOrigin,
+    },
+    Bot,
+};
+
+use crate::{
+    cmd_authentication::{authorization_label, is_group_manager},
+    cmd_help::update_chat_menu,
+    commands::RESTRICTED_COMMANDS,
+    config::config,
+    i18n::Lang,
+    storage::{
+        AdminRepository, AuthorizationRepository, ChatRepository, Error, KnownChat,
+        PermissionRepository,
+    },
+    t,
+    throttle::Throttle,
+    HandlerResult,
+};
+
+pub const AUTHORIZATION_CALLBACK_PREFIX: &str = "authz:";
+
+/// Chats recently recorded, whose title does not need to be saved again.
+static REMEMBERED: Throttle = Throttle::new();
+
+/// Button of the authorization keyboards.
+#[derive(Debug, Clone)]
+pub enum AuthorizationCallback {
+    /// Back to the list of the chats
+    Chats,
+    /// Checklist of the commands of the chat
+    Chat(ChatId),
+    /// Authorizes or unauthorizes the command in the chat
+    Toggle(ChatId, String),
+    /// Authorizes (`true`) or unauthorizes all the restricted commands in the chat
+    All(ChatId, bool),
+}
+
+impl FromStr for AuthorizationCallback {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse_chat = |id: &str| id.parse::<i64>().map(ChatId).map_err(|_| ());
+
+        match s.split(':').collect::<Vec<_>>()[..] {
+            ["chats"] => Ok(Self::Chats),
+            ["chat", chat] => Ok(Self::Chat(parse_chat(chat)?)),
+            ["toggle", chat, command] => Ok(Self::Toggle(parse_chat(chat)?, command.to_owned())),
+            ["all", chat, "on"] => Ok(Self::All(parse_chat(chat)?, true)),
+            ["all", chat, "off"] => Ok(Self::All(parse_chat(chat)?, false)),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Display for AuthorizationCallback {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Chats => write!(f, "{AUTHORIZATION_CALLBACK_PREFIX}chats"),
+            Self::Chat(chat) => write!(f, "{AUTHORIZATION_CALLBACK_PREFIX}chat:{chat}"),
+            Self::Toggle(chat, command) => {
+                write!(f, "{AUTHORIZATION_CALLBACK_PREFIX}toggle:{chat}:{command}")
+            }
+            Self::All(chat, authorize) => {
+                let state = if *authorize { "on" } else { "off" };
+                write!(f, "{AUTHORIZATION_CALLBACK_PREFIX}all:{chat}:{state}")
+            }
+        }
+    }
+}
+
+/// Records the groups in which the bot receives messages, so that they can be listed by
+/// `/authorize`. A chat is saved at most once an hour, to keep its title up to date without
+/// writing to the database on every message.
+pub async fn remember_chat(msg: Message, chats: Arc<dyn ChatRepository>) {
+    if msg.chat.is_private()
+        || !REMEMBERED.allow(&msg.chat.id.to_string(), Duration::from_secs(3600))
+    {
+        return;
+    }
+
+    if let Err(e) = chats.save_chat(&known_chat(&msg.chat, None)).await {
+        log::error!("Could not remember the chat {}: {e}", msg.chat.id);
+    }
+}
+
+/// Records the chats the bot is added to, and forgets the ones it leaves or is kicked from
+/// together with their authorizations and the rules of the users in them, so that nothing applies
+/// if the bot is added back later. Their permanence slots, subscription to the events and language
+/// are kept, as the bot may have been removed by mistake; `forget-chat` deletes them.
+pub async fn chat_membership(
+    update: ChatMemberUpdated,
+    authorizations: Arc<dyn AuthorizationRepository>,
+    permissions: Arc<dyn PermissionRepository>,
+    chats: Arc<dyn ChatRepository>,
+) -> HandlerResult {
+    // In a private chat, the user blocked or unblocked the bot
+    if update.chat.is_private() {
+        return Ok(());
+    }
+
+    let chat_id = update.chat.id.to_string();
+    if update.new_chat_member.is_present() {
+        // The bot can also have been promoted or restricted, which does not change the join date
+        let joined_at = (!update.old_chat_member.is_present()).then(|| update.date.timestamp());
+        if joined_at.is_some() {
+            log::info!("The bot was added to the chat {chat_id}");
+        }
+        chats
+            .save_chat(&known_chat(&update.chat, joined_at))
+            .await?;
+    } else {
+        chats.remove_chat(&chat_id).await?;
+        let authorizations = authorizations.unauthorize_chat(&chat_id).await?;
+        let rules = permissions.remove_chat_permissions(&chat_id).await?;
+        log::info!(
+            "The bot was removed from the chat {chat_id}, its {authorizations} authorization(s) \
+             and {rules} rule(s) were deleted"
+        );
+    }
+
+    Ok(())
+}
+
+/// Record of the chat, the join date being kept as is if `joined_at` is `None`.
+pub fn known_chat(chat: &Chat, joined_at: Option<i64>) -> KnownChat {
+    let kind = if chat.is_channel() {
+        "channel"
+    } else if chat.is_supergroup() {
+        "supergroup"
+    } else if chat.is_group() {
+        "group"
+    } else {
+        ""
+    };
+
+    KnownChat {
+        chat_id: chat.id.to_string(),
+        title: chat_name(chat),
+        kind: kind.to_owned(),
+        joined_at,
+    }
+}
+
+/// Name of the chat as displayed to the admins.
+pub fn chat_name(chat: &Chat) -> String {
+    if let Some(title) = chat.title() {
+        return title.to_owned();
+    }
+
+    let name = [chat.first_name(), chat.last_name()]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>()
+        .join(" ");
+    if !name.is_empty() {
+        name
+    } else if let Some(username) = chat.username() {
+        format!("@{username}")
+    } else {
+        chat.id.to_string()
+    }
+}
+
+/// Chats whose authorizations can be managed: the ones the bot is a member of, and the ones which
+/// have authorizations (in the database or in the config file), named by their id if the bot has
+/// not seen them. They are sorted by name.
+async fn known_chats(
+    authorizations: &dyn AuthorizationRepository,
+    chats: &dyn ChatRepository,
+) -> Result<Vec<(ChatId, KnownChat)>, Error> {
+    let unseen = |chat: ChatId| KnownChat {
+        chat_id: chat.to_string(),
+        title: chat.to_string(),
+        kind: String::new(),
+        joined_at: None,
+    };
+
+    let mut known = BTreeMap::new();
+    for authorization in authorizations.list_all_authorizations().await? {
+        if let Ok(id) = authorization.chat_id.parse::<i64>() {
+            known.insert(ChatId(id), unseen(ChatId(id)));
+        }
+    }
+    for chat in config().chats.keys() {
+        known.insert(*chat, unseen(*chat));
+    }
+    for chat in chats.list_chats().await? {
+        if let Ok(id) = chat.chat_id.parse::<i64>() {
+            known.insert(ChatId(id), chat);
+        }
+    }
+
+    let mut known = known.into_iter().collect::<Vec<_>>();
+    known.sort_by(|(a_id, a), (b_id, b)| a.title.cmp(&b.title).then(a_id.0.cmp(&b_id.0)));
+    Ok(known)
+}
+
+/// Lists the chats with their kind, the date the bot was added to them and their authorized
+/// commands (🔒 when authorized by the config file).
+pub async fn chats(
+    bot: Bot,
+    msg: Message,
+    lang: Lang,
+    authorizations: Arc<dyn AuthorizationRepository>,
+    chats: Arc<dyn ChatRepository>,
+) -> HandlerResult {
+    let known = known_chats(authorizations.as_ref(), chats.as_ref()).await?;
+    if known.is_empty() {
+        bot.send_message(msg.chat.id, t!(lang, "chats.none"))
+            .await?;
+        return Ok(());
+    }
+
+    let now = Utc::now().timestamp();
+    let mut entries = Vec::new();
+    for (id, chat) in known {
+        let configured = config().chat_authorizations(id);
+        let mut commands = authorizations
+            .list_authorizations(&chat.chat_id)
+            .await?
+            .iter()
+            .filter(|a| !configured.contains(&a.command))
+            .map(|a| authorization_label(lang, a, now))
+            .collect::<Vec<_>>();
+        commands.extend(configured.iter().map(|command| format!("{command} 🔒")));
+
+        let kind = if chat.kind.is_empty() {
+            "unknown"
+        } else {
+            &chat.kind
+        };
+        let joined = match chat
+            .joined_at
+            .and_then(|timestamp| DateTime::from_timestamp(timestamp, 0))
+        {
+            Some(date) => t!(
+                lang,
+                "chats.joined",
+                date = date
+                    .with_timezone(&config().timezone)
+                    .format(&t!(lang, "chats.date_format"))
+            ),
+            None => t!(lang, "chats.joined_unknown"),
+        };
+        let commands = if commands.is_empty() {
+            t!(lang, "chats.no_commands")
+        } else {
+            t!(lang, "chats.commands", commands = commands.join(", "))
+        };
+
+        entries.push(t!(
+            lang,
+            "chats.entry",
+            title = chat.title,
+            kind = t!(lang, &format!("chats.kinds.{kind}")),
+            chat_id = chat.chat_id,
+            joined = joined,
+            commands = commands
+        ));
+    }
+
+    bot.send_message(
+        msg.chat.id,
+        format!("{}\n\n{}", t!(lang, "chats.header"), entries.join("\n\n")),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Name of a chat which may not be the current one, or its id if the bot has not seen it.
+pub async fn known_chat_name(chats: &dyn ChatRepository, chat: ChatId) -> Result<String, Error> {
+    Ok(chats
+        .list_chats()
+        .await?
+        .into_iter()
+        .find(|c| c.chat_id == chat.to_string())
+        .map(|c| c.title)
+        .unwrap_or_else(|| chat.to_string()))
+}
+
+/// Sends the list of the known chats, to pick the one whose authorizations to manage.
+pub async fn send_chat_list(
+    bot: &Bot,
+    to: ChatId,
+    lang: Lang,
+    authorizations: &dyn AuthorizationRepository,
+    chats: &dyn ChatRepository,
+) -> HandlerResult {
+    let (text, keyboard) = chat_list(lang, authorizations, chats).await?;
+    bot.send_message(to, text).reply_markup(keyboard).await?;
+
+    Ok(())
+}
+
+/// Sends the checklist of the commands of the chat.
+pub async fn send_checklist(
+    bot: &Bot,
+    to: ChatId,
+    chat: ChatId,
+    lang: Lang,
+    authorizations: &dyn AuthorizationRepository,
+    chats: &dyn ChatRepository,
+) -> HandlerResult {
+    let (text, keyboard) = checklist(chat, lang, to != chat, authorizations, chats).await?;
+    bot.send_message(to, text).reply_markup(keyboard).await?;
+
+    Ok(())
+}
+
+async fn chat_list(
+    lang: Lang,
+    authorizations: &dyn AuthorizationRepository,
+    chats: &dyn ChatRepository,
+) -> Result<(String, InlineKeyboardMarkup), Error> {
+    let known = known_chats(authorizations, chats).await?;
+    if known.is_empty() {
+        return Ok((t!(lang, "auth.no_chats"), InlineKeyboardMarkup::default()));
+    }
+
+    Ok((
+        t!(lang, "auth.choose_chat"),
+        InlineKeyboardMarkup::new(known.into_iter().map(|(id, chat)| {
+            [InlineKeyboardButton::callback(
+                chat.title,
+                AuthorizationCallback::Chat(id).to_string(),
+            )]
+        })),
+    ))
+}
+
+/// Lists the group restricted commands with their state in the chat: ✅ authorized, ❌ not
+/// authorized, 🔒 authorized by the config file (which cannot be changed from Telegram). `back`
+/// adds a button back to the chat list, which must not be shown in the chat itself.
+async fn checklist(
+    chat: ChatId,
+    lang: Lang,
+    back: bool,
+    authorizations: &dyn AuthorizationRepository,
+    chats: &dyn ChatRepository,
+) -> Result<(String, InlineKeyboardMarkup), Error> {
+    let authorized = authorizations
+        .list_authorizations(&chat.to_string())
+        .await?;
+    let configured = config().chat_authorizations(chat);
+
+    let mut rows = RESTRICTED_COMMANDS
+        .iter()
+        .map(|command| {
+            let command = command.shortand();
+            let state = if configured.iter().any(|c| c == command) {
+                "🔒"
+            } else if let Some(authorization) = authorized.iter().find(|a| a.command == command) {
+                if authorization.expires_at.is_some() {
+                    "⏳"
+                } else {
+                    "✅"
+                }
+            } else {
+                "❌"
+            };
+            vec![InlineKeyboardButton::callback(
+                format!("{state} /{command}"),
+                AuthorizationCallback::Toggle(chat, command.to_owned()).to_string(),
+            )]
+        })
+        .collect::<Vec<_>>();
+    rows.push(vec![
+        InlineKeyboardButton::callback(
+            t!(lang, "auth.authorize_all"),
+            AuthorizationCallback::All(chat, true).to_string(),
+        ),
+        InlineKeyboardButton::callback(
+            t!(lang, "auth.unauthorize_all"),
+            AuthorizationCallback::All(chat, false).to_string(),
+        ),
+    ]);
+    if back {
+        rows.push(vec![InlineKeyboardButton::callback(
+            t!(lang, "auth.back"),
+            AuthorizationCallback::Chats.to_string(),
+        )]);
+    }
+
+    Ok((
+        t!(
+            lang,
+            "auth.checklist",
+            chat = known_chat_name(chats, chat).await?,
+            chat_id = chat
+        ),
+        InlineKeyboardMarkup::new(rows),
+    ))
+}
+
+/// Handles the buttons of the chat list and of the checklists, which only the admins can use (and
+/// the Telegram administrators of a group for its checklist, see [`is_group_manager`]).
+#[allow(clippy::too_many_arguments)]
+pub async fn authorization_callback(
+    bot: Bot,
+    callback_query: CallbackQuery,
+    action: AuthorizationCallback,
+    lang: Lang,
+    admins: Arc<dyn AdminRepository>,
+    authorizations: Arc<dyn AuthorizationRepository>,
+    permissions: Arc<dyn PermissionRepository>,
+    chats: Arc<dyn ChatRepository>,
+) -> HandlerResult {
+    let Some(message) = callback_query.message.as_ref() else {
+        return Ok(());
+    };
+    let shown_in = message.chat().id;
+    let own_group = match &action {
+        AuthorizationCallback::Chats => false,
+        AuthorizationCallback::Chat(chat)
+        | AuthorizationCallback::Toggle(chat, _)
+        | AuthorizationCallback::All(chat, _) => *chat == shown_in,
+    };
+    let allowed = admins.is_admin(&callback_query.from.id.to_string()).await?
+        || (own_group && is_group_manager(&bot, shown_in, callback_query.from.id).await?);
+    if !allowed {
+        bot.answer_callback_query(callback_query.id)
+            .text(t!(lang, "auth.admins_only"))
+            .await?;
+        return Ok(());
+    }
+
+    let back = !own_group;
+    let (text, keyboard) = match action {
+        AuthorizationCallback::Chats => {
+            bot.answer_callback_query(callback_query.id).await?;
+            chat_list(lang, authorizations.as_ref(), chats.as_ref()).await?
+        }
+        AuthorizationCallback::Chat(chat) => {
+            bot.answer_callback_query(callback_query.id).await?;
+            checklist(chat, lang, back, authorizations.as_ref(), chats.as_ref()).await?
+        }
+        AuthorizationCallback::Toggle(chat, command) => {
+            if !RESTRICTED_COMMANDS.iter().any(|c| c.shortand() == command) {
+                bot.answer_callback_query(callback_query.id)
+                    .text(t!(lang, "auth.unknown_command"))
+                    .await?;
+                return Ok(());
+            }
+            if config().chat_authorizations(chat).contains(&command) {
+                // Nothing changes, so the message must not be edited
+                bot.answer_callback_query(callback_query.id)
+                    .text(t!(lang, "auth.locked", command = command))
+                    .await?;
+                return Ok(());
+            }
+
+            let chat_id = chat.to_string();
+            let text = if authorizations.unauthorize(&chat_id, &command).await? {
+                t!(lang, "auth.toggled_off", command = command)
+            } else {
+                authorizations.authorize(&chat_id, &command, None).await?;
+                t!(lang, "auth.toggled_on", command = command)
+            };
+            bot.answer_callback_query(callback_query.id)
+                .text(text)
+                .await?;
+            update_chat_menu(
+                &bot,
+                chat,
+                admins.as_ref(),
+                authorizations.as_ref(),
+                permissions.as_ref(),
+            )
+            .await?;
+
+            checklist(chat, lang, back, authorizations.as_ref(), chats.as_ref()).await?
+        }
+        AuthorizationCallback::All(chat, authorize) => {
+            let chat_id = chat.to_string();
+            let existing = authorizations.list_authorizations(&chat_id).await?;
+            let mut changed = false;
+            for command in RESTRICTED_COMMANDS.iter().map(|c| c.shortand()) {
+                changed |= if authorize {
+                    // The temporary authorizations keep their expiry
+                    !existing.iter().any(|a| a.command == command)
+                        && authorizations.authorize(&chat_id, command, None).await?
+                } else {
+                    authorizations.unauthorize(&chat_id, command).await?
+                };
+            }
+            bot.answer_callback_query(callback_query.id)
+                .text(if authorize {
+                    t!(lang, "auth.all_authorized")
+                } else {
+                    t!(lang, "auth.all_unauthorized")
+                })
+                .await?;
+            if !changed {
+                // Telegram refuses to edit a message without changing it
+                return Ok(());
+            }
+            update_chat_menu(
+                &bot,
+                chat,
+                admins.as_ref(),
+                authorizations.as_ref(),
+                permissions.as_ref(),
+            )
+            .await?;
+
+            checklist(chat, lang, back, authorizations.as_ref(), chats.as_ref()).await?
+        }
+    };
+
+    bot.edit_message_text(message.chat().id, message.id(), text)
+        .reply_markup(keyboard)
+        .await?;
+
+    Ok(())
+}
+
+/// Shows the checklist of the chat a message forwarded by an admin in a private chat comes from.
+/// Telegram only tells it for the messages sent on behalf of a chat (channel posts, anonymous
+/// admins), not for the messages of the members.
+pub async fn forwarded_message(
+    bot: Bot,
+    msg: Message,
+    lang: Lang,
+    admins: Arc<dyn AdminRepository>,
+    authorizations: Arc<dyn AuthorizationRepository>,
+    chats: Arc<dyn ChatRepository>,
+) -> HandlerResult {
+    let Some(user) = &msg.from else {
+        return Ok(());
+    };
+    if !admins.is_admin(&user.id.to_string()).await? {
+        return Ok(());
+    }
+
+    let chat = match msg.forward_origin() {
+        Some(MessageOrigin::Chat { sender_chat, .. }) => sender_chat,
+        Some(MessageOrigin::Channel { chat, .. }) => chat,
+        _ => {
+            bot.send_message(msg.chat.id, t!(lang, "auth.forwarded_from_user"))
+                .await?;
+            return Ok(());
+        }
+    };
+
+    chats.save_chat(&known_chat(chat, None)).await?;
+    send_checklist(
+        &bot,
+        msg.chat.id,
+        chat.id,
+        lang,
+        authorizations.as_ref(),
+        chats.as_ref(),
+    )
+    .await
+}
@@ -0,0 +1,243 @@
+use std::{sync::Arc, time::Duration};
+
+use chrono::{DateTime, Datelike, TimeDelta, Utc};
+use teloxide::{
+    payloads::{AnswerCallbackQuerySetters, SendMessageSetters},
+    requests::Requester,
+    types::{CallbackQuery, ChatId, InlineKeyboardButton, InlineKeyboardMarkup, Message},
+    Bot,
+};
+
+use crate::{
+    config::config,
+    directus::{get_event, get_upcoming_events, Event},
+    i18n::{chat_language, Lang},
+    storage::{EventRepository, LanguageRepository},
+    t, HandlerResult,
+};
+
+/// Prefix of the callback data of the details buttons, followed by the id of the event.
+pub const EVENT_CALLBACK_PREFIX: &str = "event:";
+/// Maximum number of events listed by /events.
+const EVENTS_LIST_LIMIT: u64 = 10;
+
+/// Lists the upcoming events, with a button to display the details of each of them.
+pub async fn events(bot: Bot, msg: Message, lang: Lang) -> HandlerResult {
+    let events = match get_upcoming_events(Some(EVENTS_LIST_LIMIT)).await {
+        Ok(v) => v,
+        Err(e) => {
+            log::error!("Could not fetch events: {e:#?}");
+            bot.send_message(msg.chat.id, e.user_message(lang)).await?;
+            return Ok(());
+        }
+    };
+
+    if events.is_empty() {
+        bot.send_message(msg.chat.id, t!(lang, "events.none"))
+            .await?;
+        return Ok(());
+    }
+
+    bot.send_message(
+        msg.chat.id,
+        t!(
+            lang,
+            "events.upcoming",
+            events = events
+                .iter()
+                .map(|e| format!(" - {}: {}", format_date(lang, e.start), e.title))
+                .collect::<Vec<_>>()
+                .join("\n")
+        ),
+    )
+    .reply_markup(InlineKeyboardMarkup::new(events.iter().map(|e| {
+        vec![InlineKeyboardButton::callback(
+            e.title.clone(),
+            format!("{}{}", EVENT_CALLBACK_PREFIX, e.id),
+        )]
+    })))
+    .await?;
+
+    Ok(())
+}
+
+/// Sends the details of the event selected in the list of /events.
+pub async fn event_details(
+    bot: Bot,
+    callback_query: CallbackQuery,
+    id: i32,
+    lang: Lang,
+) -> HandlerResult {
+    let Some(message) = callback_query.message.as_ref() else {
+        return Ok(());
+    };
+
+    let event = match get_event(id).await {
+        Ok(v) => v,
+        Err(e) => {
+            log::error!("Could not fetch event {id}: {e:#?}");
+            bot.answer_callback_query(callback_query.id)
+                .text(e.user_message(lang))
+                .await?;
+            return Ok(());
+        }
+    };
+
+    bot.answer_callback_query(callback_query.id).await?;
+    bot.send_message(message.chat().id, format_event(lang, &event))
+        .await?;
+
+    Ok(())
+}
+
+/// Subscribes the chat to the announcements of new events and to the reminders.
+pub async fn event_subscribe(
+    bot: Bot,
+    msg: Message,
+    lang: Lang,
+    events: Arc<dyn EventRepository>,
+) -> HandlerResult {
+    let subscribed = events.subscribe_events(&msg.chat.id.to_string()).await?;
+
+    bot.send_message(
+        msg.chat.id,
+        if subscribed {
+            t!(lang, "events.subscribed")
+        } else {
+            t!(lang, "events.already_subscribed")
+        },
+    )
+    .await?;
+
+    Ok(())
+}
+
+pub async fn event_unsubscribe(
+    bot: Bot,
+    msg: Message,
+    lang: Lang,
+    events: Arc<dyn EventRepository>,
+) -> HandlerResult {
+    let unsubscribed = events.unsubscribe_events(&msg.chat.id.to_string()).await?;
+
+    bot.send_message(
+        msg.chat.id,
+        if unsubscribed {
+            t!(lang, "events.unsubscribed")
+        } else {
+            t!(lang, "events.already_unsubscribed")
+        },
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Background task polling Directus, announcing new events and reminding upcoming ones to the
+/// subscribed chats.
+pub async fn event_announcements(
+    bot: Bot,
+    repository: Arc<dyn EventRepository>,
+    languages: Arc<dyn LanguageRepository>,
+) {
+    let mut interval =
+        tokio::time::interval(Duration::from_secs(config().event_sync_interval * 60));
+
+    let mut first = true;
+    loop {
+        interval.tick().await;
+
+        match sync_events(&bot, repository.as_ref(), languages.as_ref(), first).await {
+            Ok(()) => first = false,
+            Err(e) => log::error!("Could not synchronize events: {e:#?}"),
+        }
+    }
+}
+
+async fn sync_events(
+    bot: &Bot,
+    repository: &dyn EventRepository,
+    languages: &dyn LanguageRepository,
+    first: bool,
+) -> HandlerResult {
+    let events = get_upcoming_events(None).await?;
+    let reminder_delay = TimeDelta::minutes(config().event_reminder);
+
+    // When no event was ever announced (first start, reset or restored database), the existing
+    // events are recorded without announcing them, not to flood the subscribed chats
+    if first && !repository.has_announced_events().await? {
+        for event in &events {
+            repository.mark_event_announced(event.id).await?;
+        }
+        return Ok(());
+    }
+
+    let chats = repository
+        .list_event_subscriptions()
+        .await?
+        .into_iter()
+        .filter_map(|chat_id| chat_id.parse::<i64>().ok().map(ChatId))
+        .collect::<Vec<_>>();
+    if chats.is_empty() {
+        // Nothing is recorded, so the first subscribers get the upcoming events
+        return Ok(());
+    }
+    let mut chats_lang = Vec::with_capacity(chats.len());
+    for chat in chats {
+        chats_lang.push((chat, chat_language(languages, chat).await));
+    }
+
+    for event in events {
+        let key = match repository.event_reminded(event.id).await? {
+            None => {
+                repository.mark_event_announced(event.id).await?;
+                "events.new"
+            }
+            Some(false) if event.start - Utc::now() <= reminder_delay => {
+                repository.mark_event_reminded(event.id).await?;
+                "events.reminder"
+            }
+            Some(_) => continue,
+        };
+
+        for &(chat, lang) in &chats_lang {
+            let text = t!(lang, key, event = format_event(lang, &event));
+            if let Err(e) = bot.send_message(chat, text).await {
+                log::error!(
+                    "Could not announce event {} in chat {}: {e:#?}",
+                    event.id,
+                    chat
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn format_event(lang: Lang, event: &Event) -> String {
+    let mut text = format!(
+        "{}\n{}",
+        event.title,
+        t!(lang, "events.when", date = format_date(lang, event.start))
+    );
+
+    if let Some(location) = &event.location {
+        text.push('\n');
+        text.push_str(&t!(lang, "events.location", location = location));
+    }
+    if let Some(description) = &event.description {
+        text.push_str(&format!("\n\n{}", description));
+    }
+
+    text
+}
+
+fn format_date(lang: Lang, date: DateTime<Utc>) -> String {
+    let date = date.with_timezone(&config().timezone);
+    format!(
+        "{} {}",
+        lang.weekday(date.weekday().num_days_from_monday() as usize),
+        date.format(&t!(lang, "events.date_format"))
+    )
+}
@@ -0,0 +1,345 @@
+use std::{
+    collections::BTreeSet,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use teloxide::{
+    payloads::{DeleteMyCommandsSetters, SetMyCommandsSetters},
+    requests::Requester,
+    types::{BotCommand, BotCommandScope, ChatId, Message, Recipient, UserId},
+    Bot,
+};
+
+use crate::{
+    cmd_authentication::sent_by_group_manager,
+    commands::{resolve_permission, Access, Command, Decision, COMMANDS},
+    config::config,
+    i18n::Lang,
+    storage::{AdminRepository, AuthorizationRepository, Error, PermissionRepository},
+    t, HandlerResult,
+};
+
+/// Lists the commands the user can use in the chat, or details the usage of a command with
+/// `/help <command>`.
+pub async fn help(
+    bot: Bot,
+    msg: Message,
+    topic: String,
+    lang: Lang,
+    admins: Arc<dyn AdminRepository>,
+    authorizations: Arc<dyn AuthorizationRepository>,
+    permissions: Arc<dyn PermissionRepository>,
+) -> HandlerResult {
+    let usable = usable_commands(
+        msg.chat.id,
+        msg.from.as_ref().map(|u| u.id),
+        sent_by_group_manager(&bot, &msg).await,
+        admins.as_ref(),
+        authorizations.as_ref(),
+        permissions.as_ref(),
+    )
+    .await?;
+
+    let topic = topic.trim().trim_start_matches('/').to_lowercase();
+    if topic.is_empty() {
+        bot.send_message(
+            msg.chat.id,
+            format!(
+                "{}\n{}\n\n{}",
+                t!(lang, "help.header"),
+                usable
+                    .iter()
+                    .map(|c| format!("/{} — {}", c.shortand(), description(lang, c)))
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+                t!(lang, "help.details")
+            ),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let Some(command) = COMMANDS.into_iter().find(|c| c.shortand() == topic) else {
+        bot.send_message(msg.chat.id, t!(lang, "help.unknown", command = topic))
+            .await?;
+        return Ok(());
+    };
+
+    let mut text = t!(lang, &format!("usage.{}", command.shortand()));
+    if !usable.iter().any(|c| c.shortand() == command.shortand()) {
+        text.push_str("\n\n");
+        text.push_str(&match command.access() {
+            Access::Admins => t!(lang, "help.admins_only"),
+            _ => t!(lang, "help.not_authorized"),
+        });
+    }
+    bot.send_message(msg.chat.id, text).await?;
+
+    Ok(())
+}
+
+/// Commands the user can use in the chat: the public ones, the ones the user is allowed to use
+/// in the chat, and the admin ones if the user is an admin (or those managing the authorizations
+/// if the user is a `group_manager`, see
+/// [`is_group_manager`](crate::cmd_authentication::is_group_manager)).
+async fn usable_commands(
+    chat: ChatId,
+    user: Option<UserId>,
+    group_manager: bool,
+    admins: &dyn AdminRepository,
+    authorizations: &dyn AuthorizationRepository,
+    permissions: &dyn PermissionRepository,
+) -> Result<Vec<Command>, Error> {
+    let is_admin = match user {
+        Some(user) => admins.is_admin(&user.to_string()).await?,
+        None => false,
+    };
+
+    let mut usable = Vec::new();
+    for command in COMMANDS {
+        let allowed = match command.access() {
+            Access::Everyone => true,
+            Access::AuthorizedChats => {
+                resolve_permission(chat, user, command.shortand(), authorizations, permissions)
+                    .await?
+                    == Decision::Allowed
+            }
+            Access::Admins => is_admin || (group_manager && command.manages_authorizations()),
+        };
+        if allowed {
+            usable.push(command);
+        }
+    }
+
+    Ok(usable)
+}
+
+fn description(lang: Lang, command: &Command) -> String {
+    t!(lang, &format!("command.{}", command.shortand()))
+}
+
+// ------------------------------ COMMAND MENUS -------------------------------
+
+/// Sets the menus of commands displayed by Telegram: the public commands everywhere, plus the
+/// authorized commands in the chats which have some (and in the private chats of the users with
+/// rules), and the admin commands in the private chats of the admins. With
+/// `GROUP_ADMINS_MANAGE_AUTHORIZATIONS`, the administrators of the groups also see the commands
+/// managing the authorizations. Telegram shows the menu of the most specific scope.
+pub async fn update_command_menus(
+    bot: &Bot,
+    admins: &dyn AdminRepository,
+    authorizations: &dyn AuthorizationRepository,
+    permissions: &dyn PermissionRepository,
+) -> Result<(), Error> {
+    let public = COMMANDS
+        .into_iter()
+        .filter(|c| c.access() == Access::Everyone)
+        .collect::<Vec<_>>();
+    set_menu(bot, BotCommandScope::Default, &public).await;
+    set_menu(bot, BotCommandScope::AllPrivateChats, &public).await;
+    set_menu(
+        bot,
+        BotCommandScope::AllGroupChats,
+        &group_menu(public.clone()),
+    )
+    .await;
+    if config().group_admins_manage_authorizations {
+        set_menu(
+            bot,
+            BotCommandScope::AllChatAdministrators,
+            &group_menu(with_management(public)),
+        )
+        .await;
+    } else {
+        delete_menu(bot, BotCommandScope::AllChatAdministrators).await;
+    }
+
+    let mut chats = BTreeSet::new();
+    chats.extend(
+        authorizations
+            .list_all_authorizations()
+            .await?
+            .into_iter()
+            .filter_map(|a| a.chat_id.parse::<i64>().ok().map(ChatId)),
+    );
+    chats.extend(config().chats.keys().copied());
+    chats.extend(
+        admins
+            .list_admins()
+            .await?
+            .into_iter()
+            .filter_map(|a| a.telegram_id.parse::<i64>().ok().map(ChatId)),
+    );
+    chats.extend(
+        permissions
+            .list_permissions()
+            .await?
+            .into_iter()
+            .filter_map(|p| p.user_id.parse::<i64>().ok().map(ChatId)),
+    );
+
+    for chat in chats {
+        update_chat_menu(bot, chat, admins, authorizations, permissions).await?;
+    }
+
+    Ok(())
+}
+
+/// Updates the menu of the chat after its authorizations (or the admin status or the rules of the
+/// user, for a private chat) changed. Only the errors of the storage are returned: those of
+/// Telegram are logged, as a menu which could not be updated (e.g. the user never started a chat
+/// with the bot) must not fail the command which changed it.
+pub async fn update_chat_menu(
+    bot: &Bot,
+    chat: ChatId,
+    admins: &dyn AdminRepository,
+    authorizations: &dyn AuthorizationRepository,
+    permissions: &dyn PermissionRepository,
+) -> Result<(), Error> {
+    let commands = usable_commands(
+        chat,
+        chat.as_user(),
+        false,
+        admins,
+        authorizations,
+        permissions,
+    )
+    .await?;
+    let scope = BotCommandScope::Chat {
+        chat_id: Recipient::Id(chat),
+    };
+
+    let specific = commands.iter().any(|c| c.access() != Access::Everyone);
+    if !chat.is_user() {
+        // The menu of the administrators of the chat takes precedence over the one of the chat
+        let scope = BotCommandScope::ChatAdministrators {
+            chat_id: Recipient::Id(chat),
+        };
+        if specific && config().group_admins_manage_authorizations {
+            set_menu(bot, scope, &group_menu(with_management(commands.clone()))).await;
+        } else {
+            delete_menu(bot, scope).await;
+        }
+    }
+
+    if !specific {
+        // The chat has nothing specific, the menu of all the chats applies
+        delete_menu(bot, scope).await;
+    } else if chat.is_user() {
+        set_menu(bot, scope, &commands).await;
+    } else {
+        set_menu(bot, scope, &group_menu(commands)).await;
+    }
+
+    Ok(())
+}
+
+/// Adds the commands managing the authorizations, for the menus of the Telegram administrators
+/// of the groups.
+fn with_management(mut commands: Vec<Command>) -> Vec<Command> {
+    commands.extend(COMMANDS.into_iter().filter(|c| c.manages_authorizations()));
+    commands
+}
+
+/// Leaves /authenticate out of the menus of the groups, where the token would be disclosed.
+fn group_menu(commands: Vec<Command>) -> Vec<Command> {
+    commands
+        .into_iter()
+        .filter(|c| !matches!(c, Command::Authenticate(..)))
+        .collect()
+}
+
+/// Minimal delay between two requests changing the menus, which are sent one after another for
+/// every chat and would otherwise exceed the rate limits of Telegram.
+const MENU_REQUEST_INTERVAL: Duration = Duration::from_millis(100);
+
+/// When the next request changing the menus can be sent.
+static NEXT_MENU_REQUEST: Mutex<Option<Instant>> = Mutex::new(None);
+
+/// Scopes and languages whose menu was deleted since the bot started, see [`set_menu`].
+static CLEARED_LANGUAGE_MENUS: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+
+/// Waits until a request changing the menus can be sent, concurrent callers being spaced out too.
+async fn pace_menu_request() {
+    let wait = {
+        let mut next = NEXT_MENU_REQUEST.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        let at = next.filter(|next| *next > now).unwrap_or(now);
+        *next = Some(at + MENU_REQUEST_INTERVAL);
+        at - now
+    };
+    tokio::time::sleep(wait).await;
+}
+
+/// Deletes the menu of the scope in every language, so that the menu of a broader scope applies.
+async fn delete_menu(bot: &Bot, scope: BotCommandScope) {
+    for language_code in std::iter::once(None).chain(Lang::ALL.map(|l| Some(l.code()))) {
+        pace_menu_request().await;
+        let mut request = bot.delete_my_commands().scope(scope.clone());
+        if let Some(code) = language_code {
+            request = request.language_code(code);
+        }
+        if let Err(e) = request.await {
+            log::error!("Could not delete the command menu of {scope:?}: {e}");
+        }
+    }
+
+    CLEARED_LANGUAGE_MENUS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .extend(Lang::ALL.map(|lang| format!("{scope:?} {lang}")));
+}
+
+/// Sets the menu of the scope in every language, the default language being used for the
+/// clients in other languages. The languages whose menu is the same as the default one are
+/// skipped, their menu being only deleted once since the bot started, in case it was set before.
+async fn set_menu(bot: &Bot, scope: BotCommandScope, commands: &[Command]) {
+    let menu = |lang: Lang| {
+        commands
+            .iter()
+            .map(|c| BotCommand::new(c.shortand(), description(lang, c)))
+            .collect::<Vec<_>>()
+    };
+
+    let default_menu = menu(config().default_language);
+    pace_menu_request().await;
+    if let Err(e) = bot
+        .set_my_commands(default_menu.clone())
+        .scope(scope.clone())
+        .await
+    {
+        log::error!("Could not set the command menu of {scope:?}: {e}");
+    }
+    for lang in Lang::ALL {
+        let lang_menu = menu(lang);
+        if lang_menu == default_menu {
+            let not_cleared = CLEARED_LANGUAGE_MENUS
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .insert(format!("{scope:?} {lang}"));
+            if not_cleared {
+                pace_menu_request().await;
+                if let Err(e) = bot
+                    .delete_my_commands()
+                    .scope(scope.clone())
+                    .language_code(lang.code())
+                    .await
+                {
+                    log::error!("Could not delete the command menu of {scope:?} in {lang}: {e}");
+                }
+            }
+            continue;
+        }
+
+        pace_menu_request().await;
+        if let Err(e) = bot
+            .set_my_commands(lang_menu)
+            .scope(scope.clone())
+            .language_code(lang.code())
+            .await
+        {
+            log::error!("Could not set the command menu of {scope:?} in {lang}: {e}");
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use std::sync::Arc;
+
+use teloxide::{requests::Requester, types::Message, Bot};
+
+use crate::{
+    i18n::Lang,
+    storage::{AdminRepository, LanguageRepository},
+    t, HandlerResult,
+};
+
+/// Displays the language of the bot, or changes it with `/lang <code>`. In a private chat it
+/// changes the language of the user, which is also used in the groups without one; in a group it
+/// changes the language of the group, which only the admins can do.
+pub async fn language(
+    bot: Bot,
+    msg: Message,
+    code: String,
+    lang: Lang,
+    admins: Arc<dyn AdminRepository>,
+    languages: Arc<dyn LanguageRepository>,
+) -> HandlerResult {
+    let available = Lang::ALL
+        .into_iter()
+        .map(|l| format!("{} ({})", l.code(), t!(l, "lang.name")))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let code = code.trim();
+    if code.is_empty() {
+        bot.send_message(
+            msg.chat.id,
+            t!(
+                lang,
+                "lang.current",
+                language = t!(lang, "lang.name"),
+                languages = available
+            ),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let Some(new_lang) = Lang::from_code(code) else {
+        bot.send_message(msg.chat.id, t!(lang, "lang.unknown", languages = available))
+            .await?;
+        return Ok(());
+    };
+
+    if !msg.chat.is_private() {
+        let is_admin = match &msg.from {
+            Some(user) => admins.is_admin(&user.id.to_string()).await?,
+            None => false,
+        };
+        if !is_admin {
+            bot.send_message(msg.chat.id, t!(lang, "lang.admin_only"))
+                .await?;
+            return Ok(());
+        }
+    }
+
+    // The id of a private chat is the one of the user
+    languages
+        .set_language(&msg.chat.id.to_string(), new_lang.code())
+        .await?;
+
+    bot.send_message(
+        msg.chat.id,
+        if msg.chat.is_private() {
+            t!(new_lang, "lang.user_set")
+        } else {
+            t!(new_lang, "lang.chat_set")
+        },
+    )
+    .await?;
+
+    Ok(())
+}
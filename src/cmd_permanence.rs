@@ -0,0 +1,444 @@
+use std::{sync::Arc, time::Duration};
+
+use chrono::{DateTime, Datelike, TimeDelta, TimeZone, Utc};
+use chrono_tz::Tz;
+use teloxide::{
+    payloads::{AnswerCallbackQuerySetters, EditMessageTextSetters, SendMessageSetters},
+    requests::Requester,
+    types::{CallbackQuery, ChatId, InlineKeyboardButton, InlineKeyboardMarkup, Message, UserId},
+    Bot,
+};
+
+use crate::{
+    config::config,
+    i18n::{chat_language, Lang},
+    storage::{LanguageRepository, PermanenceRepository, PermanenceSlot},
+    t, HandlerResult,
+};
+
+/// Prefix of the callback data of the sign up buttons, followed by the id of the slot.
+pub const PERMANENCE_CALLBACK_PREFIX: &str = "permanence:";
+/// Delay between two checks of the upcoming slots.
+const REMINDER_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+/// Longest duration (in minutes) of a slot, so that it ends before its next occurrence.
+const MAX_SLOT_DURATION: i64 = 24 * 60;
+
+impl PermanenceSlot {
+    fn label(&self, lang: Lang) -> String {
+        slot_label(lang, self.weekday, self.start_minute, self.duration)
+    }
+}
+
+/// Displays the weekly slots of the chat, with a button to (un)register to each of them.
+pub async fn permanences(
+    bot: Bot,
+    msg: Message,
+    lang: Lang,
+    permanences: Arc<dyn PermanenceRepository>,
+) -> HandlerResult {
+    let slots = permanences
+        .list_slots(Some(&msg.chat.id.to_string()))
+        .await?;
+
+    if slots.is_empty() {
+        bot.send_message(msg.chat.id, t!(lang, "permanence.none"))
+            .await?;
+        return Ok(());
+    }
+
+    bot.send_message(msg.chat.id, planning_text(lang, &slots))
+        .reply_markup(planning_keyboard(lang, &slots))
+        .await?;
+
+    Ok(())
+}
+
+/// Toggles the registration of the user to the slot, and updates the planning message.
+pub async fn toggle_permanence(
+    bot: Bot,
+    callback_query: CallbackQuery,
+    slot_id: i64,
+    lang: Lang,
+    permanences: Arc<dyn PermanenceRepository>,
+) -> HandlerResult {
+    let Some(message) = callback_query.message.as_ref() else {
+        return Ok(());
+    };
+    let chat_id = message.chat().id;
+    let chat_id_str = chat_id.to_string();
+
+    let Some(registered) = permanences
+        .toggle_signup(
+            &chat_id_str,
+            slot_id,
+            &callback_query.from.id.to_string(),
+            &callback_query.from.full_name(),
+        )
+        .await?
+    else {
+        bot.answer_callback_query(callback_query.id)
+            .text(t!(lang, "permanence.deleted_slot"))
+            .await?;
+        return Ok(());
+    };
+
+    bot.answer_callback_query(callback_query.id)
+        .text(if registered {
+            t!(lang, "permanence.signed_up")
+        } else {
+            t!(lang, "permanence.withdrawn")
+        })
+        .await?;
+
+    let slots = permanences.list_slots(Some(&chat_id_str)).await?;
+    bot.edit_message_text(chat_id, message.id(), planning_text(lang, &slots))
+        .reply_markup(planning_keyboard(lang, &slots))
+        .await?;
+
+    Ok(())
+}
+
+/// Creates a weekly slot in the chat: `/permanenceadd <jour> <HH:MM> <durée en minutes>`
+pub async fn permanence_add(
+    bot: Bot,
+    msg: Message,
+    args: String,
+    lang: Lang,
+    permanences: Arc<dyn PermanenceRepository>,
+) -> HandlerResult {
+    let Some((weekday, start_minute, duration)) = parse_slot(&args) else {
+        bot.send_message(msg.chat.id, t!(lang, "permanence.add_usage"))
+            .await?;
+        return Ok(());
+    };
+
+    permanences
+        .add_slot(&msg.chat.id.to_string(), weekday, start_minute, duration)
+        .await?;
+
+    bot.send_message(
+        msg.chat.id,
+        t!(
+            lang,
+            "permanence.added",
+            slot = slot_label(lang, weekday, start_minute, duration)
+        ),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Deletes a slot of the chat from its id, as displayed by /permanences.
+pub async fn permanence_remove(
+    bot: Bot,
+    msg: Message,
+    id: String,
+    lang: Lang,
+    permanences: Arc<dyn PermanenceRepository>,
+) -> HandlerResult {
+    let Ok(id) = id.trim().parse::<i64>() else {
+        bot.send_message(msg.chat.id, t!(lang, "permanence.remove_usage"))
+            .await?;
+        return Ok(());
+    };
+
+    let deleted = permanences
+        .remove_slot(&msg.chat.id.to_string(), id)
+        .await?;
+
+    bot.send_message(
+        msg.chat.id,
+        if deleted {
+            t!(lang, "permanence.removed", id = id)
+        } else {
+            t!(lang, "permanence.unknown", id = id)
+        },
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Background task reminding the assignees shortly before their slot starts, and reporting
+/// uncovered slots in the chat they belong to.
+pub async fn permanence_reminders(
+    bot: Bot,
+    permanences: Arc<dyn PermanenceRepository>,
+    languages: Arc<dyn LanguageRepository>,
+) {
+    let mut interval = tokio::time::interval(REMINDER_CHECK_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        if let Err(e) = send_reminders(&bot, permanences.as_ref(), languages.as_ref()).await {
+            log::error!("Could not send permanence reminders: {e:#?}");
+        }
+    }
+}
+
+async fn send_reminders(
+    bot: &Bot,
+    permanences: &dyn PermanenceRepository,
+    languages: &dyn LanguageRepository,
+) -> HandlerResult {
+    let now = Utc::now().with_timezone(&config().timezone);
+    let reminder_delay = TimeDelta::minutes(config().permanence_reminder);
+
+    let slots = permanences.list_slots(None).await?;
+
+    for slot in slots {
+        let Some(start) = next_occurrence(slot.weekday, slot.start_minute, now) else {
+            continue;
+        };
+
+        if start - now > reminder_delay || slot.last_reminder == Some(start.timestamp()) {
+            continue;
+        }
+
+        permanences
+            .set_last_reminder(slot.id, start.timestamp())
+            .await?;
+
+        let Ok(chat_id) = slot.chat_id.parse::<i64>().map(ChatId) else {
+            continue;
+        };
+        let chat_lang = chat_language(languages, chat_id).await;
+
+        if slot.assignees.is_empty() {
+            if let Err(e) = bot
+                .send_message(
+                    chat_id,
+                    t!(
+                        chat_lang,
+                        "permanence.uncovered",
+                        slot = slot.label(chat_lang)
+                    ),
+                )
+                .await
+            {
+                log::error!(
+                    "Could not remind slot {} in chat {chat_id}: {e:#?}",
+                    slot.id
+                );
+            }
+            continue;
+        }
+
+        for assignee in &slot.assignees {
+            let sent = match assignee.telegram_id.parse::<u64>() {
+                Ok(id) => {
+                    let user = UserId(id);
+                    let lang = chat_language(languages, ChatId::from(user)).await;
+                    let text = t!(lang, "permanence.reminder", slot = slot.label(lang));
+                    bot.send_message(user, text).await.is_ok()
+                }
+                Err(_) => false,
+            };
+
+            // The user may not have started a private conversation with the bot
+            if !sent {
+                if let Err(e) = bot
+                    .send_message(
+                        chat_id,
+                        t!(
+                            chat_lang,
+                            "permanence.reminder_in_chat",
+                            name = assignee.name,
+                            slot = slot.label(chat_lang)
+                        ),
+                    )
+                    .await
+                {
+                    // The bot may have been removed from the chat
+                    log::error!(
+                        "Could not remind slot {} in chat {chat_id}: {e:#?}",
+                        slot.id
+                    );
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Formats a slot as `<jour> HH:MM-HH:MM`.
+fn slot_label(lang: Lang, weekday: i64, start_minute: i64, duration: i64) -> String {
+    let end_minute = start_minute + duration;
+    format!(
+        "{} {:02}:{:02}-{:02}:{:02}",
+        lang.weekday(weekday as usize),
+        start_minute / 60,
+        start_minute % 60,
+        (end_minute / 60) % 24,
+        end_minute % 60
+    )
+}
+
+fn planning_text(lang: Lang, slots: &[PermanenceSlot]) -> String {
+    t!(
+        lang,
+        "permanence.planning",
+        slots = slots
+            .iter()
+            .map(|s| format!(
+                " - #{} {}: {}",
+                s.id,
+                s.label(lang),
+                if s.assignees.is_empty() {
+                    t!(lang, "permanence.nobody")
+                } else {
+                    s.assignees
+                        .iter()
+                        .map(|a| a.name.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                }
+            ))
+            .collect::<Vec<_>>()
+            .join("\n")
+    )
+}
+
+fn planning_keyboard(lang: Lang, slots: &[PermanenceSlot]) -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new(slots.iter().map(|s| {
+        vec![InlineKeyboardButton::callback(
+            s.label(lang),
+            format!("{}{}", PERMANENCE_CALLBACK_PREFIX, s.id),
+        )]
+    }))
+}
+
+/// Parses `<jour> <HH:MM> <durée en minutes>` into (weekday, start minute, duration). The day
+/// can be given in any of the languages of the bot, or as its number starting at 1 for Monday.
+fn parse_slot(args: &str) -> Option<(i64, i64, i64)> {
+    let mut args = args.split_whitespace();
+
+    let weekday = args.next()?.to_lowercase();
+    let weekday = Lang::ALL
+        .into_iter()
+        .find_map(|lang| (0..7).find(|&d| lang.weekday(d).to_lowercase() == weekday))
+        .or_else(|| {
+            weekday
+                .parse::<usize>()
+                .ok()
+                .filter(|d| (1..=7).contains(d))
+                .map(|d| d - 1)
+        })?;
+
+    let (hours, minutes) = args.next()?.split_once([':', 'h'])?;
+    let (hours, minutes) = (hours.parse::<i64>().ok()?, minutes.parse::<i64>().ok()?);
+    if !(0..24).contains(&hours) || !(0..60).contains(&minutes) {
+        return None;
+    }
+
+    let duration = args
+        .next()?
+        .parse::<i64>()
+        .ok()
+        .filter(|d| (1..=MAX_SLOT_DURATION).contains(d))?;
+
+    if args.next().is_some() {
+        return None;
+    }
+
+    Some((weekday as i64, hours * 60 + minutes, duration))
+}
+
+/// Computes the next start of a weekly slot, or the current one if it has not started yet.
+fn next_occurrence(weekday: i64, start_minute: i64, now: DateTime<Tz>) -> Option<DateTime<Tz>> {
+    let today = now.date_naive();
+    let days_ahead = (weekday - today.weekday().num_days_from_monday() as i64).rem_euclid(7);
+
+    [days_ahead, days_ahead + 7].into_iter().find_map(|offset| {
+        let start = (today + TimeDelta::days(offset)).and_hms_opt(
+            (start_minute / 60) as u32,
+            (start_minute % 60) as u32,
+            0,
+        )?;
+        now.timezone()
+            .from_local_datetime(&start)
+            .earliest()
+            .filter(|start| *start >= now)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(tz: Tz, date: (i32, u32, u32), time: (u32, u32)) -> DateTime<Tz> {
+        tz.with_ymd_and_hms(date.0, date.1, date.2, time.0, time.1, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn parses_slots() {
+        assert_eq!(parse_slot("lundi 12:15 60"), Some((0, 12 * 60 + 15, 60)));
+        assert_eq!(
+            parse_slot("Sunday 23h30 1440"),
+            Some((6, 23 * 60 + 30, 24 * 60))
+        );
+        assert_eq!(parse_slot("3 8:00 45"), Some((2, 8 * 60, 45)));
+
+        for invalid in [
+            "",
+            "monday 12:15",
+            "monday 24:00 60",
+            "monday 12:60 60",
+            "monday 12:15 0",
+            "monday 12:15 1441",
+            "monday 12:15 9223372036854775807",
+            "8 12:15 60",
+            "monday 12:15 60 more",
+        ] {
+            assert_eq!(parse_slot(invalid), None, "{invalid:?}");
+        }
+    }
+
+    #[test]
+    fn finds_the_next_occurrence() {
+        let tz = chrono_tz::Europe::Zurich;
+        // A Monday
+        let now = at(tz, (2026, 10, 19), (10, 0));
+
+        assert_eq!(
+            next_occurrence(2, 18 * 60 + 30, now),
+            Some(at(tz, (2026, 10, 21), (18, 30)))
+        );
+        // Has not started yet
+        assert_eq!(next_occurrence(0, 10 * 60, now), Some(now));
+        assert_eq!(
+            next_occurrence(0, 10 * 60 + 1, now),
+            Some(at(tz, (2026, 10, 19), (10, 1)))
+        );
+        // Already started today
+        assert_eq!(
+            next_occurrence(0, 9 * 60, now),
+            Some(at(tz, (2026, 10, 26), (9, 0)))
+        );
+        // A Sunday, across the end of the month
+        assert_eq!(
+            next_occurrence(6, 0, now),
+            Some(at(tz, (2026, 10, 25), (0, 0)))
+        );
+    }
+
+    #[test]
+    fn skips_the_occurrences_which_do_not_exist() {
+        let tz = chrono_tz::Europe::Zurich;
+        // The clocks go from 2:00 to 3:00 on Sunday 29 March 2026
+        let now = at(tz, (2026, 3, 28), (12, 0));
+
+        assert_eq!(
+            next_occurrence(6, 2 * 60 + 30, now),
+            Some(at(tz, (2026, 4, 5), (2, 30)))
+        );
+        assert_eq!(
+            next_occurrence(6, 3 * 60, now),
+            Some(at(tz, (2026, 3, 29), (3, 0)))
+        );
+    }
+}
@@ -0,0 +1,243 @@
+//! Rules allowing or denying users to use the restricted commands, in a chat or in every chat,
+//! which take precedence over the authorizations of the chats.
+
+use std::sync::Arc;
+
+use teloxide::{
+    requests::Requester,
+    types::{ChatId, Message, UserId},
+    Bot,
+};
+
+use crate::{
+    cmd_chats::{chat_name, known_chat_name},
+    cmd_help::update_chat_menu,
+    commands::{expand_restricted_commands, restricted_command_names, Command},
+    i18n::Lang,
+    storage::{
+        AdminRepository, AuthorizationRepository, ChatRepository, Permission, PermissionRepository,
+    },
+    t, HandlerResult,
+};
+
+/// User and chat targeted by /grant, /deny or /revoke, and the names of the commands.
+struct RuleTarget {
+    user: UserId,
+    /// Name of the user if known, else their id
+    user_name: String,
+    /// `None` for a rule applying in every chat
+    chat: Option<ChatId>,
+    names: Vec<String>,
+}
+
+/// Parses `<user id> [<chat id>] <command>...`, or `<command>...` in reply to a message of the
+/// user in a group, in which case the rule applies in the group.
+fn rule_target(msg: &Message, args: &str) -> Option<RuleTarget> {
+    let mut args = args
+        .split_whitespace()
+        .map(str::to_owned)
+        .collect::<Vec<_>>();
+
+    let target = match args.first().and_then(|arg| arg.parse::<u64>().ok()) {
+        Some(user) => {
+            args.remove(0);
+            let chat = args.first().and_then(|arg| arg.parse::<i64>().ok());
+            if chat.is_some() {
+                args.remove(0);
+            }
+            RuleTarget {
+                user: UserId(user),
+                user_name: user.to_string(),
+                chat: chat.map(ChatId),
+                names: args,
+            }
+        }
+        None => {
+            let user = msg
+                .reply_to_message()
+                .filter(|_| !msg.chat.is_private())
+                .and_then(|reply| reply.from.as_ref())
+                .filter(|user| !user.is_bot)?;
+            RuleTarget {
+                user: user.id,
+                user_name: format!("{} ({})", user.full_name(), user.id),
+                chat: Some(msg.chat.id),
+                names: args,
+            }
+        }
+    };
+
+    (!target.names.is_empty()).then_some(target)
+}
+
+/// Handles /grant, which allows the user to use the commands whatever the authorizations of the
+/// chat, /deny, which denies them to the user, and /revoke, which removes the rules of the user so
+/// that the authorizations of the chat apply again.
+pub async fn set_rules(
+    bot: Bot,
+    msg: Message,
+    command: Command,
+    lang: Lang,
+    admins: Arc<dyn AdminRepository>,
+    authorizations: Arc<dyn AuthorizationRepository>,
+    permissions: Arc<dyn PermissionRepository>,
+) -> HandlerResult {
+    let (args, allowed) = match &command {
+        Command::Grant(args) => (args, Some(true)),
+        Command::Deny(args) => (args, Some(false)),
+        Command::Revoke(args) => (args, None),
+        _ => return Ok(()),
+    };
+    let Some(target) = rule_target(&msg, args) else {
+        bot.send_message(
+            msg.chat.id,
+            t!(lang, &format!("usage.{}", command.shortand())),
+        )
+        .await?;
+        return Ok(());
+    };
+    let commands = match expand_restricted_commands(&target.names) {
+        Ok(commands) => commands,
+        Err(unknown) => {
+            bot.send_message(
+                msg.chat.id,
+                t!(
+                    lang,
+                    "auth.unknown_commands",
+                    commands = unknown.join(", "),
+                    available = restricted_command_names().join(", ")
+                ),
+            )
+            .await?;
+            return Ok(());
+        }
+    };
+
+    let scope = match target.chat {
+        None => t!(lang, "permissions.everywhere"),
+        Some(chat) if chat == msg.chat.id => {
+            t!(lang, "permissions.in_chat", chat = chat_name(&msg.chat))
+        }
+        // Also checks that the bot is in the chat
+        Some(chat) => match bot.get_chat(chat).await {
+            Ok(chat) => t!(lang, "permissions.in_chat", chat = chat_name(&chat)),
+            Err(e) => {
+                log::warn!("Could not get the chat {chat}: {e}");
+                bot.send_message(msg.chat.id, t!(lang, "auth.unknown_chat", chat = chat))
+                    .await?;
+                return Ok(());
+            }
+        },
+    };
+
+    let user_id = target.user.to_string();
+    let chat_id = target.chat.map(|c| c.to_string()).unwrap_or_default();
+    let mut changed = Vec::new();
+    for command in &commands {
+        let change = match allowed {
+            Some(allowed) => {
+                permissions
+                    .set_permission(&Permission {
+                        user_id: user_id.clone(),
+                        chat_id: chat_id.clone(),
+                        command: command.clone(),
+                        allowed,
+                    })
+                    .await?;
+                true
+            }
+            None => {
+                permissions
+                    .remove_permission(&user_id, &chat_id, command)
+                    .await?
+            }
+        };
+        if change {
+            changed.push(format!("/{command}"));
+        }
+    }
+
+    let key = match allowed {
+        Some(true) => "permissions.granted",
+        Some(false) => "permissions.denied",
+        None if changed.is_empty() => "permissions.no_rules",
+        None => "permissions.revoked",
+    };
+    let commands = if changed.is_empty() {
+        commands
+            .iter()
+            .map(|c| format!("/{c}"))
+            .collect::<Vec<_>>()
+            .join(", ")
+    } else {
+        changed.join(", ")
+    };
+    bot.send_message(
+        msg.chat.id,
+        t!(
+            lang,
+            key,
+            user = target.user_name,
+            commands = commands,
+            scope = scope
+        ),
+    )
+    .await?;
+
+    // The rules of the user apply to their private chat, whose menu may change
+    update_chat_menu(
+        &bot,
+        ChatId(target.user.0 as i64),
+        admins.as_ref(),
+        authorizations.as_ref(),
+        permissions.as_ref(),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Lists the rules of all the users, or of the given user.
+pub async fn list_permissions(
+    bot: Bot,
+    msg: Message,
+    user: String,
+    lang: Lang,
+    permissions: Arc<dyn PermissionRepository>,
+    chats: Arc<dyn ChatRepository>,
+) -> HandlerResult {
+    let user = user.trim();
+    let rules = if user.is_empty() {
+        permissions.list_permissions().await?
+    } else {
+        permissions.list_user_permissions(user).await?
+    };
+    if rules.is_empty() {
+        bot.send_message(msg.chat.id, t!(lang, "permissions.none"))
+            .await?;
+        return Ok(());
+    }
+
+    let mut lines = vec![t!(lang, "permissions.header")];
+    for rule in rules {
+        let scope = match rule.chat_id.parse::<i64>() {
+            Ok(chat) => t!(
+                lang,
+                "permissions.in_chat",
+                chat = known_chat_name(chats.as_ref(), ChatId(chat)).await?
+            ),
+            Err(_) => t!(lang, "permissions.everywhere"),
+        };
+        lines.push(t!(
+            lang,
+            "permissions.rule",
+            user = rule.user_id,
+            state = if rule.allowed { "✅" } else { "🚫" },
+            command = rule.command,
+            scope = scope
+        ));
+    }
+    bot.send_message(msg.chat.id, lines.join("\n")).await?;
+
+    Ok(())
+}
@@ -1,6 +1,6 @@
 const POLL_MAX_OPTIONS_COUNT: u8 = 10; // max poll options
 
-use crate::directus::{get_committee, update_committee, Committee};
+use crate::directus::{get_committee, increment_poll_count};
 use log::error;
 use rand::{seq::SliceRandom, thread_rng, Rng};
 use teloxide::{
@@ -14,7 +14,7 @@ use teloxide::{
     Bot,
 };
 
-use crate::HandlerResult;
+use crate::{i18n::Lang, t, HandlerResult};
 
 #[derive(Default, Clone, Debug)]
 pub enum PollState {
@@ -35,7 +35,12 @@ pub enum PollState {
 pub type PollDialogue = Dialogue<PollState, InMemStorage<PollState>>;
 
 /// Starts the /poll dialogue by sending a message with an inline keyboard to select the target of the /poll.
-pub async fn start_poll_dialogue(bot: Bot, msg: Message, dialogue: PollDialogue) -> HandlerResult {
+pub async fn start_poll_dialogue(
+    bot: Bot,
+    msg: Message,
+    dialogue: PollDialogue,
+    lang: Lang,
+) -> HandlerResult {
     log::info!("Starting /poll dialogue");
 
     log::debug!("Removing /poll message");
@@ -45,13 +50,14 @@ pub async fn start_poll_dialogue(bot: Bot, msg: Message, dialogue: PollDialogue)
         Ok(v) => v,
         Err(e) => {
             error!("Could not fetch committee: {e:#?}");
+            bot.send_message(msg.chat.id, e.user_message(lang)).await?;
             return Ok(());
         }
     };
 
     log::debug!("Sending message with inline keyboard for callback");
     let msg = bot
-        .send_message(msg.chat.id, "Qui l'a dit ?")
+        .send_message(msg.chat.id, t!(lang, "poll.who_said"))
         .reply_markup(ReplyMarkup::InlineKeyboard(InlineKeyboardMarkup::new(
             committee
                 .into_iter()
@@ -89,13 +95,14 @@ pub async fn choose_target(
     callback_query: CallbackQuery,
     dialogue: PollDialogue,
     message_id: MessageId,
+    lang: Lang,
 ) -> HandlerResult {
     if let Some(id) = callback_query.chat_id() {
         log::debug!("Removing target query message");
         bot.delete_message(dialogue.chat_id(), message_id).await?;
 
         log::debug!("Sending quote query message");
-        let msg = bot.send_message(id, "Qu'a-t'il/elle dit ?").await?;
+        let msg = bot.send_message(id, t!(lang, "poll.what_said")).await?;
 
         log::debug!("Updating dialogue to SetQuote");
         dialogue
@@ -116,6 +123,7 @@ pub async fn set_quote(
     msg: Message,
     dialogue: PollDialogue,
     (message_id, target): (MessageId, String),
+    lang: Lang,
 ) -> HandlerResult {
     if let Some(text) = msg.text() {
         log::debug!("Removing quote query message");
@@ -127,6 +135,9 @@ pub async fn set_quote(
             Ok(v) => v,
             Err(e) => {
                 error!("Could not fetch committee: {e:#?}");
+                bot.send_message(dialogue.chat_id(), e.user_message(lang))
+                    .await?;
+                dialogue.update(PollState::Start).await?;
                 return Ok(());
             }
         };
@@ -147,7 +158,7 @@ pub async fn set_quote(
         log::debug!("Sending poll");
         bot.send_poll(
             dialogue.chat_id(),
-            format!(r#"Qui a dit: "{}" ?"#, text),
+            t!(lang, "poll.question", quote = text),
             poll,
         )
         .type_(teloxide::types::PollType::Quiz)
@@ -155,22 +166,22 @@ pub async fn set_quote(
         .correct_option_id(index)
         .await?;
 
-        update_committee(
-            committee
-                .into_iter()
-                .map(|c| {
-                    if c.name == target {
-                        Committee {
-                            poll_count: c.poll_count + 1,
-                            ..c
-                        }
-                    } else {
-                        c
-                    }
-                })
-                .collect(),
-        )
-        .await;
+        let Some(member) = committee.iter().find(|c| c.name == target) else {
+            dialogue.update(PollState::Start).await?;
+            return Ok(());
+        };
+        if let Err(e) = increment_poll_count(member.id).await {
+            bot.send_message(
+                dialogue.chat_id(),
+                t!(
+                    lang,
+                    "poll.stats_not_updated",
+                    name = target,
+                    error = e.user_message(lang)
+                ),
+            )
+            .await?;
+        }
 
         log::debug!("Resetting dialogue status");
         dialogue.update(PollState::Start).await?;
@@ -179,11 +190,12 @@ pub async fn set_quote(
     Ok(())
 }
 
-pub async fn stats(bot: Bot, msg: Message) -> HandlerResult {
+pub async fn stats(bot: Bot, msg: Message, lang: Lang) -> HandlerResult {
     let mut committee = match get_committee().await {
         Ok(v) => v,
         Err(e) => {
             error!("Could not fetch committee: {e:#?}");
+            bot.send_message(msg.chat.id, e.user_message(lang)).await?;
             return Ok(());
         }
     };
@@ -195,7 +207,7 @@ pub async fn stats(bot: Bot, msg: Message) -> HandlerResult {
         committee
             .into_iter()
             .rev()
-            .map(|c| format!("- {} (polls: {})", c.name, c.poll_count))
+            .map(|c| t!(lang, "poll.stats_line", name = c.name, count = c.poll_count))
             .collect::<Vec<_>>()
             .join("\n"),
     )
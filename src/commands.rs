@@ -1,110 +1,413 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
-use sqlx::SqlitePool;
 use teloxide::{
-    dispatching::DpHandlerDescription, prelude::*, types::Message, utils::command::BotCommands, Bot,
+    dispatching::DpHandlerDescription, prelude::*, types::Message, utils::command::BotCommands,
 };
 
 use crate::{
+    cmd_admins::{
+        admin_add, admin_callback, admin_list, admin_remove, AdminCallback, ADMIN_CALLBACK_PREFIX,
+    },
     cmd_authentication::{
-        admin_list, admin_remove, authenticate, authorizations, authorize, unauthorize,
+        authenticate, authorizations, authorize, sent_by_group_manager, unauthorize,
     },
+    cmd_backup::backup,
     cmd_bureau::bureau,
+    cmd_chats::{
+        authorization_callback, chats, forwarded_message, remember_chat, AuthorizationCallback,
+        AUTHORIZATION_CALLBACK_PREFIX,
+    },
+    cmd_events::{
+        event_details, event_subscribe, event_unsubscribe, events, EVENT_CALLBACK_PREFIX,
+    },
+    cmd_help::help,
+    cmd_language::language,
+    cmd_permanence::{
+        permanence_add, permanence_remove, permanences, toggle_permanence,
+        PERMANENCE_CALLBACK_PREFIX,
+    },
+    cmd_permissions::{list_permissions, set_rules},
     cmd_poll::{choose_target, set_quote, start_poll_dialogue, stats, PollState},
+    config::config,
+    i18n::Lang,
+    rejection::{reject, Rejection},
+    storage::{
+        AdminRepository, AuthorizationRepository, Error, LanguageRepository, PermissionRepository,
+    },
+    throttle::Throttle,
     HandlerResult,
 };
 
 pub fn command_message_handler(
 ) -> Endpoint<'static, DependencyMap, HandlerResult, DpHandlerDescription> {
     dptree::entry()
+        .inspect_async(remember_chat)
         .branch(
             dptree::entry()
                 .filter_command::<Command>()
-                .branch(dptree::case![Command::Help].endpoint(help))
+                .branch(dptree::case![Command::Help(topic)].endpoint(help))
                 .branch(dptree::case![Command::Authenticate(token, name)].endpoint(authenticate))
+                .branch(dptree::case![Command::Language(code)].endpoint(language))
                 .branch(
-                    require_authorization()
-                        .branch(dptree::case![Command::Bureau].endpoint(bureau))
-                        .branch(dptree::case![Command::Poll].endpoint(start_poll_dialogue))
-                        .branch(dptree::case![Command::Stats].endpoint(stats)),
+                    require_authorization().chain(
+                        require_rate_limit()
+                            .branch(dptree::case![Command::Bureau(args)].endpoint(bureau))
+                            .branch(dptree::case![Command::Poll].endpoint(start_poll_dialogue))
+                            .branch(dptree::case![Command::Stats].endpoint(stats))
+                            .branch(dptree::case![Command::Permanences].endpoint(permanences))
+                            .branch(dptree::case![Command::Events].endpoint(events)),
+                    ),
                 )
                 .branch(
                     require_admin().chain(
                         dptree::entry()
                             .branch(dptree::case![Command::AdminList].endpoint(admin_list))
+                            .branch(dptree::case![Command::AdminAdd(args)].endpoint(admin_add))
                             .branch(
-                                dptree::case![Command::AdminRemove(name)].endpoint(admin_remove),
+                                dptree::case![Command::AdminRemove(args)].endpoint(admin_remove),
                             )
                             .branch(dptree::case![Command::Authorize(command)].endpoint(authorize))
                             .branch(
                                 dptree::case![Command::Unauthorize(command)].endpoint(unauthorize),
                             )
                             .branch(
-                                dptree::case![Command::Authorizations].endpoint(authorizations),
-                            ),
+                                dptree::case![Command::Authorizations(args)]
+                                    .endpoint(authorizations),
+                            )
+                            .branch(dptree::case![Command::Chats].endpoint(chats))
+                            .branch(
+                                dptree::filter(|command: Command| {
+                                    matches!(
+                                        command,
+                                        Command::Grant(..)
+                                            | Command::Deny(..)
+                                            | Command::Revoke(..)
+                                    )
+                                })
+                                .endpoint(set_rules),
+                            )
+                            .branch(
+                                dptree::case![Command::Permissions(user)]
+                                    .endpoint(list_permissions),
+                            )
+                            .branch(
+                                dptree::case![Command::PermanenceAdd(args)]
+                                    .endpoint(permanence_add),
+                            )
+                            .branch(
+                                dptree::case![Command::PermanenceRemove(id)]
+                                    .endpoint(permanence_remove),
+                            )
+                            .branch(
+                                dptree::case![Command::EventSubscribe].endpoint(event_subscribe),
+                            )
+                            .branch(
+                                dptree::case![Command::EventUnsubscribe]
+                                    .endpoint(event_unsubscribe),
+                            )
+                            .branch(dptree::case![Command::Backup].endpoint(backup)),
                     ),
                 ),
         )
         .branch(dptree::case![PollState::SetQuote { message_id, target }].endpoint(set_quote))
+        .branch(
+            dptree::filter(|msg: Message| msg.chat.is_private() && msg.forward_origin().is_some())
+                .endpoint(forwarded_message),
+        )
 }
 
 pub fn command_callback_query_handler(
 ) -> Endpoint<'static, DependencyMap, HandlerResult, DpHandlerDescription> {
-    dptree::case![PollState::ChooseTarget { message_id }].endpoint(choose_target)
+    dptree::entry()
+        .branch(
+            dptree::filter_map(|callback_query: CallbackQuery| {
+                callback_query
+                    .data?
+                    .strip_prefix(PERMANENCE_CALLBACK_PREFIX)?
+                    .parse::<i64>()
+                    .ok()
+            })
+            .endpoint(toggle_permanence),
+        )
+        .branch(
+            dptree::filter_map(|callback_query: CallbackQuery| {
+                callback_query
+                    .data?
+                    .strip_prefix(EVENT_CALLBACK_PREFIX)?
+                    .parse::<i32>()
+                    .ok()
+            })
+            .endpoint(event_details),
+        )
+        .branch(
+            dptree::filter_map(|callback_query: CallbackQuery| {
+                callback_query
+                    .data?
+                    .strip_prefix(AUTHORIZATION_CALLBACK_PREFIX)?
+                    .parse::<AuthorizationCallback>()
+                    .ok()
+            })
+            .endpoint(authorization_callback),
+        )
+        .branch(
+            dptree::filter_map(|callback_query: CallbackQuery| {
+                callback_query
+                    .data?
+                    .strip_prefix(ADMIN_CALLBACK_PREFIX)?
+                    .parse::<AdminCallback>()
+                    .ok()
+            })
+            .endpoint(admin_callback),
+        )
+        .branch(dptree::case![PollState::ChooseTarget { message_id }].endpoint(choose_target))
 }
 
 // ----------------------------- ACCESS CONTROL -------------------------------
 
-/// Check that the chat from which a command originated as the authorization to use it
+/// Check that the user who sent the command can use it in the chat (see [`resolve_permission`]),
+/// and explain the rejection to the user otherwise. The commands which do not need an
+/// authorization are left to the other branches.
 ///
-/// Required dependencies: `teloxide_core::types::message::Message`, `roboclic_v2::commands::Command`
+/// Required dependencies: `teloxide_core::types::message::Message`, `roboclic_v2::commands::Command`,
+/// `roboclic_v2::storage::AuthorizationRepository`, `roboclic_v2::storage::PermissionRepository`,
+/// `roboclic_v2::storage::AdminRepository`, `roboclic_v2::storage::LanguageRepository`
 fn require_authorization() -> Endpoint<'static, DependencyMap, HandlerResult, DpHandlerDescription>
 {
     dptree::entry().filter_async(
-        |command: Command, msg: Message, pool: Arc<SqlitePool>| async move {
-            let chat_id = msg.chat.id.to_string();
-            let shortand = command.shortand();
-            let authorized =  match sqlx::query!(
-                r#"SELECT COUNT(*) AS count FROM authorizations WHERE chat_id = $1 AND command = $2"#,
-                chat_id,
-                shortand
+        |bot: Bot,
+         command: Command,
+         msg: Message,
+         lang: Lang,
+         authorizations: Arc<dyn AuthorizationRepository>,
+         permissions: Arc<dyn PermissionRepository>,
+         admins: Arc<dyn AdminRepository>,
+         languages: Arc<dyn LanguageRepository>| async move {
+            if command.access() != Access::AuthorizedChats {
+                return false;
+            }
+
+            let decision = match resolve_permission(
+                msg.chat.id,
+                msg.from.as_ref().map(|u| u.id),
+                command.shortand(),
+                authorizations.as_ref(),
+                permissions.as_ref(),
             )
-            .fetch_one(pool.as_ref())
-            .await {
-                Ok(result) => result.count > 0,
+            .await
+            {
+                Ok(decision) => decision,
                 Err(e) => {
-                    log::error!("Could not check authorization in database: {:?}", e);
-                    false
-                },
+                    log::error!("Could not check authorization: {:?}", e);
+                    Decision::NotAuthorized
+                }
+            };
+
+            let rejection = match decision {
+                Decision::Allowed => return true,
+                Decision::Denied => Rejection::UserDenied,
+                Decision::NotAuthorized => Rejection::ChatNotAuthorized,
+            };
+            log::warn!(
+                "Unauthorized Chat {} (User {:?}) tried to use the commmand {}",
+                msg.chat.id,
+                msg.from.as_ref().map(|u| u.id),
+                command.shortand()
+            );
+            reject(
+                &bot,
+                &msg,
+                &command,
+                rejection,
+                lang,
+                admins.as_ref(),
+                languages.as_ref(),
+            )
+            .await;
+
+            false
+        },
+    )
+}
+
+/// Last uses of the rate limited commands, per chat and per user.
+static RATE_LIMITS: Throttle = Throttle::new();
+
+/// Check that the command was not used too recently in the chat or by the user, according to the
+/// `[rate_limits]` of the config file, and ask the user to wait otherwise. The admins are not
+/// limited.
+///
+/// Required dependencies: `teloxide_core::types::message::Message`, `roboclic_v2::commands::Command`,
+/// `roboclic_v2::storage::AdminRepository`, `roboclic_v2::storage::LanguageRepository`
+fn require_rate_limit() -> Endpoint<'static, DependencyMap, HandlerResult, DpHandlerDescription> {
+    dptree::entry().filter_async(
+        |bot: Bot,
+         command: Command,
+         msg: Message,
+         lang: Lang,
+         admins: Arc<dyn AdminRepository>,
+         languages: Arc<dyn LanguageRepository>| async move {
+            let Some(limit) = config()
+                .rate_limits
+                .get(command.shortand())
+                .filter(|_| command.rate_limited())
+            else {
+                return true;
             };
+            let user = msg.from.as_ref().map(|u| u.id);
+            if let Some(user) = user {
+                match admins.is_admin(&user.to_string()).await {
+                    Ok(true) => return true,
+                    Ok(false) => {}
+                    Err(e) => log::error!("Could not check admin status: {:?}", e),
+                }
+            }
 
-            if !authorized {
-                log::warn!("Unauthorized Chat {} tried to use the commmand {}", msg.chat.id, command.shortand())
+            // The private chat of a user has the same id as the user, hence the prefixes
+            let mut keys = vec![(
+                format!("chat {} {}", msg.chat.id, command.shortand()),
+                limit.chat,
+            )];
+            if let Some(user) = user {
+                keys.push((format!("user {user} {}", command.shortand()), limit.user));
             }
+            keys.retain(|(_, interval)| *interval > 0);
 
-            authorized
+            if let Some(wait) = keys
+                .iter()
+                .filter_map(|(key, _)| RATE_LIMITS.remaining(key))
+                .max()
+            {
+                log::info!(
+                    "Chat {} (User {:?}) used the command {} too often",
+                    msg.chat.id,
+                    user,
+                    command.shortand()
+                );
+                reject(
+                    &bot,
+                    &msg,
+                    &command,
+                    Rejection::RateLimited { wait },
+                    lang,
+                    admins.as_ref(),
+                    languages.as_ref(),
+                )
+                .await;
+                return false;
+            }
+
+            for (key, interval) in &keys {
+                RATE_LIMITS.allow(key, Duration::from_secs(*interval));
+            }
+            true
         },
     )
 }
 
-/// Check that the chat is admin
+/// Whether the command manages the authorizations of the chat it is sent in, and not of another
+/// chat given by id.
+fn targets_own_group(command: &Command, msg: &Message) -> bool {
+    let (Command::Authorize(args) | Command::Unauthorize(args) | Command::Authorizations(args)) =
+        command
+    else {
+        return false;
+    };
+    !msg.chat.is_private()
+        && args
+            .split_whitespace()
+            .next()
+            .and_then(|arg| arg.parse::<i64>().ok())
+            .is_none_or(|chat| chat == msg.chat.id.0)
+}
+
+/// Outcome of the access control of a restricted command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    Allowed,
+    /// A rule denies the command to the user
+    Denied,
+    /// Neither the user nor the chat are allowed to use the command
+    NotAuthorized,
+}
+
+/// Resolves whether the user can use the restricted command in the chat, from the most specific
+/// rule: the rule of the user in the chat, else their rule in every chat, else the authorizations
+/// of the chat (from the database or the config file).
+pub async fn resolve_permission(
+    chat: ChatId,
+    user: Option<UserId>,
+    command: &str,
+    authorizations: &dyn AuthorizationRepository,
+    permissions: &dyn PermissionRepository,
+) -> Result<Decision, Error> {
+    if let Some(user) = user {
+        let rules = permissions.list_user_permissions(&user.to_string()).await?;
+        let chat_id = chat.to_string();
+        for scope in [chat_id.as_str(), ""] {
+            if let Some(rule) = rules
+                .iter()
+                .find(|r| r.chat_id == scope && r.command == command)
+            {
+                return Ok(if rule.allowed {
+                    Decision::Allowed
+                } else {
+                    Decision::Denied
+                });
+            }
+        }
+    }
+
+    if config()
+        .chat_authorizations(chat)
+        .iter()
+        .any(|c| c == command)
+        || authorizations
+            .is_authorized(&chat.to_string(), command)
+            .await?
+    {
+        Ok(Decision::Allowed)
+    } else {
+        Ok(Decision::NotAuthorized)
+    }
+}
+
+/// Check that the chat is admin, and explain the rejection to the user otherwise. The commands
+/// which are not reserved to the admins are left to the other branches. The Telegram
+/// administrators of a group can also manage the authorizations of the group, if
+/// `GROUP_ADMINS_MANAGE_AUTHORIZATIONS` is set.
 ///
-/// Required dependencies: `teloxide_core::types::message::Message`, `sqlx_sqlite::SqlitePool`
+/// Required dependencies: `teloxide_core::types::message::Message`, `roboclic_v2::storage::AdminRepository`,
+/// `roboclic_v2::storage::LanguageRepository`
 fn require_admin() -> Endpoint<'static, DependencyMap, HandlerResult, DpHandlerDescription> {
     dptree::entry().filter_async(
-        |command: Command, msg: Message, db: Arc<SqlitePool>| async move {
-            let Some(user) = msg.from else {
+        |bot: Bot,
+         command: Command,
+         msg: Message,
+         lang: Lang,
+         admins: Arc<dyn AdminRepository>,
+         languages: Arc<dyn LanguageRepository>| async move {
+            if command.access() != Access::Admins {
+                return false;
+            }
+            let Some(user) = &msg.from else {
                 return false;
             };
 
             let id = user.id.to_string();
-            let is_admin = sqlx::query!(
-                "SELECT COUNT(*) AS is_admin FROM admins WHERE telegram_id = $1",
-                id
-            )
-            .fetch_one(db.as_ref())
-            .await
-            .is_ok_and(|r| r.is_admin > 0);
+            let is_admin = match admins.is_admin(&id).await {
+                Ok(is_admin) => is_admin,
+                Err(e) => {
+                    log::error!("Could not check admin status: {:?}", e);
+                    false
+                }
+            };
+
+            if !is_admin
+                && targets_own_group(&command, &msg)
+                && sent_by_group_manager(&bot, &msg).await
+            {
+                return true;
+            }
 
             if !is_admin {
                 log::warn!(
@@ -112,6 +415,16 @@ fn require_admin() -> Endpoint<'static, DependencyMap, HandlerResult, DpHandlerD
                     id,
                     command.shortand()
                 );
+                reject(
+                    &bot,
+                    &msg,
+                    &command,
+                    Rejection::NotAdmin,
+                    lang,
+                    admins.as_ref(),
+                    languages.as_ref(),
+                )
+                .await;
             }
 
             is_admin
@@ -121,64 +434,296 @@ fn require_admin() -> Endpoint<'static, DependencyMap, HandlerResult, DpHandlerD
 
 // --------------------------- AVAILABLE COMMANDS -----------------------------
 
+/// The descriptions of the commands are in the `command` section of the catalogs, and their
+/// detailed usage in the `usage` section.
 #[derive(BotCommands, Clone)]
-#[command(
-    rename_rule = "lowercase",
-    description = "These commands are supported:"
-)]
+#[command(rename_rule = "lowercase")]
 pub enum Command {
-    #[command(description = "display this text.")]
-    Help,
-    #[command(description = "Crée un sondage pour savoir qui est au bureau")]
-    Bureau,
-    #[command(description = "Crée un quiz sur une citation d'un des membres du comité")]
+    Help(String),
+    Bureau(String),
     Poll,
-    #[command(
-        description = "Authentifcation admin: /auth <token> <name>",
-        parse_with = "split",
-        separator = " "
-    )]
+    #[command(parse_with = "split", separator = " ")]
     Authenticate(String, String),
-    #[command(description = "(Admin) Liste les admins")]
     AdminList,
-    #[command(description = "(Admin) Supprime un admin à partir de son nom")]
+    AdminAdd(String),
     AdminRemove(String),
-    #[command(description = "(Admin) Authorise le groupe à utiliser la commande donnée")]
     Authorize(String),
-    #[command(
-        description = "(Admin) Révoque l'authorisation du groupe à utiliser la commande donnée"
-    )]
     Unauthorize(String),
-    #[command(description = "(Admin) Liste les commandes que ce groupe peut utiliser")]
-    Authorizations,
-    #[command(description = "(Admin) Affiche les stats des membres du comité")]
+    Authorizations(String),
+    Chats,
+    Grant(String),
+    Deny(String),
+    Revoke(String),
+    Permissions(String),
     Stats,
+    Permanences,
+    PermanenceAdd(String),
+    PermanenceRemove(String),
+    Events,
+    EventSubscribe,
+    EventUnsubscribe,
+    Backup,
+    #[command(rename = "lang")]
+    Language(String),
+}
+
+/// Who can use a command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Everyone,
+    /// The chats authorized with /authorize or by the config file
+    AuthorizedChats,
+    Admins,
 }
 
-pub const RESTRICTED_COMMANDS: [Command; 3] = [Command::Bureau, Command::Poll, Command::Stats];
+/// All the commands, in the order in which they are listed by /help.
+pub const COMMANDS: [Command; 24] = [
+    Command::Help(String::new()),
+    Command::Language(String::new()),
+    Command::Authenticate(String::new(), String::new()),
+    Command::Bureau(String::new()),
+    Command::Poll,
+    Command::Stats,
+    Command::Permanences,
+    Command::Events,
+    Command::AdminList,
+    Command::AdminAdd(String::new()),
+    Command::AdminRemove(String::new()),
+    Command::Authorize(String::new()),
+    Command::Unauthorize(String::new()),
+    Command::Authorizations(String::new()),
+    Command::Chats,
+    Command::Grant(String::new()),
+    Command::Deny(String::new()),
+    Command::Revoke(String::new()),
+    Command::Permissions(String::new()),
+    Command::PermanenceAdd(String::new()),
+    Command::PermanenceRemove(String::new()),
+    Command::EventSubscribe,
+    Command::EventUnsubscribe,
+    Command::Backup,
+];
+
+pub const RESTRICTED_COMMANDS: [Command; 5] = [
+    Command::Bureau(String::new()),
+    Command::Poll,
+    Command::Stats,
+    Command::Permanences,
+    Command::Events,
+];
+
+/// Expands names of restricted commands, of groups of commands from the config file, and `all`
+/// into the restricted commands they refer to, in the order of [`RESTRICTED_COMMANDS`]. Returns
+/// the unknown names if there are any.
+pub fn expand_restricted_commands(names: &[String]) -> Result<Vec<String>, Vec<String>> {
+    let mut commands = Vec::new();
+    let mut unknown = Vec::new();
+    for name in names {
+        let name = name.trim_start_matches('/').to_lowercase();
+        if name == "all" {
+            commands.extend(RESTRICTED_COMMANDS.iter().map(|c| c.shortand().to_owned()));
+        } else if let Some(group) = config().command_groups.get(&name) {
+            commands.extend(group.iter().cloned());
+        } else if RESTRICTED_COMMANDS.iter().any(|c| c.shortand() == name) {
+            commands.push(name);
+        } else {
+            unknown.push(name);
+        }
+    }
+
+    if !unknown.is_empty() {
+        return Err(unknown);
+    }
+    Ok(RESTRICTED_COMMANDS
+        .iter()
+        .map(|c| c.shortand().to_owned())
+        .filter(|c| commands.contains(c))
+        .collect())
+}
+
+/// Names accepted by [`expand_restricted_commands`], for the error messages.
+pub fn restricted_command_names() -> Vec<String> {
+    let mut groups = config().command_groups.keys().cloned().collect::<Vec<_>>();
+    groups.sort();
+
+    RESTRICTED_COMMANDS
+        .iter()
+        .map(|c| c.shortand().to_owned())
+        .chain(groups)
+        .chain(["all".to_owned()])
+        .collect()
+}
 
 impl Command {
-    // Used as key for the access control map
+    /// Name of the command, also used as key for the access control map
     pub fn shortand(&self) -> &str {
         match self {
-            Self::Help => "help",
-            Self::Bureau => "bureau",
+            Self::Help(..) => "help",
+            Self::Bureau(..) => "bureau",
             Self::Poll => "poll",
-            Self::Authenticate(..) => "auth",
+            Self::Authenticate(..) => "authenticate",
             Self::AdminList => "adminlist",
+            Self::AdminAdd(..) => "adminadd",
             Self::AdminRemove(..) => "adminremove",
             Self::Authorize(..) => "authorize",
             Self::Unauthorize(..) => "unauthorize",
-            Self::Authorizations => "authorizations",
+            Self::Authorizations(..) => "authorizations",
+            Self::Chats => "chats",
+            Self::Grant(..) => "grant",
+            Self::Deny(..) => "deny",
+            Self::Revoke(..) => "revoke",
+            Self::Permissions(..) => "permissions",
             Self::Stats => "stats",
+            Self::Permanences => "permanences",
+            Self::PermanenceAdd(..) => "permanenceadd",
+            Self::PermanenceRemove(..) => "permanenceremove",
+            Self::Events => "events",
+            Self::EventSubscribe => "eventsubscribe",
+            Self::EventUnsubscribe => "eventunsubscribe",
+            Self::Backup => "backup",
+            Self::Language(..) => "lang",
+        }
+    }
+
+    pub fn access(&self) -> Access {
+        match self {
+            Self::Help(..) | Self::Authenticate(..) | Self::Language(..) => Access::Everyone,
+            Self::Bureau(..) | Self::Poll | Self::Stats | Self::Permanences | Self::Events => {
+                Access::AuthorizedChats
+            }
+            Self::AdminList
+            | Self::AdminAdd(..)
+            | Self::AdminRemove(..)
+            | Self::Authorize(..)
+            | Self::Unauthorize(..)
+            | Self::Authorizations(..)
+            | Self::Chats
+            | Self::Grant(..)
+            | Self::Deny(..)
+            | Self::Revoke(..)
+            | Self::Permissions(..)
+            | Self::PermanenceAdd(..)
+            | Self::PermanenceRemove(..)
+            | Self::EventSubscribe
+            | Self::EventUnsubscribe
+            | Self::Backup => Access::Admins,
+        }
+    }
+
+    /// Whether the command manages the authorizations of a chat, which the Telegram
+    /// administrators of a group can do for their group if `GROUP_ADMINS_MANAGE_AUTHORIZATIONS` is
+    /// set.
+    pub fn manages_authorizations(&self) -> bool {
+        matches!(
+            self,
+            Self::Authorize(..) | Self::Unauthorize(..) | Self::Authorizations(..)
+        )
+    }
+
+    /// Whether the use of the command counts towards its rate limit: `/bureau stats` and
+    /// `/bureau export` only read the answers, and must not delay the next poll.
+    pub fn rate_limited(&self) -> bool {
+        match self {
+            Self::Bureau(args) => {
+                !matches!(args.split_whitespace().next(), Some("stats" | "export"))
+            }
+            _ => true,
         }
     }
 }
 
-// ---------------------------- COMMAND ENDPOINTS -----------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::load_test_config,
+        storage::{MemoryStorage, Permission},
+    };
 
-async fn help(bot: Bot, msg: Message) -> HandlerResult {
-    bot.send_message(msg.chat.id, Command::descriptions().to_string())
-        .await?;
-    Ok(())
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    #[test]
+    fn expands_restricted_commands() {
+        load_test_config();
+
+        assert_eq!(
+            expand_restricted_commands(&names(&["/Stats", "poll", "stats"])),
+            Ok(names(&["poll", "stats"]))
+        );
+        assert_eq!(
+            expand_restricted_commands(&names(&["directus", "poll"])),
+            Ok(names(&["bureau", "poll", "events"]))
+        );
+        assert_eq!(
+            expand_restricted_commands(&names(&["all"])),
+            Ok(names(&["bureau", "poll", "stats", "permanences", "events"]))
+        );
+        assert_eq!(
+            expand_restricted_commands(&names(&["poll", "help", "games"])),
+            Err(names(&["help", "games"]))
+        );
+    }
+
+    #[test]
+    fn only_limits_the_bureau_polls() {
+        assert!(Command::Bureau(String::new()).rate_limited());
+        assert!(Command::Bureau("invalid".into()).rate_limited());
+        assert!(!Command::Bureau("stats 7".into()).rate_limited());
+        assert!(!Command::Bureau(" export".into()).rate_limited());
+        assert!(Command::Poll.rate_limited());
+    }
+
+    #[tokio::test]
+    async fn resolves_permissions_from_the_most_specific_rule() {
+        load_test_config();
+        let storage = MemoryStorage::default();
+        let (chat, other_chat, user) = (ChatId(-200), ChatId(-300), UserId(7));
+        let resolve = |chat| resolve_permission(chat, Some(user), "stats", &storage, &storage);
+        let permission = |chat_id: &str, allowed| Permission {
+            user_id: user.to_string(),
+            chat_id: chat_id.to_owned(),
+            command: "stats".to_owned(),
+            allowed,
+        };
+
+        assert_eq!(resolve(chat).await.unwrap(), Decision::NotAuthorized);
+
+        storage
+            .authorize(&chat.to_string(), "stats", None)
+            .await
+            .unwrap();
+        assert_eq!(resolve(chat).await.unwrap(), Decision::Allowed);
+
+        // The rule of the user in every chat takes precedence over the authorization of the chat
+        storage
+            .set_permission(&permission("", false))
+            .await
+            .unwrap();
+        assert_eq!(resolve(chat).await.unwrap(), Decision::Denied);
+
+        // The rule of the user in the chat takes precedence over their rule in every chat
+        storage
+            .set_permission(&permission(&chat.to_string(), true))
+            .await
+            .unwrap();
+        assert_eq!(resolve(chat).await.unwrap(), Decision::Allowed);
+        assert_eq!(resolve(other_chat).await.unwrap(), Decision::Denied);
+
+        // An explicit deny in the chat wins over the authorization of the chat
+        storage
+            .set_permission(&permission(&chat.to_string(), false))
+            .await
+            .unwrap();
+        assert_eq!(resolve(chat).await.unwrap(), Decision::Denied);
+
+        // Without a rule, the authorizations of the config file apply too
+        assert_eq!(
+            resolve_permission(ChatId(-100), None, "poll", &storage, &storage)
+                .await
+                .unwrap(),
+            Decision::Allowed
+        );
+    }
 }
@@ -1,23 +1,670 @@
-use envconfig::Envconfig;
-use std::sync::OnceLock;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::OnceLock,
+};
+use teloxide::types::ChatId;
+
+use crate::{commands::RESTRICTED_COMMANDS, i18n::Lang};
+
+/// Name of the config file looked up in `DATA_DIR` when no path is given.
+const CONFIG_FILE_NAME: &str = "config.toml";
+
+/// Where the admins and authorizations are stored.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StorageBackend {
+    /// Everything in the database (SQLite or PostgreSQL)
+    #[default]
+    Database,
+    /// Admins and authorizations in Directus, the rest in the database
+    Directus,
+    /// Everything in memory, lost when the bot stops (for tests)
+    Memory,
+}
+
+impl FromStr for StorageBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            // `sqlite` was the name of this backend before PostgreSQL was supported
+            "database" | "sqlite" => Ok(Self::Database),
+            "directus" => Ok(Self::Directus),
+            "memory" => Ok(Self::Memory),
+            _ => Err(format!("unknown storage backend: {}", s)),
+        }
+    }
+}
 
-#[derive(Envconfig)]
 pub struct Config {
-    #[envconfig(from = "BOT_TOKEN")]
     pub bot_token: String,
-    #[envconfig(from = "DATA_DIR")]
     pub data_dir: String,
-    #[envconfig(from = "DATABASE_URL")]
+    /// Url of the database, either `sqlite://` or `postgres://`
     pub database_url: Option<String>,
-    #[envconfig(from = "ADMIN_TOKEN")]
     pub admin_token: String,
-    #[envconfig(from = "DIRECTUS_URL")]
+    /// Storage backend, `database`, `directus` or `memory`
+    pub storage: StorageBackend,
     pub directus_url: String,
-    #[envconfig(from = "DIRECTUS_TOKEN")]
     pub directus_token: String,
+    /// Timeout (in seconds) of the requests to Directus
+    pub directus_timeout: u64,
+    /// Number of retries of the failed idempotent requests to Directus
+    pub directus_retries: u32,
+    /// Number of consecutive failures after which requests to Directus are paused
+    pub directus_circuit_threshold: u32,
+    /// Duration (in seconds) of the pause of the requests to Directus
+    pub directus_circuit_cooldown: u64,
+    /// Duration (in seconds) during which the committee fetched from Directus is reused
+    pub committee_cache_ttl: u64,
+    /// Address on which the Directus webhook receiver listens
+    pub webhook_address: Option<SocketAddr>,
+    /// Secret used to sign the Directus notifications
+    pub webhook_secret: Option<String>,
+    /// Whether the users are told why their command was rejected
+    pub rejection_feedback: bool,
+    /// Delay (in seconds) during which a chat is not told again why its commands are rejected
+    pub rejection_feedback_interval: u64,
+    /// Who to ask for an authorization, the names of the admins by default
+    pub rejection_contact: Option<String>,
+    /// Whether the admins are notified in private of the rejected commands
+    pub notify_admins_of_rejections: bool,
+    /// Whether the Telegram administrators of a group can manage the authorizations of the group
+    /// without being admins of the bot
+    pub group_admins_manage_authorizations: bool,
+    /// Chats in which committee changes are announced
+    pub committee_announce_chats: Vec<ChatId>,
+    /// Settings of specific chats, from the `[chats."<id>"]` tables of the config file
+    pub chats: HashMap<ChatId, ChatConfig>,
+    /// Named groups of restricted commands, which can be given to /authorize and /unauthorize,
+    /// from the `[command_groups]` table of the config file
+    pub command_groups: HashMap<String, Vec<String>>,
+    /// Rate limits of the restricted commands, from the `[rate_limits.<command>]` tables of the
+    /// config file
+    pub rate_limits: HashMap<String, RateLimit>,
+    pub timezone: chrono_tz::Tz,
+    /// Language of the chats and users which did not choose one, and whose Telegram client
+    /// language is not supported
+    pub default_language: Lang,
+    /// Delay (in minutes) before the start of a permanence at which the assignees are reminded
+    pub permanence_reminder: i64,
+    /// Delay (in minutes) between two fetches of the events from Directus
+    pub event_sync_interval: u64,
+    /// Delay (in minutes) before the start of an event at which the reminder is sent
+    pub event_reminder: i64,
+    /// Directory of the backups of the database, `backups` in `DATA_DIR` by default
+    pub backup_dir: Option<String>,
+    /// Delay (in hours) between two backups of the database, 0 to disable them
+    pub backup_interval: u64,
+    /// Number of backups kept
+    pub backup_retention: usize,
+}
+
+/// Defaults of a chat, set in the config file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChatConfig {
+    /// Commands the chat can use, in addition to those authorized with /authorize
+    pub authorizations: Vec<String>,
+    /// Whether the committee changes are announced in the chat
+    pub committee_announcements: bool,
+    /// Language of the chat, until one is chosen with /lang
+    pub language: Option<Lang>,
+}
+
+/// Minimal delays between two uses of a restricted command, set in the config file. The admins
+/// are not limited.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimit {
+    /// Delay (in seconds) between two uses of the command in a chat, 0 for no limit
+    pub chat: u64,
+    /// Delay (in seconds) between two uses of the command by a user, 0 for no limit
+    pub user: u64,
+}
+
+impl Config {
+    /// Reads the config from the file (if any) and the environment, which takes precedence, and
+    /// returns all the missing or invalid settings at once if there are any.
+    fn read(path: Option<&Path>) -> Result<Self, Vec<String>> {
+        let mut vars = Vars::default();
+        vars.read_file(path);
+        Self::from_vars(vars)
+    }
+
+    fn from_vars(mut vars: Vars) -> Result<Self, Vec<String>> {
+        let chats = vars.chats();
+        let command_groups = vars.command_groups();
+        let rate_limits = vars.rate_limits();
+
+        let mut config = Config {
+            bot_token: vars.required("BOT_TOKEN"),
+            data_dir: vars.required("DATA_DIR"),
+            database_url: vars.optional("DATABASE_URL"),
+            admin_token: vars.required("ADMIN_TOKEN"),
+            storage: vars.with_default("STORAGE", "database"),
+            directus_url: vars.required("DIRECTUS_URL"),
+            directus_token: vars.required("DIRECTUS_TOKEN"),
+            directus_timeout: vars.with_default("DIRECTUS_TIMEOUT", "10"),
+            directus_retries: vars.with_default("DIRECTUS_RETRIES", "3"),
+            directus_circuit_threshold: vars.with_default("DIRECTUS_CIRCUIT_THRESHOLD", "5"),
+            directus_circuit_cooldown: vars.with_default("DIRECTUS_CIRCUIT_COOLDOWN", "60"),
+            committee_cache_ttl: vars.with_default("COMMITTEE_CACHE_TTL", "300"),
+            webhook_address: vars.optional("WEBHOOK_ADDRESS"),
+            webhook_secret: vars.optional("WEBHOOK_SECRET"),
+            rejection_feedback: vars.with_default("REJECTION_FEEDBACK", "true"),
+            rejection_feedback_interval: vars.with_default("REJECTION_FEEDBACK_INTERVAL", "300"),
+            rejection_contact: vars.optional("REJECTION_CONTACT"),
+            notify_admins_of_rejections: vars.with_default("NOTIFY_ADMINS_OF_REJECTIONS", "false"),
+            group_admins_manage_authorizations: vars
+                .with_default("GROUP_ADMINS_MANAGE_AUTHORIZATIONS", "false"),
+            committee_announce_chats: vars.list("COMMITTEE_ANNOUNCE_CHATS", |id| {
+                id.parse::<i64>().map(ChatId)
+            }),
+            timezone: vars.with_default("TIMEZONE", "Europe/Zurich"),
+            default_language: vars.with_default("DEFAULT_LANGUAGE", "fr"),
+            permanence_reminder: vars.with_default("PERMANENCE_REMINDER", "30"),
+            event_sync_interval: vars.with_default("EVENT_SYNC_INTERVAL", "10"),
+            event_reminder: vars.with_default("EVENT_REMINDER", "60"),
+            backup_dir: vars.optional("BACKUP_DIR"),
+            backup_interval: vars.with_default("BACKUP_INTERVAL", "24"),
+            backup_retention: vars.with_default("BACKUP_RETENTION", "7"),
+            chats,
+            command_groups,
+            rate_limits,
+        };
+
+        let mut errors = vars.finish();
+        if config.directus_timeout == 0 {
+            errors.push("DIRECTUS_TIMEOUT must be greater than 0".into());
+        }
+        if config.event_sync_interval == 0 {
+            errors.push("EVENT_SYNC_INTERVAL must be greater than 0".into());
+        }
+        if config.backup_retention == 0 {
+            errors.push("BACKUP_RETENTION must be greater than 0".into());
+        }
+        if config.webhook_address.is_some() && config.webhook_secret.is_none() {
+            errors.push("WEBHOOK_SECRET must be set when WEBHOOK_ADDRESS is set".into());
+        }
+        if let Some(url) = &config.database_url {
+            if !["sqlite:", "postgres://", "postgresql://"]
+                .iter()
+                .any(|scheme| url.starts_with(scheme))
+            {
+                errors.push("DATABASE_URL must start with sqlite: or postgres://".into());
+            }
+        }
+
+        for (chat, chat_config) in &config.chats {
+            if chat_config.committee_announcements
+                && !config.committee_announce_chats.contains(chat)
+            {
+                config.committee_announce_chats.push(*chat);
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(config)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Commands the chat can use according to the config file.
+    pub fn chat_authorizations(&self, chat: ChatId) -> &[String] {
+        self.chats
+            .get(&chat)
+            .map(|c| c.authorizations.as_slice())
+            .unwrap_or_default()
+    }
+}
+
+/// Reads the settings, collecting the errors instead of stopping at the first one. Each setting
+/// is read from the environment variable `NAME`, then from the file whose path is in `NAME_FILE`
+/// (for Docker secrets), then from the key `name` of the config file.
+///
+/// Invalid settings are replaced by their type's default value, which is never used as the
+/// config is discarded if there are errors.
+struct Vars {
+    /// Settings of the config file which were not read yet
+    file: toml::Table,
+    /// Looks up an environment variable, replaced in the tests so that they do not depend on the
+    /// environment
+    env: fn(&str) -> Option<String>,
+    errors: Vec<String>,
+}
+
+impl Default for Vars {
+    fn default() -> Self {
+        Self {
+            file: toml::Table::new(),
+            env: |name| std::env::var(name).ok(),
+            errors: vec![],
+        }
+    }
+}
+
+impl Vars {
+    /// Reads the config file at `path`, or in `DATA_DIR` if it exists there.
+    fn read_file(&mut self, path: Option<&Path>) {
+        let path = match path {
+            Some(path) => path.to_owned(),
+            None => {
+                let Some(data_dir) = (self.env)("DATA_DIR") else {
+                    return;
+                };
+                let path = PathBuf::from(data_dir).join(CONFIG_FILE_NAME);
+                if !path.exists() {
+                    return;
+                }
+                path
+            }
+        };
+
+        log::info!("Reading config file {}", path.display());
+        match std::fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|content| {
+                content
+                    .parse::<toml::Table>()
+                    .map_err(|e| e.to_string().trim().to_owned())
+            }) {
+            Ok(file) => self.file = file,
+            Err(e) => self
+                .errors
+                .push(format!("Could not read {}: {e}", path.display())),
+        }
+    }
+
+    /// Reads the `[chats."<id>"]` tables of the config file.
+    fn chats(&mut self) -> HashMap<ChatId, ChatConfig> {
+        let Some(chats) = self.file.remove("chats") else {
+            return HashMap::new();
+        };
+        let toml::Value::Table(chats) = chats else {
+            self.errors.push("chats must be a table".into());
+            return HashMap::new();
+        };
+
+        let mut configs = HashMap::new();
+        for (id, chat) in chats {
+            let config = match chat.try_into::<ChatConfig>() {
+                Ok(config) => config,
+                Err(e) => {
+                    self.errors
+                        .push(format!("chats.{id} is invalid: {}", e.to_string().trim()));
+                    continue;
+                }
+            };
+            for command in &config.authorizations {
+                if !RESTRICTED_COMMANDS.iter().any(|c| c.shortand() == command) {
+                    self.errors.push(format!(
+                        "chats.{id}.authorizations contains an unknown command: {command}"
+                    ));
+                }
+            }
+            match id.parse::<i64>() {
+                Ok(id) => {
+                    configs.insert(ChatId(id), config);
+                }
+                Err(e) => self
+                    .errors
+                    .push(format!("chats.{id} is not a chat id: {e}")),
+            }
+        }
+
+        configs
+    }
+
+    /// Reads the `[command_groups]` table of the config file, whose entries are lists of
+    /// restricted commands.
+    fn command_groups(&mut self) -> HashMap<String, Vec<String>> {
+        let Some(groups) = self.file.remove("command_groups") else {
+            return HashMap::new();
+        };
+        let groups = match groups.try_into::<HashMap<String, Vec<String>>>() {
+            Ok(groups) => groups,
+            Err(e) => {
+                self.errors.push(format!(
+                    "command_groups must map names to lists of commands: {}",
+                    e.to_string().trim()
+                ));
+                return HashMap::new();
+            }
+        };
+
+        let mut lowercase_groups = HashMap::new();
+        for (name, commands) in groups {
+            let name = name.to_lowercase();
+            if name == "all" || RESTRICTED_COMMANDS.iter().any(|c| c.shortand() == name) {
+                self.errors.push(format!(
+                    "command_groups.{name} cannot be named like a command or all"
+                ));
+            }
+            for command in &commands {
+                if !RESTRICTED_COMMANDS.iter().any(|c| c.shortand() == command) {
+                    self.errors.push(format!(
+                        "command_groups.{name} contains an unknown command: {command}"
+                    ));
+                }
+            }
+            lowercase_groups.insert(name, commands);
+        }
+
+        lowercase_groups
+    }
+
+    /// Reads the `[rate_limits.<command>]` tables of the config file.
+    fn rate_limits(&mut self) -> HashMap<String, RateLimit> {
+        let Some(limits) = self.file.remove("rate_limits") else {
+            return HashMap::new();
+        };
+        let toml::Value::Table(limits) = limits else {
+            self.errors.push("rate_limits must be a table".into());
+            return HashMap::new();
+        };
+
+        let mut rate_limits = HashMap::new();
+        for (command, limit) in limits {
+            if !RESTRICTED_COMMANDS.iter().any(|c| c.shortand() == command) {
+                self.errors.push(format!(
+                    "rate_limits contains an unknown command: {command}"
+                ));
+            }
+            match limit.try_into::<RateLimit>() {
+                Ok(limit) => {
+                    rate_limits.insert(command, limit);
+                }
+                Err(e) => self.errors.push(format!(
+                    "rate_limits.{command} is invalid: {}",
+                    e.to_string().trim()
+                )),
+            }
+        }
+
+        rate_limits
+    }
+
+    fn get(&mut self, name: &str) -> Option<String> {
+        let key = name.to_lowercase();
+        let in_file = self.file.remove(&key);
+
+        if let Some(value) = (self.env)(name).filter(|v| !v.trim().is_empty()) {
+            return Some(value);
+        }
+        if let Some(path) = (self.env)(&format!("{name}_FILE")) {
+            return match std::fs::read_to_string(&path) {
+                Ok(value) => Some(value.trim().to_owned()),
+                Err(e) => {
+                    self.errors
+                        .push(format!("{name}_FILE could not be read ({path}): {e}"));
+                    // The error is already reported, do not report the setting as missing too
+                    Some(String::new())
+                }
+            };
+        }
+
+        match in_file? {
+            toml::Value::String(value) => Some(value),
+            toml::Value::Array(values) => Some(
+                values
+                    .iter()
+                    .map(|v| match v {
+                        toml::Value::String(v) => v.clone(),
+                        v => v.to_string(),
+                    })
+                    .collect::<Vec<_>>()
+                    .join(","),
+            ),
+            toml::Value::Table(_) => {
+                self.errors.push(format!("{key} must not be a table"));
+                None
+            }
+            value => Some(value.to_string()),
+        }
+    }
+
+    /// Returns the errors, including the unknown settings of the config file.
+    fn finish(mut self) -> Vec<String> {
+        for key in self.file.keys() {
+            self.errors
+                .push(format!("Unknown setting in the config file: {key}"));
+        }
+        self.errors
+    }
+
+    fn parse<T: FromStr>(&mut self, name: &str, value: &str) -> Option<T>
+    where
+        T::Err: Display,
+    {
+        value
+            .trim()
+            .parse()
+            .map_err(|e| {
+                self.errors
+                    .push(format!("{name} is invalid ({value:?}): {e}"))
+            })
+            .ok()
+    }
+
+    fn required<T: FromStr + Default>(&mut self, name: &str) -> T
+    where
+        T::Err: Display,
+    {
+        match self.get(name) {
+            Some(value) => self.parse(name, &value).unwrap_or_default(),
+            None => {
+                self.errors.push(format!("{name} is missing"));
+                T::default()
+            }
+        }
+    }
+
+    fn optional<T: FromStr>(&mut self, name: &str) -> Option<T>
+    where
+        T::Err: Display,
+    {
+        let value = self.get(name)?;
+        self.parse(name, &value)
+    }
+
+    fn with_default<T: FromStr + Default>(&mut self, name: &str, default: &str) -> T
+    where
+        T::Err: Display,
+    {
+        let value = self.get(name).unwrap_or_else(|| default.to_owned());
+        self.parse(name, &value).unwrap_or_default()
+    }
+
+    /// Reads a comma-separated list, empty if the variable is not set.
+    fn list<T, E: Display>(&mut self, name: &str, parse: impl Fn(&str) -> Result<T, E>) -> Vec<T> {
+        let Some(value) = self.get(name) else {
+            return vec![];
+        };
+
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .filter_map(|item| match parse(item) {
+                Ok(item) => Some(item),
+                Err(e) => {
+                    self.errors
+                        .push(format!("{name} contains an invalid value ({item:?}): {e}"));
+                    None
+                }
+            })
+            .collect()
+    }
 }
 
 static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// Loads the config from the file at `path` (or `config.toml` in `DATA_DIR`) and the
+/// environment. Must be called once at startup, before [`config`].
+pub fn load(path: Option<&Path>) -> Result<&'static Config, Vec<String>> {
+    let config = Config::read(path)?;
+    Ok(CONFIG.get_or_init(|| config))
+}
+
 pub fn config() -> &'static Config {
-    CONFIG.get_or_init(|| Config::init_from_env().unwrap())
+    CONFIG
+        .get()
+        .expect("the config is accessed before being loaded")
+}
+
+/// Loads the config of the tests, with a chat authorized to use /poll, a group of commands and a
+/// webhook secret, unless it is already loaded.
+#[cfg(test)]
+pub fn load_test_config() -> &'static Config {
+    CONFIG.get_or_init(|| {
+        let file = r#"
+            bot_token = "1:token"
+            data_dir = "/tmp"
+            admin_token = "admin"
+            directus_url = "http://localhost"
+            directus_token = "directus"
+            webhook_secret = "secret"
+
+            [chats."-100"]
+            authorizations = ["poll"]
+
+            [command_groups]
+            Directus = ["events", "bureau"]
+        "#;
+        let vars = Vars {
+            file: file.parse().unwrap(),
+            env: |_| None,
+            ..Default::default()
+        };
+        Config::from_vars(vars).unwrap_or_else(|e| panic!("invalid test config: {e:?}"))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(file: &str) -> Vars {
+        Vars {
+            file: file.parse().unwrap(),
+            env: |_| None,
+            ..Default::default()
+        }
+    }
+
+    fn read(file: &str) -> Result<Config, Vec<String>> {
+        Config::from_vars(vars(file))
+    }
+
+    const REQUIRED: &str = r#"
+        bot_token = "1:token"
+        data_dir = "/tmp"
+        admin_token = "admin"
+        directus_url = "http://localhost"
+        directus_token = "directus"
+    "#;
+
+    #[test]
+    fn reads_the_config_file() {
+        let config = read(&format!(
+            "{REQUIRED}
+            committee_announce_chats = [1, 2]
+            rejection_feedback = false
+
+            [chats.\"3\"]
+            committee_announcements = true
+            language = \"de\"
+
+            [command_groups]
+            Games = [\"poll\"]
+
+            [rate_limits.poll]
+            chat = 60"
+        ))
+        .unwrap();
+
+        assert_eq!(config.bot_token, "1:token");
+        assert!(!config.rejection_feedback);
+        assert_eq!(config.directus_timeout, 10);
+        assert_eq!(
+            config.committee_announce_chats,
+            [ChatId(1), ChatId(2), ChatId(3)]
+        );
+        assert_eq!(config.chats[&ChatId(3)].language, Some(Lang::De));
+        assert_eq!(config.command_groups["games"], ["poll"]);
+        assert_eq!(config.rate_limits["poll"].chat, 60);
+        assert_eq!(config.rate_limits["poll"].user, 0);
+    }
+
+    #[test]
+    fn collects_all_the_errors() {
+        let errors = read(&format!(
+            "{REQUIRED}
+            directus_timeout = 0
+            storage = \"mongodb\"
+            committee_announce_chats = [\"1\", \"general\"]
+            webhook_address = \"127.0.0.1:8080\"
+            unknown_setting = 1
+
+            [chats.general]
+            authorizations = [\"help\"]
+
+            [command_groups]
+            poll = [\"poll\"]
+
+            [rate_limits.stats]
+            hourly = 1"
+        ))
+        .err()
+        .unwrap();
+
+        assert_eq!(
+            errors,
+            [
+                "chats.general.authorizations contains an unknown command: help",
+                "chats.general is not a chat id: invalid digit found in string",
+                "command_groups.poll cannot be named like a command or all",
+                "rate_limits.stats is invalid: unknown field `hourly`, expected `chat` or `user`",
+                "STORAGE is invalid (\"mongodb\"): unknown storage backend: mongodb",
+                "COMMITTEE_ANNOUNCE_CHATS contains an invalid value (\"general\"): invalid digit \
+                 found in string",
+                "Unknown setting in the config file: unknown_setting",
+                "DIRECTUS_TIMEOUT must be greater than 0",
+                "WEBHOOK_SECRET must be set when WEBHOOK_ADDRESS is set",
+            ]
+        );
+    }
+
+    #[test]
+    fn prefers_the_environment_to_the_config_file() {
+        let config = Config::from_vars(Vars {
+            env: |name| (name == "DIRECTUS_TIMEOUT").then(|| "20".to_owned()),
+            ..vars(&format!("{REQUIRED}\ndirectus_timeout = 5"))
+        })
+        .unwrap();
+
+        assert_eq!(config.directus_timeout, 20);
+    }
+
+    #[test]
+    fn reports_missing_and_invalid_variables() {
+        let mut vars = vars("test_count = \"many\"");
+
+        assert_eq!(vars.required::<String>("TEST_MISSING"), "");
+        assert_eq!(vars.required::<u32>("TEST_COUNT"), 0);
+        assert_eq!(vars.optional::<u32>("TEST_OPTIONAL"), None);
+        assert_eq!(vars.with_default::<u32>("TEST_DEFAULT", "3"), 3);
+        assert_eq!(
+            vars.finish(),
+            [
+                "TEST_MISSING is missing",
+                "TEST_COUNT is invalid (\"many\"): invalid digit found in string",
+            ]
+        );
+    }
 }
@@ -1,16 +1,96 @@
-use log::error;
-use reqwest::Client;
-use serde::Deserialize;
-use tokio::task::JoinSet;
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, OnceLock,
+    },
+    time::{Duration, Instant},
+};
 
-use crate::config::config;
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use reqwest::{Method, RequestBuilder, StatusCode};
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize};
+use serde_json::{json, Value};
+
+use crate::{config::config, i18n::Lang, t};
+
+/// Number of items fetched per request by [`Client::get_all_items`].
+const PAGE_SIZE: u64 = 100;
+/// Delay before the first retry of a failed request, doubled at each retry.
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(500);
+/// Longest delay between two retries, however many retries are configured.
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(30);
 
 #[derive(Debug)]
 pub enum Error {
     Request(reqwest::Error),
     Serde(serde_json::Error),
+    /// Directus answered with an error status, with the errors of its payload (if any).
+    Directus {
+        status: StatusCode,
+        errors: Vec<DirectusError>,
+    },
+    /// Too many requests failed recently, Directus is considered down.
+    Unavailable,
+}
+
+impl Error {
+    /// Whether the error may disappear by retrying later (network errors, timeouts, server
+    /// errors). Only those are retried and count as failures for the circuit breaker.
+    fn is_transient(&self) -> bool {
+        match self {
+            Self::Request(e) => e.is_timeout() || e.is_connect() || e.is_request(),
+            Self::Directus { status, .. } => {
+                status.is_server_error() || *status == StatusCode::TOO_MANY_REQUESTS
+            }
+            Self::Serde(_) | Self::Unavailable => false,
+        }
+    }
+
+    /// Message to display to the user whose command failed because of this error.
+    pub fn user_message(&self, lang: Lang) -> String {
+        if self.is_transient() || matches!(self, Self::Unavailable) {
+            t!(lang, "directus.unavailable")
+        } else {
+            t!(lang, "directus.error")
+        }
+    }
 }
 
+/// Error as returned by Directus in the `errors` field of the response.
+#[derive(Deserialize, Debug)]
+pub struct DirectusError {
+    pub message: String,
+    #[serde(default)]
+    pub extensions: DirectusErrorExtensions,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct DirectusErrorExtensions {
+    pub code: Option<String>,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Request(e) => write!(f, "request to Directus failed: {e}"),
+            Self::Serde(e) => write!(f, "invalid response from Directus: {e}"),
+            Self::Directus { status, errors } => {
+                write!(f, "Directus answered with status {status}")?;
+                for e in errors {
+                    match &e.extensions.code {
+                        Some(code) => write!(f, ", {} ({})", e.message, code)?,
+                        None => write!(f, ", {}", e.message)?,
+                    }
+                }
+                Ok(())
+            }
+            Self::Unavailable => write!(f, "Directus is unavailable (circuit breaker open)"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
 impl From<reqwest::Error> for Error {
     fn from(value: reqwest::Error) -> Self {
         Self::Request(value)
@@ -23,7 +103,319 @@ impl From<serde_json::Error> for Error {
     }
 }
 
+// ---------------------------------- CLIENT ----------------------------------
+
+#[derive(Deserialize, Debug)]
+struct DirectusResponse<T> {
+    data: T,
+}
+
 #[derive(Deserialize, Debug)]
+struct DirectusErrorResponse {
+    errors: Vec<DirectusError>,
+}
+
+/// Query parameters of the item endpoints.
+/// See https://docs.directus.io/reference/query.html
+#[derive(Default, Clone, Debug)]
+pub struct Query {
+    fields: Vec<String>,
+    filters: Vec<Value>,
+    sort: Vec<String>,
+    limit: Option<u64>,
+    page: Option<u64>,
+}
+
+impl Query {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds fields to return, using the dot notation for relational fields.
+    pub fn fields(mut self, fields: &[&str]) -> Self {
+        self.fields.extend(fields.iter().map(|f| f.to_string()));
+        self
+    }
+
+    /// Adds a filter rule, e.g. `filter("start", "_gte", "$NOW")`. The field can use the dot
+    /// notation for relational fields. Multiple rules must all be satisfied.
+    pub fn filter(mut self, field: &str, operator: &str, value: impl Serialize) -> Self {
+        let rule = field.rsplit('.').fold(
+            json!({ operator: value }),
+            |rule, field| json!({ field: rule }),
+        );
+        self.filters.push(rule);
+        self
+    }
+
+    /// Adds a field to sort on. Prefix it with `-` to sort in descending order.
+    pub fn sort(mut self, field: &str) -> Self {
+        self.sort.push(field.to_owned());
+        self
+    }
+
+    pub fn limit(mut self, limit: u64) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    fn page(mut self, page: u64) -> Self {
+        self.page = Some(page);
+        self
+    }
+
+    fn to_params(&self) -> Vec<(&'static str, String)> {
+        let mut params = vec![];
+
+        if !self.fields.is_empty() {
+            params.push(("fields", self.fields.join(",")));
+        }
+        match self.filters.as_slice() {
+            [] => {}
+            [filter] => params.push(("filter", filter.to_string())),
+            filters => params.push(("filter", json!({ "_and": filters }).to_string())),
+        }
+        if !self.sort.is_empty() {
+            params.push(("sort", self.sort.join(",")));
+        }
+        if let Some(limit) = self.limit {
+            params.push(("limit", limit.to_string()));
+        }
+        if let Some(page) = self.page {
+            params.push(("page", page.to_string()));
+        }
+
+        params
+    }
+}
+
+/// Stops sending requests to Directus for a while after too many consecutive failures, so
+/// that handlers fail fast instead of waiting for timeouts.
+#[derive(Default)]
+struct CircuitBreaker {
+    state: Mutex<CircuitState>,
+}
+
+#[derive(Default)]
+struct CircuitState {
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    fn check(&self) -> Result<(), Error> {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        match state.open_until {
+            Some(until) if Instant::now() < until => Err(Error::Unavailable),
+            _ => Ok(()),
+        }
+    }
+
+    fn record_success(&self) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.consecutive_failures = 0;
+        state.open_until = None;
+    }
+
+    fn record_failure(&self) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.consecutive_failures += 1;
+
+        if state.consecutive_failures >= config().directus_circuit_threshold {
+            log::warn!(
+                "Directus failed {} times in a row, pausing requests for {}s",
+                state.consecutive_failures,
+                config().directus_circuit_cooldown
+            );
+            state.open_until =
+                Some(Instant::now() + Duration::from_secs(config().directus_circuit_cooldown));
+        }
+    }
+}
+
+/// Client of the Directus REST API, authenticated as the RoboCLIC user.
+pub struct Client {
+    http: reqwest::Client,
+    url: String,
+    token: String,
+    circuit_breaker: CircuitBreaker,
+}
+
+static CLIENT: OnceLock<Client> = OnceLock::new();
+/// Returns the shared client, so that all requests reuse the same connection pool.
+pub fn client() -> &'static Client {
+    CLIENT.get_or_init(|| Client {
+        http: reqwest::Client::builder()
+            .timeout(Duration::from_secs(config().directus_timeout))
+            .build()
+            .expect("Could not initialize the HTTP client"),
+        url: config().directus_url.trim_end_matches('/').to_owned(),
+        token: config().directus_token.clone(),
+        circuit_breaker: CircuitBreaker::default(),
+    })
+}
+
+impl Client {
+    /// Fetches the items of a collection matching the query, as a single request.
+    pub async fn get_items<T: DeserializeOwned>(
+        &self,
+        collection: &str,
+        query: &Query,
+    ) -> Result<Vec<T>, Error> {
+        let path = format!("items/{}", collection);
+        self.send(|| self.request(Method::GET, &path, query), true)
+            .await
+    }
+
+    /// Fetches all the items of a collection matching the query, page by page.
+    pub async fn get_all_items<T: DeserializeOwned>(
+        &self,
+        collection: &str,
+        query: &Query,
+    ) -> Result<Vec<T>, Error> {
+        let mut items = vec![];
+
+        for page in 1.. {
+            let query = query.clone().limit(PAGE_SIZE).page(page);
+            let mut page_items = self.get_items(collection, &query).await?;
+            let last_page = (page_items.len() as u64) < PAGE_SIZE;
+            items.append(&mut page_items);
+
+            if last_page {
+                break;
+            }
+        }
+
+        Ok(items)
+    }
+
+    pub async fn get_item<T: DeserializeOwned>(
+        &self,
+        collection: &str,
+        id: impl std::fmt::Display,
+        query: &Query,
+    ) -> Result<T, Error> {
+        let path = format!("items/{}/{}", collection, id);
+        self.send(|| self.request(Method::GET, &path, query), true)
+            .await
+    }
+
+    /// Updates the given fields of an item, and returns the updated item.
+    pub async fn patch_item<B: Serialize, T: DeserializeOwned>(
+        &self,
+        collection: &str,
+        id: impl std::fmt::Display,
+        body: &B,
+        query: &Query,
+    ) -> Result<T, Error> {
+        let path = format!("items/{}/{}", collection, id);
+        self.send(
+            || self.request(Method::PATCH, &path, query).json(body),
+            false,
+        )
+        .await
+    }
+
+    /// Creates an item, and returns it.
+    pub async fn create_item<B: Serialize, T: DeserializeOwned>(
+        &self,
+        collection: &str,
+        body: &B,
+        query: &Query,
+    ) -> Result<T, Error> {
+        let path = format!("items/{}", collection);
+        self.send(
+            || self.request(Method::POST, &path, query).json(body),
+            false,
+        )
+        .await
+    }
+
+    /// Deletes the items with the given ids.
+    pub async fn delete_items<I: Serialize>(
+        &self,
+        collection: &str,
+        ids: &[I],
+    ) -> Result<(), Error> {
+        let path = format!("items/{}", collection);
+        self.send(
+            || self.request(Method::DELETE, &path, &Query::new()).json(ids),
+            true,
+        )
+        .await
+    }
+
+    fn request(&self, method: Method, path: &str, query: &Query) -> RequestBuilder {
+        self.http
+            .request(method, format!("{}/{}", self.url, path))
+            .query(&query.to_params())
+            .bearer_auth(&self.token)
+    }
+
+    /// Sends the request built by `request`. Idempotent requests are retried with an
+    /// exponential backoff when they fail with a transient error.
+    async fn send<T: DeserializeOwned>(
+        &self,
+        request: impl Fn() -> RequestBuilder,
+        idempotent: bool,
+    ) -> Result<T, Error> {
+        let retries = if idempotent {
+            config().directus_retries
+        } else {
+            0
+        };
+        let mut delay = RETRY_BASE_DELAY;
+
+        for _ in 0..retries {
+            match self.send_once(request()).await {
+                Err(e) if e.is_transient() => {
+                    log::warn!("Request to Directus failed, retrying in {delay:?}: {e}");
+                    tokio::time::sleep(delay).await;
+                    delay = delay.saturating_mul(2).min(RETRY_MAX_DELAY);
+                }
+                result => return result,
+            }
+        }
+
+        self.send_once(request()).await
+    }
+
+    async fn send_once<T: DeserializeOwned>(&self, request: RequestBuilder) -> Result<T, Error> {
+        self.circuit_breaker.check()?;
+
+        let result = Self::execute(request).await;
+        match &result {
+            Err(e) if e.is_transient() => self.circuit_breaker.record_failure(),
+            _ => self.circuit_breaker.record_success(),
+        }
+
+        result
+    }
+
+    async fn execute<T: DeserializeOwned>(request: RequestBuilder) -> Result<T, Error> {
+        let response = request.send().await?;
+        let status = response.status();
+        let body = response.bytes().await?;
+
+        if !status.is_success() {
+            let errors = serde_json::from_slice::<DirectusErrorResponse>(&body)
+                .map(|r| r.errors)
+                .unwrap_or_default();
+            return Err(Error::Directus { status, errors });
+        }
+
+        // Deletions answer with an empty body
+        if body.is_empty() {
+            return Ok(serde_json::from_value(Value::Null)?);
+        }
+
+        Ok(serde_json::from_slice::<DirectusResponse<T>>(&body)?.data)
+    }
+}
+
+// --------------------------------- COMMITTEE --------------------------------
+
+#[derive(Deserialize, Debug, Clone)]
 pub struct Committee {
     pub id: i32,
     #[serde(rename = "name")]
@@ -31,51 +423,174 @@ pub struct Committee {
     pub poll_count: i32,
 }
 
-#[derive(Deserialize, Debug)]
-struct DirectusResponse<T> {
-    data: T,
-}
+/// Last fetched committee, with the time it was fetched at.
+static COMMITTEE_CACHE: Mutex<Option<(Instant, Vec<Committee>)>> = Mutex::new(None);
+/// Incremented (with the cache locked) each time the bot changes a poll count, so that a
+/// committee fetched before the change is not cached.
+static COMMITTEE_VERSION: AtomicU64 = AtomicU64::new(0);
+/// Held while a poll count is incremented, as Directus cannot increment it atomically.
+static POLL_COUNT_UPDATE: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
 
+/// Returns the committee, from the cache if it was fetched less than `COMMITTEE_CACHE_TTL`
+/// seconds ago.
 pub async fn get_committee() -> Result<Vec<Committee>, Error> {
+    if let Some(committee) = cached_committee(Duration::from_secs(config().committee_cache_ttl)) {
+        return Ok(committee);
+    }
+
+    refresh_committee().await
+}
+
+/// Returns the cached committee if it is younger than `max_age`.
+fn cached_committee(max_age: Duration) -> Option<Vec<Committee>> {
+    match &*COMMITTEE_CACHE.lock().unwrap_or_else(|e| e.into_inner()) {
+        Some((fetched_at, committee)) if fetched_at.elapsed() < max_age => Some(committee.clone()),
+        _ => None,
+    }
+}
+
+pub fn invalidate_committee() {
+    *COMMITTEE_CACHE.lock().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+/// Fetches the committee from Directus and updates the cache, unless a poll count was changed in
+/// the meantime.
+pub async fn refresh_committee() -> Result<Vec<Committee>, Error> {
     #[derive(Deserialize, Debug)]
     struct Member {
         member: Committee,
     }
 
-    let response = Client::new()
-        .get(format!(
-            "{}/items/association_memberships?fields=member.id,member.name,member.poll_count",
-            config().directus_url
-        ))
-        .bearer_auth(&config().directus_token)
-        .send()
-        .await?
-        .error_for_status()?;
-
-    let response =
-        serde_json::from_str::<DirectusResponse<Vec<Member>>>(response.text().await?.as_str())?;
-
-    Ok(response.data.into_iter().map(|m| m.member).collect())
-}
-
-pub async fn update_committee(committee: Vec<Committee>) {
-    let mut set = JoinSet::new();
-    for c in committee {
-        set.spawn(
-            Client::new()
-                .patch(format!("{}/items/members/{}", config().directus_url, c.id))
-                .bearer_auth(&config().directus_token)
-                .header("Content-Type", "application/json")
-                .body(format!(r#"{{ "poll_count": {} }}"#, c.poll_count))
-                .send(),
-        );
+    let version = COMMITTEE_VERSION.load(Ordering::SeqCst);
+    let members = client()
+        .get_all_items::<Member>(
+            "association_memberships",
+            &Query::new().fields(&["member.id", "member.name", "member.poll_count"]),
+        )
+        .await?;
+
+    let committee = members.into_iter().map(|m| m.member).collect::<Vec<_>>();
+    let mut cache = COMMITTEE_CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    if COMMITTEE_VERSION.load(Ordering::SeqCst) == version {
+        *cache = Some((Instant::now(), committee.clone()));
     }
 
-    while let Some(r) = set.join_next().await {
-        match r {
-            Err(e) => error!("Join error while updating committee: {e:#?}"),
-            Ok(Err(e)) => error!("Request error while updating committee: {e:#?}"),
-            Ok(_) => {}
-        }
+    Ok(committee)
+}
+
+/// Increments the poll count of the member, and returns the updated member. The count is read
+/// again from Directus rather than taken from the cache, which may be outdated, so that the
+/// changes made since (by the bot or in Directus) are not overwritten.
+pub async fn increment_poll_count(member_id: i32) -> Result<Committee, Error> {
+    #[derive(Serialize, Deserialize)]
+    struct PollCount {
+        poll_count: i32,
+    }
+
+    let _guard = POLL_COUNT_UPDATE.lock().await;
+    let current = client()
+        .get_item::<PollCount>("members", member_id, &Query::new().fields(&["poll_count"]))
+        .await?;
+    let member = client()
+        .patch_item::<_, Committee>(
+            "members",
+            member_id,
+            &PollCount {
+                poll_count: current.poll_count + 1,
+            },
+            &Query::new().fields(&["id", "name", "poll_count"]),
+        )
+        .await?;
+
+    // The poll counts in the cache, and in the committees being fetched, are now outdated
+    let mut cache = COMMITTEE_CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    COMMITTEE_VERSION.fetch_add(1, Ordering::SeqCst);
+    *cache = None;
+
+    Ok(member)
+}
+
+// ---------------------------------- EVENTS ----------------------------------
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Event {
+    pub id: i32,
+    pub title: String,
+    pub description: Option<String>,
+    pub location: Option<String>,
+    #[serde(deserialize_with = "deserialize_date")]
+    pub start: DateTime<Utc>,
+}
+
+const EVENT_FIELDS: [&str; 5] = ["id", "title", "description", "location", "start"];
+
+/// Fetches the events that did not start yet, sorted by start date. Fetches all of them if no
+/// limit is given.
+pub async fn get_upcoming_events(limit: Option<u64>) -> Result<Vec<Event>, Error> {
+    let query = Query::new()
+        .fields(&EVENT_FIELDS)
+        .filter("start", "_gte", "$NOW")
+        .sort("start");
+
+    match limit {
+        Some(limit) => client().get_items("events", &query.limit(limit)).await,
+        None => client().get_all_items("events", &query).await,
+    }
+}
+
+pub async fn get_event(id: i32) -> Result<Event, Error> {
+    client()
+        .get_item("events", id, &Query::new().fields(&EVENT_FIELDS))
+        .await
+}
+
+/// Directus sends `timestamp` fields in RFC 3339, but `datetime` fields without timezone,
+/// in which case they are interpreted in the configured timezone.
+fn deserialize_date<'de, D: Deserializer<'de>>(deserializer: D) -> Result<DateTime<Utc>, D::Error> {
+    let value = String::deserialize(deserializer)?;
+
+    if let Ok(date) = DateTime::parse_from_rfc3339(&value) {
+        return Ok(date.to_utc());
+    }
+
+    NaiveDateTime::parse_from_str(&value, "%Y-%m-%dT%H:%M:%S%.f")
+        .ok()
+        .and_then(|date| config().timezone.from_local_datetime(&date).earliest())
+        .map(|date| date.to_utc())
+        .ok_or_else(|| serde::de::Error::custom(format!("invalid date: {}", value)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_queries_to_params() {
+        assert_eq!(Query::new().to_params(), []);
+        assert_eq!(
+            Query::new().filter("start", "_gte", "$NOW").to_params(),
+            [("filter", r#"{"start":{"_gte":"$NOW"}}"#.to_owned())]
+        );
+        assert_eq!(
+            Query::new()
+                .fields(&["id", "event.name"])
+                .filter("start", "_gte", "$NOW")
+                .filter("event.id", "_eq", 3)
+                .sort("-start")
+                .sort("id")
+                .limit(10)
+                .page(2)
+                .to_params(),
+            [
+                ("fields", "id,event.name".to_owned()),
+                (
+                    "filter",
+                    r#"{"_and":[{"start":{"_gte":"$NOW"}},{"event":{"id":{"_eq":3}}}]}"#.to_owned()
+                ),
+                ("sort", "-start,id".to_owned()),
+                ("limit", "10".to_owned()),
+                ("page", "2".to_owned()),
+            ]
+        );
     }
 }
@@ -0,0 +1,191 @@
+//! Localization of the messages of the bot. The catalogs are the TOML files of `locales/`,
+//! embedded in the binary, which map a key (the path of the entry, e.g. `bureau.question`)
+//! to a message with `{name}` placeholders. Missing entries fall back to French.
+
+use std::{collections::HashMap, fmt::Display, str::FromStr, sync::Arc, sync::OnceLock};
+
+use serde::Deserialize;
+use teloxide::types::{ChatId, Update, User};
+
+use crate::{config::config, storage::LanguageRepository};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Lang {
+    #[default]
+    Fr,
+    En,
+    De,
+}
+
+impl Lang {
+    pub const ALL: [Lang; 3] = [Lang::Fr, Lang::En, Lang::De];
+
+    /// ISO 639-1 code of the language, as used by Telegram.
+    pub fn code(self) -> &'static str {
+        match self {
+            Self::Fr => "fr",
+            Self::En => "en",
+            Self::De => "de",
+        }
+    }
+
+    /// Parses a language code, ignoring the region if any (`fr-CH` is French).
+    pub fn from_code(code: &str) -> Option<Self> {
+        let language = code.split(['-', '_']).next()?.to_lowercase();
+        Self::ALL.into_iter().find(|l| l.code() == language)
+    }
+
+    /// Name of the day of the week, starting at 0 for Monday.
+    pub fn weekday(self, weekday: usize) -> String {
+        translate(self, &format!("weekdays.{weekday}"), &[])
+    }
+
+    fn source(self) -> &'static str {
+        match self {
+            Self::Fr => include_str!("../locales/fr.toml"),
+            Self::En => include_str!("../locales/en.toml"),
+            Self::De => include_str!("../locales/de.toml"),
+        }
+    }
+
+    fn catalog(self) -> &'static HashMap<String, String> {
+        static CATALOGS: OnceLock<HashMap<Lang, HashMap<String, String>>> = OnceLock::new();
+
+        &CATALOGS.get_or_init(|| {
+            Self::ALL
+                .into_iter()
+                .map(|lang| {
+                    let table = lang.source().parse::<toml::Table>().unwrap_or_else(|e| {
+                        panic!("the catalog of {} is invalid: {e}", lang.code())
+                    });
+                    let mut catalog = HashMap::new();
+                    flatten(&mut catalog, "", toml::Value::Table(table));
+                    (lang, catalog)
+                })
+                .collect()
+        })[&self]
+    }
+}
+
+impl FromStr for Lang {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::from_code(s).ok_or_else(|| format!("unsupported language: {s}"))
+    }
+}
+
+impl Display for Lang {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+/// Stores the entries of the table under their path, the items of the arrays being indexed
+/// from 0 (`weekdays.0`).
+fn flatten(catalog: &mut HashMap<String, String>, prefix: &str, value: toml::Value) {
+    let join = |key: &dyn Display| {
+        if prefix.is_empty() {
+            key.to_string()
+        } else {
+            format!("{prefix}.{key}")
+        }
+    };
+
+    match value {
+        toml::Value::Table(table) => {
+            for (key, value) in table {
+                flatten(catalog, &join(&key), value);
+            }
+        }
+        toml::Value::Array(values) => {
+            for (i, value) in values.into_iter().enumerate() {
+                flatten(catalog, &join(&i), value);
+            }
+        }
+        toml::Value::String(message) => {
+            catalog.insert(prefix.to_owned(), message);
+        }
+        value => {
+            catalog.insert(prefix.to_owned(), value.to_string());
+        }
+    }
+}
+
+/// Returns the message of the key in the language, with its placeholders replaced by the
+/// arguments. Use [`t!`](crate::t) rather than calling it directly.
+pub fn translate(lang: Lang, key: &str, args: &[(&str, &(dyn Display + Sync))]) -> String {
+    let Some(message) = lang
+        .catalog()
+        .get(key)
+        .or_else(|| Lang::Fr.catalog().get(key))
+    else {
+        log::warn!("Missing message {key}");
+        return key.to_owned();
+    };
+
+    args.iter().fold(message.clone(), |message, (name, value)| {
+        message.replace(&format!("{{{name}}}"), &value.to_string())
+    })
+}
+
+/// Localizes a message: `t!(lang, "bureau.no_presence", days = 30)`.
+#[macro_export]
+macro_rules! t {
+    ($lang:expr, $key:expr $(, $name:ident = $value:expr)* $(,)?) => {
+        $crate::i18n::translate(
+            $lang,
+            $key,
+            &[$((stringify!($name), &$value as &(dyn ::std::fmt::Display + Sync))),*],
+        )
+    };
+}
+
+/// Language in which to answer an update: the one chosen for the chat (with /lang or in the
+/// config file), else the one chosen by the user, else the one of their Telegram client.
+pub async fn update_language(update: Update, languages: Arc<dyn LanguageRepository>) -> Lang {
+    let chat = update.chat().map(|c| c.id);
+    let user = update.from();
+
+    if let Some(lang) = chat_preference(languages.as_ref(), chat).await {
+        return lang;
+    }
+    if let Some(lang) = user_preference(languages.as_ref(), user).await {
+        return lang;
+    }
+
+    config().default_language
+}
+
+/// Language of the messages sent to the chat outside of a command, e.g. the reminders.
+pub async fn chat_language(languages: &dyn LanguageRepository, chat: ChatId) -> Lang {
+    chat_preference(languages, Some(chat))
+        .await
+        .unwrap_or(config().default_language)
+}
+
+async fn chat_preference(languages: &dyn LanguageRepository, chat: Option<ChatId>) -> Option<Lang> {
+    let chat = chat?;
+    stored_language(languages, &chat.to_string())
+        .await
+        .or_else(|| config().chats.get(&chat)?.language)
+}
+
+async fn user_preference(languages: &dyn LanguageRepository, user: Option<&User>) -> Option<Lang> {
+    let user = user?;
+    match stored_language(languages, &user.id.to_string()).await {
+        Some(lang) => Some(lang),
+        None => Lang::from_code(user.language_code.as_deref()?),
+    }
+}
+
+async fn stored_language(languages: &dyn LanguageRepository, id: &str) -> Option<Lang> {
+    match languages.get_language(id).await {
+        Ok(language) => Lang::from_code(&language?),
+        Err(e) => {
+            log::error!("Could not get the language of {id}: {e}");
+            None
+        }
+    }
+}
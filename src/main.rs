@@ -1,71 +1,151 @@
-use std::sync::Arc;
-
-use config::config;
-use sqlx::{migrate::MigrateDatabase, SqlitePool};
+use clap::Parser;
 use teloxide::{
     dispatching::dialogue::{self, InMemStorage},
     prelude::*,
-    utils::command::BotCommands,
 };
 
 use crate::{
+    cli::{Args, CliCommand, StorageCommand},
+    cmd_authentication::authorization_expiry,
+    cmd_backup::periodic_backups,
+    cmd_bureau::record_bureau_answer,
+    cmd_chats::chat_membership,
+    cmd_events::event_announcements,
+    cmd_help::update_command_menus,
+    cmd_permanence::permanence_reminders,
     cmd_poll::PollState,
-    commands::{command_callback_query_handler, command_message_handler, Command},
-    directus::{update_committee, Committee},
+    commands::{command_callback_query_handler, command_message_handler},
+    i18n::update_language,
+    startup::StartupError,
+    storage::Repositories,
+    webhook::serve_webhook,
 };
 
+mod cli;
+mod cmd_admins;
 mod cmd_authentication;
+mod cmd_backup;
 mod cmd_bureau;
+mod cmd_chats;
+mod cmd_events;
+mod cmd_help;
+mod cmd_language;
+mod cmd_permanence;
+mod cmd_permissions;
 mod cmd_poll;
 mod commands;
 mod config;
 mod directus;
+mod i18n;
+mod rejection;
+mod startup;
+mod storage;
+mod throttle;
+mod webhook;
 
 pub type HandlerResult = Result<(), Box<dyn std::error::Error + Send + Sync>>;
 
-async fn init_db() -> SqlitePool {
-    let database_url = config()
-        .database_url
-        .clone()
-        .unwrap_or_else(|| format!("sqlite://{}/db.sqlite", config::config().data_dir));
+#[tokio::main]
+async fn main() {
+    pretty_env_logger::init();
 
-    if !sqlx::Sqlite::database_exists(&database_url).await.unwrap() {
-        sqlx::Sqlite::create_database(&database_url).await.unwrap();
-    }
+    let args = Args::parse();
+    exit_on_startup_error(startup::load_config(args.config.as_deref()));
 
-    let database = SqlitePool::connect(&database_url).await.unwrap();
-    sqlx::migrate!().run(&database).await.unwrap();
+    let command = args
+        .command
+        .unwrap_or(CliCommand::Storage(StorageCommand::Serve));
+    let result = match command {
+        // The database must not be opened while it is replaced
+        CliCommand::Restore { backup } => cli::restore(&backup).await,
+        CliCommand::Storage(command) => {
+            let repositories = exit_on_startup_error(startup::open_storage().await);
+            cli::run(command, repositories).await
+        }
+    };
 
-    database
+    if let Err(e) = result {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
 }
 
-#[tokio::main]
-async fn main() {
-    pretty_env_logger::init();
+fn exit_on_startup_error<T>(result: Result<T, StartupError>) -> T {
+    result.unwrap_or_else(|e| {
+        log::error!("{e}");
+        std::process::exit(e.exit_code());
+    })
+}
+
+async fn serve(repositories: Repositories) {
+    let bot = exit_on_startup_error(startup::connect().await);
 
-    update_committee(vec![Committee {
-        id: 1,
-        name: "".into(),
-        poll_count: 15,
-    }])
-    .await;
+    log::info!("Setting the command menus");
+    // In the background, as the requests are spaced out to respect the limits of Telegram
+    tokio::spawn({
+        let bot = bot.clone();
+        let repositories = repositories.clone();
+        async move {
+            if let Err(e) = update_command_menus(
+                &bot,
+                repositories.admins.as_ref(),
+                repositories.authorizations.as_ref(),
+                repositories.permissions.as_ref(),
+            )
+            .await
+            {
+                log::error!("Could not set the command menus: {e}");
+            }
+        }
+    });
 
-    log::info!("Loading config files");
-    config::config();
-    let database = init_db().await;
+    log::info!("Starting permanence reminders");
+    tokio::spawn(permanence_reminders(
+        bot.clone(),
+        repositories.permanences.clone(),
+        repositories.languages.clone(),
+    ));
 
-    let bot = Bot::new(config::config().bot_token.clone());
-    bot.set_my_commands(Command::bot_commands()).await.unwrap();
+    log::info!("Starting authorization expiry");
+    tokio::spawn(authorization_expiry(
+        bot.clone(),
+        repositories.admins.clone(),
+        repositories.authorizations.clone(),
+        repositories.permissions.clone(),
+        repositories.languages.clone(),
+    ));
+
+    log::info!("Starting event announcements");
+    tokio::spawn(event_announcements(
+        bot.clone(),
+        repositories.events.clone(),
+        repositories.languages.clone(),
+    ));
+    tokio::spawn(serve_webhook(bot.clone(), repositories.languages.clone()));
+
+    if let Some(backups) = repositories.backups.clone() {
+        log::info!("Starting periodic backups");
+        tokio::spawn(periodic_backups(backups));
+    }
 
     log::info!("Initializing dispatchers");
     let message_handler = Update::filter_message().chain(command_message_handler());
     let callback_handler = Update::filter_callback_query().chain(command_callback_query_handler());
+    let poll_answer_handler = Update::filter_poll_answer().endpoint(record_bureau_answer);
+    let chat_member_handler = Update::filter_my_chat_member().endpoint(chat_membership);
 
     let mut bot_dispatcher = Dispatcher::builder(
         bot,
-        dialogue::enter::<Update, InMemStorage<PollState>, PollState, _>()
-            .branch(message_handler)
-            .branch(callback_handler),
+        dptree::entry()
+            .branch(poll_answer_handler)
+            .branch(chat_member_handler)
+            .branch(
+                dptree::map_async(update_language).chain(
+                    dialogue::enter::<Update, InMemStorage<PollState>, PollState, _>()
+                        .branch(message_handler)
+                        .branch(callback_handler),
+                ),
+            ),
     )
     .default_handler(|_| async move {})
     .error_handler(LoggingErrorHandler::with_custom_text(
@@ -73,7 +153,15 @@ async fn main() {
     ))
     .dependencies(dptree::deps![
         InMemStorage::<PollState>::new(),
-        Arc::new(database)
+        repositories.admins,
+        repositories.authorizations,
+        repositories.permissions,
+        repositories.bureau,
+        repositories.permanences,
+        repositories.events,
+        repositories.languages,
+        repositories.chats,
+        repositories.backups
     ])
     .enable_ctrlc_handler()
     .build();
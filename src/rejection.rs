@@ -0,0 +1,190 @@
+//! Answers to the commands rejected by the access control, so that the users know why nothing
+//! happens, and notifications of the admins about these attempts.
+
+use std::time::Duration;
+
+use teloxide::{
+    requests::Requester,
+    types::{ChatId, Message},
+    Bot,
+};
+
+use crate::{
+    cmd_authentication::format_duration,
+    commands::Command,
+    config::config,
+    i18n::{chat_language, Lang},
+    storage::{AdminRepository, LanguageRepository},
+    t,
+    throttle::Throttle,
+};
+
+/// Why a command was rejected.
+#[derive(Debug, Clone, Copy)]
+pub enum Rejection {
+    /// The chat is not authorized to use the command
+    ChatNotAuthorized,
+    /// The command is reserved to the admins
+    NotAdmin,
+    /// A rule denies the command to the user
+    UserDenied,
+    /// The command was used too recently in the chat or by the user
+    RateLimited { wait: Duration },
+}
+
+impl Rejection {
+    /// Name of the kind of rejection, whatever its details.
+    fn kind(&self) -> &'static str {
+        match self {
+            Self::ChatNotAuthorized => "not_authorized",
+            Self::NotAdmin => "not_admin",
+            Self::UserDenied => "user_denied",
+            Self::RateLimited { .. } => "rate_limited",
+        }
+    }
+}
+
+/// Chats which were recently answered (or whose rejection was notified to the admins), with the
+/// kind of the rejection.
+static THROTTLE: Throttle = Throttle::new();
+
+/// Explains to the user why the command was rejected if `REJECTION_FEEDBACK` is set, and notifies
+/// the admins if `NOTIFY_ADMINS_OF_REJECTIONS` is set. The users are always asked to wait when
+/// they are rate limited. This is done at most once per chat and kind of rejection every
+/// `REJECTION_FEEDBACK_INTERVAL`, so that the bot cannot be made to spam.
+pub async fn reject(
+    bot: &Bot,
+    msg: &Message,
+    command: &Command,
+    rejection: Rejection,
+    lang: Lang,
+    admins: &dyn AdminRepository,
+    languages: &dyn LanguageRepository,
+) {
+    let feedback =
+        config().rejection_feedback || matches!(rejection, Rejection::RateLimited { .. });
+    if !feedback && !config().notify_admins_of_rejections {
+        return;
+    }
+    let interval = Duration::from_secs(config().rejection_feedback_interval);
+    if !THROTTLE.allow(&format!("{} {}", msg.chat.id, rejection.kind()), interval) {
+        return;
+    }
+
+    if feedback {
+        let text = match rejection {
+            Rejection::ChatNotAuthorized => match contact(admins).await {
+                Some(contact) => t!(
+                    lang,
+                    "rejection.not_authorized",
+                    command = command.shortand(),
+                    contact = contact
+                ),
+                None => t!(
+                    lang,
+                    "rejection.not_authorized_no_contact",
+                    command = command.shortand()
+                ),
+            },
+            Rejection::NotAdmin => t!(lang, "rejection.not_admin", command = command.shortand()),
+            Rejection::UserDenied => {
+                t!(lang, "rejection.user_denied", command = command.shortand())
+            }
+            Rejection::RateLimited { wait } => t!(
+                lang,
+                "rejection.rate_limited",
+                command = command.shortand(),
+                time = format_duration(lang, wait.as_secs() as i64)
+            ),
+        };
+        if let Err(e) = bot.send_message(msg.chat.id, text).await {
+            log::error!(
+                "Could not answer the rejected command in {}: {e}",
+                msg.chat.id
+            );
+        }
+    }
+
+    if config().notify_admins_of_rejections {
+        notify_admins(bot, msg, command, rejection, admins, languages).await;
+    }
+}
+
+/// Who to ask for an authorization: `REJECTION_CONTACT`, else the names of the admins.
+async fn contact(admins: &dyn AdminRepository) -> Option<String> {
+    if let Some(contact) = &config().rejection_contact {
+        return Some(contact.clone());
+    }
+
+    match admins.list_admins().await {
+        Ok(admins) if !admins.is_empty() => Some(
+            admins
+                .into_iter()
+                .map(|a| a.name)
+                .collect::<Vec<_>>()
+                .join(", "),
+        ),
+        Ok(_) => None,
+        Err(e) => {
+            log::error!("Could not list the admins: {e}");
+            None
+        }
+    }
+}
+
+async fn notify_admins(
+    bot: &Bot,
+    msg: &Message,
+    command: &Command,
+    rejection: Rejection,
+    admins: &dyn AdminRepository,
+    languages: &dyn LanguageRepository,
+) {
+    let key = match rejection {
+        Rejection::ChatNotAuthorized => "rejection.notification_not_authorized",
+        Rejection::NotAdmin => "rejection.notification_not_admin",
+        Rejection::UserDenied => "rejection.notification_user_denied",
+        // Using a command too often is not worth bothering the admins
+        Rejection::RateLimited { .. } => return,
+    };
+    let admins = match admins.list_admins().await {
+        Ok(admins) => admins,
+        Err(e) => {
+            log::error!("Could not list the admins to notify: {e}");
+            return;
+        }
+    };
+    let user = msg.from.as_ref();
+    let user_name = user.map(|u| u.full_name()).unwrap_or_default();
+    let user_id = user.map(|u| u.id.to_string()).unwrap_or_default();
+
+    for admin in admins {
+        // An admin whose chat is not authorized does not need to be told about it
+        if admin.telegram_id == user_id {
+            continue;
+        }
+        let Ok(admin_chat) = admin.telegram_id.parse::<i64>().map(ChatId) else {
+            continue;
+        };
+
+        let lang = chat_language(languages, admin_chat).await;
+        let chat = msg
+            .chat
+            .title()
+            .map(str::to_owned)
+            .unwrap_or_else(|| t!(lang, "rejection.private_chat"));
+        let text = t!(
+            lang,
+            key,
+            user = user_name,
+            user_id = user_id,
+            command = command.shortand(),
+            chat = chat,
+            chat_id = msg.chat.id
+        );
+
+        if let Err(e) = bot.send_message(admin_chat, text).await {
+            log::warn!("Could not notify the admin {}: {e}", admin.telegram_id);
+        }
+    }
+}
@@ -0,0 +1,91 @@
+//! Startup phase of the bot, which loads the config and checks that the database, Telegram and
+//! Directus can be used before handling any update.
+
+use std::path::Path;
+
+use teloxide::prelude::*;
+
+use crate::{
+    config::{self, config, StorageBackend},
+    directus::{self, refresh_committee},
+    storage::{self, init_storage, Repositories},
+};
+
+#[derive(Debug)]
+pub enum StartupError {
+    Config(Vec<String>),
+    Storage(storage::Error),
+    Telegram(teloxide::RequestError),
+    Directus(directus::Error),
+}
+
+impl StartupError {
+    /// Exit code of the process, distinct for each kind of error so that it can be told apart by
+    /// the supervisor.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::Config(_) => 2,
+            Self::Storage(_) => 3,
+            Self::Telegram(_) => 4,
+            Self::Directus(_) => 5,
+        }
+    }
+}
+
+impl std::fmt::Display for StartupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Config(errors) => {
+                write!(f, "The configuration is invalid:")?;
+                for error in errors {
+                    write!(f, "\n  - {error}")?;
+                }
+                Ok(())
+            }
+            Self::Storage(e) => write!(f, "The database cannot be used: {e}"),
+            Self::Telegram(e) => write!(
+                f,
+                "Could not connect to Telegram, check that BOT_TOKEN is valid: {e}"
+            ),
+            Self::Directus(e) => write!(
+                f,
+                "Could not fetch the committee from Directus, check DIRECTUS_URL and \
+                DIRECTUS_TOKEN: {e}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for StartupError {}
+
+pub fn load_config(config_path: Option<&Path>) -> Result<(), StartupError> {
+    log::info!("Loading config");
+    config::load(config_path).map_err(StartupError::Config)?;
+
+    Ok(())
+}
+
+/// Connects to the storage, which is all the administrative commands of the command line need.
+pub async fn open_storage() -> Result<Repositories, StartupError> {
+    log::info!("Connecting to the storage");
+    if config().storage == StorageBackend::Memory {
+        log::warn!(
+            "STORAGE is memory: the admins, authorizations, permanences and every other change \
+             will be LOST when the bot stops. Only use it for tests and local development"
+        );
+    }
+    init_storage().await.map_err(StartupError::Storage)
+}
+
+/// Checks the Telegram and Directus credentials, before serving the bot.
+pub async fn connect() -> Result<Bot, StartupError> {
+    log::info!("Connecting to Telegram");
+    let bot = Bot::new(config().bot_token.clone());
+    let me = bot.get_me().await.map_err(StartupError::Telegram)?;
+    log::info!("Logged in as @{}", me.username());
+
+    log::info!("Connecting to Directus");
+    refresh_committee().await.map_err(StartupError::Directus)?;
+
+    Ok(bot)
+}
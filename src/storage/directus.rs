@@ -0,0 +1,480 @@
+use async_trait::async_trait;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    Admin, AdminRemoval, AdminRepository, Authorization, AuthorizationRepository, Error,
+    Permission, PermissionRepository,
+};
+use crate::directus::{client, Query};
+
+/// Collection containing the admins, with the fields `telegram_id`, `name`, `username` and
+/// `super_admin`.
+const ADMINS_COLLECTION: &str = "roboclic_admins";
+/// Collection containing the authorizations, with the fields `chat_id`, `command` and
+/// `expires_at` (Unix timestamp, empty for the permanent ones).
+const AUTHORIZATIONS_COLLECTION: &str = "roboclic_authorizations";
+/// Collection containing the rules of the users, with the fields `user_id`, `chat_id` (empty for
+/// every chat), `command` and `allowed`.
+const PERMISSIONS_COLLECTION: &str = "roboclic_permissions";
+
+/// Stores the admins, authorizations and permissions in Directus, so that they can be managed from its
+/// admin UI.
+pub struct DirectusStorage;
+
+#[derive(Deserialize, Debug)]
+struct Id {
+    id: i32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct AdminItem {
+    telegram_id: String,
+    name: String,
+    username: Option<String>,
+    #[serde(default)]
+    super_admin: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct AdminId {
+    id: i32,
+    telegram_id: String,
+}
+
+/// Field of an admin updated by [`AdminRepository::set_super_admin`].
+#[derive(Serialize, Debug)]
+struct SuperAdminPatch {
+    super_admin: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct AuthorizationItem {
+    chat_id: String,
+    command: String,
+    expires_at: Option<i64>,
+}
+
+impl From<AuthorizationItem> for Authorization {
+    fn from(item: AuthorizationItem) -> Self {
+        Self {
+            chat_id: item.chat_id,
+            command: item.command,
+            expires_at: item.expires_at,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct PermissionItem {
+    user_id: String,
+    chat_id: String,
+    command: String,
+    allowed: bool,
+}
+
+impl From<PermissionItem> for Permission {
+    fn from(item: PermissionItem) -> Self {
+        Self {
+            user_id: item.user_id,
+            chat_id: item.chat_id,
+            command: item.command,
+            allowed: item.allowed,
+        }
+    }
+}
+
+impl DirectusStorage {
+    /// Deletes the items of the collection whose `chat_id` is the chat, and returns how many
+    /// there were.
+    async fn delete_chat_items(&self, collection: &str, chat_id: &str) -> Result<u64, Error> {
+        let ids = client()
+            .get_all_items::<Id>(
+                collection,
+                &Query::new()
+                    .fields(&["id"])
+                    .filter("chat_id", "_eq", chat_id),
+            )
+            .await?
+            .into_iter()
+            .map(|i| i.id)
+            .collect::<Vec<_>>();
+
+        if !ids.is_empty() {
+            client().delete_items(collection, &ids).await?;
+        }
+
+        Ok(ids.len() as u64)
+    }
+
+    async fn authorization_ids(&self, chat_id: &str, command: &str) -> Result<Vec<i32>, Error> {
+        Ok(client()
+            .get_all_items::<Id>(
+                AUTHORIZATIONS_COLLECTION,
+                &Query::new()
+                    .fields(&["id"])
+                    .filter("chat_id", "_eq", chat_id)
+                    .filter("command", "_eq", command),
+            )
+            .await?
+            .into_iter()
+            .map(|i| i.id)
+            .collect())
+    }
+
+    async fn permission_ids(
+        &self,
+        user_id: &str,
+        chat_id: &str,
+        command: &str,
+    ) -> Result<Vec<i32>, Error> {
+        Ok(client()
+            .get_all_items::<Id>(
+                PERMISSIONS_COLLECTION,
+                &Query::new()
+                    .fields(&["id"])
+                    .filter("user_id", "_eq", user_id)
+                    .filter("chat_id", "_eq", chat_id)
+                    .filter("command", "_eq", command),
+            )
+            .await?
+            .into_iter()
+            .map(|i| i.id)
+            .collect())
+    }
+}
+
+#[async_trait]
+impl AdminRepository for DirectusStorage {
+    async fn add_admin(&self, admin: &Admin) -> Result<(), Error> {
+        client()
+            .create_item::<_, Id>(
+                ADMINS_COLLECTION,
+                &AdminItem {
+                    telegram_id: admin.telegram_id.clone(),
+                    name: admin.name.clone(),
+                    username: admin.username.clone(),
+                    super_admin: admin.super_admin,
+                },
+                &Query::new().fields(&["id"]),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn list_admins(&self) -> Result<Vec<Admin>, Error> {
+        Ok(client()
+            .get_all_items::<AdminItem>(
+                ADMINS_COLLECTION,
+                &Query::new().fields(&["telegram_id", "name", "username", "super_admin"]),
+            )
+            .await?
+            .into_iter()
+            .map(|a| Admin {
+                telegram_id: a.telegram_id,
+                name: a.name,
+                username: a.username,
+                super_admin: a.super_admin,
+            })
+            .collect())
+    }
+
+    async fn is_admin(&self, telegram_id: &str) -> Result<bool, Error> {
+        Ok(!client()
+            .get_items::<Id>(
+                ADMINS_COLLECTION,
+                &Query::new()
+                    .fields(&["id"])
+                    .filter("telegram_id", "_eq", telegram_id)
+                    .limit(1),
+            )
+            .await?
+            .is_empty())
+    }
+
+    async fn remove_admin(&self, telegram_id: &str) -> Result<AdminRemoval, Error> {
+        // Directus has no transactions: the admins are counted right before the removal, which
+        // leaves a short window for concurrent removals
+        let admins = client()
+            .get_all_items::<AdminId>(
+                ADMINS_COLLECTION,
+                &Query::new().fields(&["id", "telegram_id"]),
+            )
+            .await?;
+        let ids = admins
+            .iter()
+            .filter(|a| a.telegram_id == telegram_id)
+            .map(|a| a.id)
+            .collect::<Vec<_>>();
+
+        if ids.is_empty() {
+            return Ok(AdminRemoval::NotAdmin);
+        }
+        if admins.len() <= ids.len() {
+            return Ok(AdminRemoval::LastAdmin);
+        }
+        client().delete_items(ADMINS_COLLECTION, &ids).await?;
+
+        Ok(AdminRemoval::Removed)
+    }
+
+    async fn set_super_admin(&self, telegram_id: &str, super_admin: bool) -> Result<bool, Error> {
+        let ids = client()
+            .get_all_items::<Id>(
+                ADMINS_COLLECTION,
+                &Query::new()
+                    .fields(&["id"])
+                    .filter("telegram_id", "_eq", telegram_id),
+            )
+            .await?;
+
+        for id in &ids {
+            client()
+                .patch_item::<_, Id>(
+                    ADMINS_COLLECTION,
+                    id.id,
+                    &SuperAdminPatch { super_admin },
+                    &Query::new().fields(&["id"]),
+                )
+                .await?;
+        }
+
+        Ok(!ids.is_empty())
+    }
+}
+
+#[async_trait]
+impl AuthorizationRepository for DirectusStorage {
+    async fn is_authorized(&self, chat_id: &str, command: &str) -> Result<bool, Error> {
+        let now = Utc::now().timestamp();
+        Ok(client()
+            .get_all_items::<AuthorizationItem>(
+                AUTHORIZATIONS_COLLECTION,
+                &Query::new()
+                    .fields(&["chat_id", "command", "expires_at"])
+                    .filter("chat_id", "_eq", chat_id)
+                    .filter("command", "_eq", command),
+            )
+            .await?
+            .iter()
+            .any(|a| a.expires_at.is_none_or(|expires_at| expires_at > now)))
+    }
+
+    async fn authorize(
+        &self,
+        chat_id: &str,
+        command: &str,
+        expires_at: Option<i64>,
+    ) -> Result<bool, Error> {
+        let ids = self.authorization_ids(chat_id, command).await?;
+        let item = AuthorizationItem {
+            chat_id: chat_id.to_owned(),
+            command: command.to_owned(),
+            expires_at,
+        };
+
+        match ids.first() {
+            Some(id) => {
+                client()
+                    .patch_item::<_, Id>(
+                        AUTHORIZATIONS_COLLECTION,
+                        id,
+                        &item,
+                        &Query::new().fields(&["id"]),
+                    )
+                    .await?;
+                Ok(false)
+            }
+            None => {
+                client()
+                    .create_item::<_, Id>(
+                        AUTHORIZATIONS_COLLECTION,
+                        &item,
+                        &Query::new().fields(&["id"]),
+                    )
+                    .await?;
+                Ok(true)
+            }
+        }
+    }
+
+    async fn unauthorize(&self, chat_id: &str, command: &str) -> Result<bool, Error> {
+        let ids = self.authorization_ids(chat_id, command).await?;
+
+        if ids.is_empty() {
+            return Ok(false);
+        }
+
+        client()
+            .delete_items(AUTHORIZATIONS_COLLECTION, &ids)
+            .await?;
+
+        Ok(true)
+    }
+
+    async fn unauthorize_chat(&self, chat_id: &str) -> Result<u64, Error> {
+        self.delete_chat_items(AUTHORIZATIONS_COLLECTION, chat_id)
+            .await
+    }
+
+    async fn list_authorizations(&self, chat_id: &str) -> Result<Vec<Authorization>, Error> {
+        Ok(client()
+            .get_all_items::<AuthorizationItem>(
+                AUTHORIZATIONS_COLLECTION,
+                &Query::new()
+                    .fields(&["chat_id", "command", "expires_at"])
+                    .filter("chat_id", "_eq", chat_id)
+                    .sort("command"),
+            )
+            .await?
+            .into_iter()
+            .map(Authorization::from)
+            .collect())
+    }
+
+    async fn list_all_authorizations(&self) -> Result<Vec<Authorization>, Error> {
+        Ok(client()
+            .get_all_items::<AuthorizationItem>(
+                AUTHORIZATIONS_COLLECTION,
+                &Query::new()
+                    .fields(&["chat_id", "command", "expires_at"])
+                    .sort("chat_id")
+                    .sort("command"),
+            )
+            .await?
+            .into_iter()
+            .map(Authorization::from)
+            .collect())
+    }
+
+    async fn expire_authorizations(&self, now: i64) -> Result<Vec<Authorization>, Error> {
+        #[derive(Deserialize)]
+        struct ExpiredItem {
+            id: i32,
+            #[serde(flatten)]
+            item: AuthorizationItem,
+        }
+
+        let expired = client()
+            .get_all_items::<ExpiredItem>(
+                AUTHORIZATIONS_COLLECTION,
+                &Query::new()
+                    .fields(&["id", "chat_id", "command", "expires_at"])
+                    .filter("expires_at", "_lte", now),
+            )
+            .await?;
+
+        if !expired.is_empty() {
+            let ids = expired.iter().map(|e| e.id).collect::<Vec<_>>();
+            client()
+                .delete_items(AUTHORIZATIONS_COLLECTION, &ids)
+                .await?;
+        }
+
+        Ok(expired
+            .into_iter()
+            .map(|e| Authorization::from(e.item))
+            .collect())
+    }
+}
+
+#[async_trait]
+impl PermissionRepository for DirectusStorage {
+    async fn set_permission(&self, permission: &Permission) -> Result<(), Error> {
+        let ids = self
+            .permission_ids(
+                &permission.user_id,
+                &permission.chat_id,
+                &permission.command,
+            )
+            .await?;
+        let item = PermissionItem {
+            user_id: permission.user_id.clone(),
+            chat_id: permission.chat_id.clone(),
+            command: permission.command.clone(),
+            allowed: permission.allowed,
+        };
+
+        match ids.first() {
+            Some(id) => {
+                client()
+                    .patch_item::<_, Id>(
+                        PERMISSIONS_COLLECTION,
+                        id,
+                        &item,
+                        &Query::new().fields(&["id"]),
+                    )
+                    .await?;
+            }
+            None => {
+                client()
+                    .create_item::<_, Id>(
+                        PERMISSIONS_COLLECTION,
+                        &item,
+                        &Query::new().fields(&["id"]),
+                    )
+                    .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn remove_permission(
+        &self,
+        user_id: &str,
+        chat_id: &str,
+        command: &str,
+    ) -> Result<bool, Error> {
+        let ids = self.permission_ids(user_id, chat_id, command).await?;
+
+        if ids.is_empty() {
+            return Ok(false);
+        }
+
+        client().delete_items(PERMISSIONS_COLLECTION, &ids).await?;
+
+        Ok(true)
+    }
+
+    async fn remove_chat_permissions(&self, chat_id: &str) -> Result<u64, Error> {
+        if chat_id.is_empty() {
+            return Ok(0);
+        }
+        self.delete_chat_items(PERMISSIONS_COLLECTION, chat_id)
+            .await
+    }
+
+    async fn list_user_permissions(&self, user_id: &str) -> Result<Vec<Permission>, Error> {
+        Ok(client()
+            .get_all_items::<PermissionItem>(
+                PERMISSIONS_COLLECTION,
+                &Query::new()
+                    .fields(&["user_id", "chat_id", "command", "allowed"])
+                    .filter("user_id", "_eq", user_id)
+                    .sort("chat_id")
+                    .sort("command"),
+            )
+            .await?
+            .into_iter()
+            .map(Permission::from)
+            .collect())
+    }
+
+    async fn list_permissions(&self) -> Result<Vec<Permission>, Error> {
+        Ok(client()
+            .get_all_items::<PermissionItem>(
+                PERMISSIONS_COLLECTION,
+                &Query::new()
+                    .fields(&["user_id", "chat_id", "command", "allowed"])
+                    .sort("user_id")
+                    .sort("chat_id")
+                    .sort("command"),
+            )
+            .await?
+            .into_iter()
+            .map(Permission::from)
+            .collect())
+    }
+}
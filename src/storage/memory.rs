@@ -0,0 +1,504 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    sync::{Mutex, MutexGuard},
+};
+
+use async_trait::async_trait;
+use chrono::Utc;
+
+use super::{
+    Admin, AdminRemoval, AdminRepository, Assignee, Authorization, AuthorizationRepository,
+    BureauAnswer, BureauRepository, ChatRepository, Error, EventRepository, KnownChat,
+    LanguagePreference, LanguageRepository, PermanenceRepository, PermanenceSlot, Permission,
+    PermissionRepository,
+};
+
+/// Keeps all the data in memory, so it is lost when the bot stops. Meant for tests and local
+/// development.
+#[derive(Default)]
+pub struct MemoryStorage {
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    admins: Vec<Admin>,
+    /// Maps the (chat id, command) to the expiry of the authorization.
+    authorizations: BTreeMap<(String, String), Option<i64>>,
+    /// Maps the (user id, chat id, command) to whether it is allowed.
+    permissions: BTreeMap<(String, String, String), bool>,
+    /// Maps the poll id to the chat id and the time it was sent at.
+    bureau_polls: HashMap<String, (String, i64)>,
+    /// Maps the (poll id, telegram id) to the answer.
+    bureau_answers: HashMap<(String, String), BureauAnswer>,
+    permanence_slots: BTreeMap<i64, PermanenceSlot>,
+    next_slot_id: i64,
+    event_subscriptions: BTreeSet<String>,
+    /// Maps the id of the announced events to whether they were reminded.
+    announced_events: HashMap<i32, bool>,
+    /// Maps the chat or telegram id to the language code.
+    languages: BTreeMap<String, String>,
+    chats: BTreeMap<String, KnownChat>,
+}
+
+impl MemoryStorage {
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[async_trait]
+impl AdminRepository for MemoryStorage {
+    async fn add_admin(&self, admin: &Admin) -> Result<(), Error> {
+        self.state().admins.push(admin.clone());
+        Ok(())
+    }
+
+    async fn list_admins(&self) -> Result<Vec<Admin>, Error> {
+        Ok(self.state().admins.clone())
+    }
+
+    async fn is_admin(&self, telegram_id: &str) -> Result<bool, Error> {
+        Ok(self
+            .state()
+            .admins
+            .iter()
+            .any(|a| a.telegram_id == telegram_id))
+    }
+
+    async fn remove_admin(&self, telegram_id: &str) -> Result<AdminRemoval, Error> {
+        let mut state = self.state();
+        if !state.admins.iter().any(|a| a.telegram_id == telegram_id) {
+            return Ok(AdminRemoval::NotAdmin);
+        }
+        if state.admins.len() <= 1 {
+            return Ok(AdminRemoval::LastAdmin);
+        }
+        state.admins.retain(|a| a.telegram_id != telegram_id);
+        Ok(AdminRemoval::Removed)
+    }
+
+    async fn set_super_admin(&self, telegram_id: &str, super_admin: bool) -> Result<bool, Error> {
+        match self
+            .state()
+            .admins
+            .iter_mut()
+            .find(|a| a.telegram_id == telegram_id)
+        {
+            Some(admin) => {
+                admin.super_admin = super_admin;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+#[async_trait]
+impl AuthorizationRepository for MemoryStorage {
+    async fn is_authorized(&self, chat_id: &str, command: &str) -> Result<bool, Error> {
+        let now = Utc::now().timestamp();
+        Ok(self
+            .state()
+            .authorizations
+            .get(&(chat_id.to_owned(), command.to_owned()))
+            .is_some_and(|expires_at| expires_at.is_none_or(|expires_at| expires_at > now)))
+    }
+
+    async fn authorize(
+        &self,
+        chat_id: &str,
+        command: &str,
+        expires_at: Option<i64>,
+    ) -> Result<bool, Error> {
+        Ok(self
+            .state()
+            .authorizations
+            .insert((chat_id.to_owned(), command.to_owned()), expires_at)
+            .is_none())
+    }
+
+    async fn unauthorize(&self, chat_id: &str, command: &str) -> Result<bool, Error> {
+        Ok(self
+            .state()
+            .authorizations
+            .remove(&(chat_id.to_owned(), command.to_owned()))
+            .is_some())
+    }
+
+    async fn unauthorize_chat(&self, chat_id: &str) -> Result<u64, Error> {
+        let mut state = self.state();
+        let count = state.authorizations.len();
+        state.authorizations.retain(|(chat, _), _| chat != chat_id);
+        Ok((count - state.authorizations.len()) as u64)
+    }
+
+    async fn list_authorizations(&self, chat_id: &str) -> Result<Vec<Authorization>, Error> {
+        Ok(self
+            .list_all_authorizations()
+            .await?
+            .into_iter()
+            .filter(|a| a.chat_id == chat_id)
+            .collect())
+    }
+
+    async fn list_all_authorizations(&self) -> Result<Vec<Authorization>, Error> {
+        Ok(self
+            .state()
+            .authorizations
+            .iter()
+            .map(|((chat_id, command), expires_at)| Authorization {
+                chat_id: chat_id.clone(),
+                command: command.clone(),
+                expires_at: *expires_at,
+            })
+            .collect())
+    }
+
+    async fn expire_authorizations(&self, now: i64) -> Result<Vec<Authorization>, Error> {
+        let mut state = self.state();
+        let mut expired = Vec::new();
+        state
+            .authorizations
+            .retain(|(chat_id, command), expires_at| match *expires_at {
+                Some(expiry) if expiry <= now => {
+                    expired.push(Authorization {
+                        chat_id: chat_id.clone(),
+                        command: command.clone(),
+                        expires_at: Some(expiry),
+                    });
+                    false
+                }
+                _ => true,
+            });
+        Ok(expired)
+    }
+}
+
+#[async_trait]
+impl PermissionRepository for MemoryStorage {
+    async fn set_permission(&self, permission: &Permission) -> Result<(), Error> {
+        self.state().permissions.insert(
+            (
+                permission.user_id.clone(),
+                permission.chat_id.clone(),
+                permission.command.clone(),
+            ),
+            permission.allowed,
+        );
+        Ok(())
+    }
+
+    async fn remove_permission(
+        &self,
+        user_id: &str,
+        chat_id: &str,
+        command: &str,
+    ) -> Result<bool, Error> {
+        Ok(self
+            .state()
+            .permissions
+            .remove(&(user_id.to_owned(), chat_id.to_owned(), command.to_owned()))
+            .is_some())
+    }
+
+    async fn remove_chat_permissions(&self, chat_id: &str) -> Result<u64, Error> {
+        let mut state = self.state();
+        let count = state.permissions.len();
+        state
+            .permissions
+            .retain(|(_, chat, _), _| chat.is_empty() || chat != chat_id);
+        Ok((count - state.permissions.len()) as u64)
+    }
+
+    async fn list_user_permissions(&self, user_id: &str) -> Result<Vec<Permission>, Error> {
+        Ok(self
+            .list_permissions()
+            .await?
+            .into_iter()
+            .filter(|p| p.user_id == user_id)
+            .collect())
+    }
+
+    async fn list_permissions(&self) -> Result<Vec<Permission>, Error> {
+        Ok(self
+            .state()
+            .permissions
+            .iter()
+            .map(|((user_id, chat_id, command), allowed)| Permission {
+                user_id: user_id.clone(),
+                chat_id: chat_id.clone(),
+                command: command.clone(),
+                allowed: *allowed,
+            })
+            .collect())
+    }
+}
+
+#[async_trait]
+impl BureauRepository for MemoryStorage {
+    async fn add_bureau_poll(
+        &self,
+        poll_id: &str,
+        chat_id: &str,
+        sent_at: i64,
+    ) -> Result<(), Error> {
+        self.state()
+            .bureau_polls
+            .insert(poll_id.to_owned(), (chat_id.to_owned(), sent_at));
+        Ok(())
+    }
+
+    async fn record_bureau_answer(
+        &self,
+        poll_id: &str,
+        telegram_id: &str,
+        name: &str,
+        option_id: Option<i64>,
+        answered_at: i64,
+    ) -> Result<(), Error> {
+        let mut state = self.state();
+        let Some(&(_, poll_sent_at)) = state.bureau_polls.get(poll_id) else {
+            return Ok(());
+        };
+
+        let key = (poll_id.to_owned(), telegram_id.to_owned());
+        match option_id {
+            None => {
+                state.bureau_answers.remove(&key);
+            }
+            Some(option_id) => {
+                state.bureau_answers.insert(
+                    key,
+                    BureauAnswer {
+                        poll_sent_at,
+                        telegram_id: telegram_id.to_owned(),
+                        name: name.to_owned(),
+                        option_id,
+                        answered_at,
+                    },
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn list_bureau_answers(
+        &self,
+        chat_id: &str,
+        since: i64,
+    ) -> Result<Vec<BureauAnswer>, Error> {
+        let state = self.state();
+        let mut answers = state
+            .bureau_answers
+            .iter()
+            .filter(|((poll_id, _), answer)| {
+                answer.answered_at >= since
+                    && state
+                        .bureau_polls
+                        .get(poll_id)
+                        .is_some_and(|(c, _)| c == chat_id)
+            })
+            .map(|(_, answer)| answer.clone())
+            .collect::<Vec<_>>();
+        answers.sort_by_key(|a| a.answered_at);
+
+        Ok(answers)
+    }
+}
+
+#[async_trait]
+impl PermanenceRepository for MemoryStorage {
+    async fn list_slots(&self, chat_id: Option<&str>) -> Result<Vec<PermanenceSlot>, Error> {
+        let mut slots = self
+            .state()
+            .permanence_slots
+            .values()
+            .filter(|s| chat_id.is_none_or(|c| s.chat_id == c))
+            .cloned()
+            .collect::<Vec<_>>();
+        slots.sort_by_key(|s| (s.weekday, s.start_minute, s.id));
+        for slot in &mut slots {
+            slot.assignees.sort_by(|a, b| a.name.cmp(&b.name));
+        }
+
+        Ok(slots)
+    }
+
+    async fn add_slot(
+        &self,
+        chat_id: &str,
+        weekday: i64,
+        start_minute: i64,
+        duration: i64,
+    ) -> Result<i64, Error> {
+        let mut state = self.state();
+        state.next_slot_id += 1;
+        let id = state.next_slot_id;
+        state.permanence_slots.insert(
+            id,
+            PermanenceSlot {
+                id,
+                chat_id: chat_id.to_owned(),
+                weekday,
+                start_minute,
+                duration,
+                last_reminder: None,
+                assignees: vec![],
+            },
+        );
+
+        Ok(id)
+    }
+
+    async fn remove_slot(&self, chat_id: &str, slot_id: i64) -> Result<bool, Error> {
+        let mut state = self.state();
+        match state.permanence_slots.get(&slot_id) {
+            Some(slot) if slot.chat_id == chat_id => {
+                state.permanence_slots.remove(&slot_id);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn remove_chat_slots(&self, chat_id: &str) -> Result<u64, Error> {
+        let mut state = self.state();
+        let count = state.permanence_slots.len();
+        state
+            .permanence_slots
+            .retain(|_, slot| slot.chat_id != chat_id);
+        Ok((count - state.permanence_slots.len()) as u64)
+    }
+
+    async fn toggle_signup(
+        &self,
+        chat_id: &str,
+        slot_id: i64,
+        telegram_id: &str,
+        name: &str,
+    ) -> Result<Option<bool>, Error> {
+        let mut state = self.state();
+        let Some(slot) = state
+            .permanence_slots
+            .get_mut(&slot_id)
+            .filter(|s| s.chat_id == chat_id)
+        else {
+            return Ok(None);
+        };
+
+        let count = slot.assignees.len();
+        slot.assignees.retain(|a| a.telegram_id != telegram_id);
+        if slot.assignees.len() < count {
+            return Ok(Some(false));
+        }
+
+        slot.assignees.push(Assignee {
+            telegram_id: telegram_id.to_owned(),
+            name: name.to_owned(),
+        });
+        Ok(Some(true))
+    }
+
+    async fn set_last_reminder(&self, slot_id: i64, timestamp: i64) -> Result<(), Error> {
+        if let Some(slot) = self.state().permanence_slots.get_mut(&slot_id) {
+            slot.last_reminder = Some(timestamp);
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl EventRepository for MemoryStorage {
+    async fn subscribe_events(&self, chat_id: &str) -> Result<bool, Error> {
+        Ok(self.state().event_subscriptions.insert(chat_id.to_owned()))
+    }
+
+    async fn unsubscribe_events(&self, chat_id: &str) -> Result<bool, Error> {
+        Ok(self.state().event_subscriptions.remove(chat_id))
+    }
+
+    async fn list_event_subscriptions(&self) -> Result<Vec<String>, Error> {
+        Ok(self.state().event_subscriptions.iter().cloned().collect())
+    }
+
+    async fn has_announced_events(&self) -> Result<bool, Error> {
+        Ok(!self.state().announced_events.is_empty())
+    }
+
+    async fn event_reminded(&self, event_id: i32) -> Result<Option<bool>, Error> {
+        Ok(self.state().announced_events.get(&event_id).copied())
+    }
+
+    async fn mark_event_announced(&self, event_id: i32) -> Result<(), Error> {
+        self.state()
+            .announced_events
+            .entry(event_id)
+            .or_insert(false);
+        Ok(())
+    }
+
+    async fn mark_event_reminded(&self, event_id: i32) -> Result<(), Error> {
+        self.state().announced_events.insert(event_id, true);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl LanguageRepository for MemoryStorage {
+    async fn get_language(&self, id: &str) -> Result<Option<String>, Error> {
+        Ok(self.state().languages.get(id).cloned())
+    }
+
+    async fn set_language(&self, id: &str, language: &str) -> Result<(), Error> {
+        self.state()
+            .languages
+            .insert(id.to_owned(), language.to_owned());
+        Ok(())
+    }
+
+    async fn list_languages(&self) -> Result<Vec<LanguagePreference>, Error> {
+        Ok(self
+            .state()
+            .languages
+            .iter()
+            .map(|(id, language)| LanguagePreference {
+                id: id.clone(),
+                language: language.clone(),
+            })
+            .collect())
+    }
+}
+
+#[async_trait]
+impl ChatRepository for MemoryStorage {
+    async fn save_chat(&self, chat: &KnownChat) -> Result<(), Error> {
+        let mut state = self.state();
+        let joined_at = chat
+            .joined_at
+            .or_else(|| state.chats.get(&chat.chat_id)?.joined_at);
+        state.chats.insert(
+            chat.chat_id.clone(),
+            KnownChat {
+                joined_at,
+                ..chat.clone()
+            },
+        );
+        Ok(())
+    }
+
+    async fn remove_chat(&self, chat_id: &str) -> Result<bool, Error> {
+        Ok(self.state().chats.remove(chat_id).is_some())
+    }
+
+    async fn list_chats(&self) -> Result<Vec<KnownChat>, Error> {
+        let mut chats = self.state().chats.values().cloned().collect::<Vec<_>>();
+        chats.sort_by(|a, b| {
+            a.title
+                .cmp(&b.title)
+                .then_with(|| a.chat_id.cmp(&b.chat_id))
+        });
+        Ok(chats)
+    }
+}
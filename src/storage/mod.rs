@@ -0,0 +1,695 @@
+//! Storage of the data of the bot, behind repository traits so that the backend can be
+//! swapped. The database (SQLite or PostgreSQL, or memory for tests) holds everything, but the admins and
+//! authorizations can also be stored in Directus, as selected by the `STORAGE` setting.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::config::{config, StorageBackend};
+
+mod directus;
+mod memory;
+mod postgres;
+mod snapshot;
+mod sqlite;
+
+pub use directus::DirectusStorage;
+pub use memory::MemoryStorage;
+pub use postgres::PostgresStorage;
+pub use snapshot::Snapshot;
+pub use sqlite::SqliteStorage;
+
+#[derive(Debug)]
+pub enum Error {
+    Database(sqlx::Error),
+    Migration(sqlx::migrate::MigrateError),
+    Directus(crate::directus::Error),
+    Io(std::io::Error),
+    Backup(String),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Database(e) => write!(f, "database error: {e}"),
+            Self::Migration(e) => write!(f, "could not migrate the database: {e}"),
+            Self::Directus(e) => write!(f, "{e}"),
+            Self::Io(e) => write!(f, "{e}"),
+            Self::Backup(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<sqlx::Error> for Error {
+    fn from(value: sqlx::Error) -> Self {
+        Self::Database(value)
+    }
+}
+
+impl From<sqlx::migrate::MigrateError> for Error {
+    fn from(value: sqlx::migrate::MigrateError) -> Self {
+        Self::Migration(value)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(value: std::io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+impl From<crate::directus::Error> for Error {
+    fn from(value: crate::directus::Error) -> Self {
+        Self::Directus(value)
+    }
+}
+
+// ---------------------------------- MODELS ----------------------------------
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Admin {
+    pub telegram_id: String,
+    pub name: String,
+    /// Telegram @username (without the @), if the admin has one
+    #[serde(default)]
+    pub username: Option<String>,
+    /// Super admins can only be removed by other super admins
+    #[serde(default)]
+    pub super_admin: bool,
+}
+
+/// Result of [`AdminRepository::remove_admin`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdminRemoval {
+    Removed,
+    NotAdmin,
+    /// Refused, as the bot could no longer be managed
+    LastAdmin,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Authorization {
+    pub chat_id: String,
+    pub command: String,
+    /// Unix timestamp at which the authorization expires, `None` if it is permanent
+    #[serde(default)]
+    pub expires_at: Option<i64>,
+}
+
+/// Answer of a user to a /bureau poll.
+#[derive(Debug, Clone)]
+pub struct BureauAnswer {
+    pub poll_sent_at: i64,
+    pub telegram_id: String,
+    pub name: String,
+    pub option_id: i64,
+    pub answered_at: i64,
+}
+
+/// Weekly permanence slot, with the people signed up for it.
+#[derive(Debug, Clone)]
+pub struct PermanenceSlot {
+    pub id: i64,
+    pub chat_id: String,
+    /// Day of the week, starting at 0 for Monday.
+    pub weekday: i64,
+    /// Start time, in minutes since midnight.
+    pub start_minute: i64,
+    /// Duration, in minutes.
+    pub duration: i64,
+    /// Timestamp of the start of the last occurrence for which a reminder was sent.
+    pub last_reminder: Option<i64>,
+    pub assignees: Vec<Assignee>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Assignee {
+    pub telegram_id: String,
+    pub name: String,
+}
+
+/// Rule allowing or denying a user to use a restricted command, in a chat or everywhere. The
+/// authorizations of the chats apply to the users without rules.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Permission {
+    pub user_id: String,
+    /// Empty for a rule applying in every chat
+    pub chat_id: String,
+    pub command: String,
+    pub allowed: bool,
+}
+
+/// Group the bot is a member of, so that its authorizations can be managed from another chat.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KnownChat {
+    pub chat_id: String,
+    pub title: String,
+    /// `group`, `supergroup` or `channel`, empty if unknown
+    pub kind: String,
+    /// When the bot was added to the chat, if it was seen
+    pub joined_at: Option<i64>,
+}
+
+/// Language chosen with /lang, for a chat or a user.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LanguagePreference {
+    pub id: String,
+    pub language: String,
+}
+
+// ------------------------------- REPOSITORIES -------------------------------
+
+#[async_trait]
+pub trait AdminRepository: Send + Sync {
+    async fn add_admin(&self, admin: &Admin) -> Result<(), Error>;
+
+    async fn list_admins(&self) -> Result<Vec<Admin>, Error>;
+
+    async fn is_admin(&self, telegram_id: &str) -> Result<bool, Error>;
+
+    /// Removes the admin, unless they are the last one. The check and the removal are atomic in
+    /// the databases, so that concurrent removals cannot remove all the admins; Directus has no
+    /// transactions, which leaves a short window between them.
+    async fn remove_admin(&self, telegram_id: &str) -> Result<AdminRemoval, Error>;
+
+    /// Makes the admin a super admin, or a regular one. Returns `false` if they are not an admin.
+    async fn set_super_admin(&self, telegram_id: &str, super_admin: bool) -> Result<bool, Error>;
+}
+
+#[async_trait]
+pub trait AuthorizationRepository: Send + Sync {
+    /// Whether the chat is authorized to use the command, an expired authorization not counting
+    /// even if it was not removed yet.
+    async fn is_authorized(&self, chat_id: &str, command: &str) -> Result<bool, Error>;
+
+    /// Authorizes the chat to use the command, until `expires_at` if given. The expiry of an
+    /// existing authorization is replaced. Returns `false` if it was already authorized.
+    async fn authorize(
+        &self,
+        chat_id: &str,
+        command: &str,
+        expires_at: Option<i64>,
+    ) -> Result<bool, Error>;
+
+    /// Revokes the authorization of the chat to use the command. Returns `false` if it was not
+    /// authorized.
+    async fn unauthorize(&self, chat_id: &str, command: &str) -> Result<bool, Error>;
+
+    /// Revokes all the authorizations of the chat, and returns how many there were.
+    async fn unauthorize_chat(&self, chat_id: &str) -> Result<u64, Error>;
+
+    /// Lists the authorizations of the chat.
+    async fn list_authorizations(&self, chat_id: &str) -> Result<Vec<Authorization>, Error>;
+
+    /// Lists the authorizations of all the chats.
+    async fn list_all_authorizations(&self) -> Result<Vec<Authorization>, Error>;
+
+    /// Removes the authorizations which expired at the timestamp `now`, and returns them.
+    async fn expire_authorizations(&self, now: i64) -> Result<Vec<Authorization>, Error>;
+}
+
+#[async_trait]
+pub trait BureauRepository: Send + Sync {
+    async fn add_bureau_poll(
+        &self,
+        poll_id: &str,
+        chat_id: &str,
+        sent_at: i64,
+    ) -> Result<(), Error>;
+
+    /// Records the answer of a user to a /bureau poll, or removes it if `option_id` is `None`
+    /// (the user retracted their vote). Answers to unknown polls are ignored.
+    async fn record_bureau_answer(
+        &self,
+        poll_id: &str,
+        telegram_id: &str,
+        name: &str,
+        option_id: Option<i64>,
+        answered_at: i64,
+    ) -> Result<(), Error>;
+
+    /// Lists the answers to the polls of the chat given since the timestamp `since`, sorted by
+    /// answer time.
+    async fn list_bureau_answers(
+        &self,
+        chat_id: &str,
+        since: i64,
+    ) -> Result<Vec<BureauAnswer>, Error>;
+}
+
+#[async_trait]
+pub trait PermanenceRepository: Send + Sync {
+    /// Lists the slots of the chat (or of all chats if `None`), sorted by weekday and time.
+    async fn list_slots(&self, chat_id: Option<&str>) -> Result<Vec<PermanenceSlot>, Error>;
+
+    /// Adds a slot to the chat, and returns its id.
+    async fn add_slot(
+        &self,
+        chat_id: &str,
+        weekday: i64,
+        start_minute: i64,
+        duration: i64,
+    ) -> Result<i64, Error>;
+
+    /// Removes a slot of the chat. Returns `false` if it does not exist.
+    async fn remove_slot(&self, chat_id: &str, slot_id: i64) -> Result<bool, Error>;
+
+    /// Removes all the slots of the chat with their signups, and returns how many there were.
+    async fn remove_chat_slots(&self, chat_id: &str) -> Result<u64, Error>;
+
+    /// Signs the user up for the slot of the chat, or withdraws them if they already were.
+    /// Returns whether the user is now signed up, or `None` if the slot does not exist.
+    async fn toggle_signup(
+        &self,
+        chat_id: &str,
+        slot_id: i64,
+        telegram_id: &str,
+        name: &str,
+    ) -> Result<Option<bool>, Error>;
+
+    async fn set_last_reminder(&self, slot_id: i64, timestamp: i64) -> Result<(), Error>;
+}
+
+#[async_trait]
+pub trait EventRepository: Send + Sync {
+    /// Subscribes the chat to the event announcements. Returns `false` if it already was.
+    async fn subscribe_events(&self, chat_id: &str) -> Result<bool, Error>;
+
+    /// Unsubscribes the chat from the event announcements. Returns `false` if it was not
+    /// subscribed.
+    async fn unsubscribe_events(&self, chat_id: &str) -> Result<bool, Error>;
+
+    async fn list_event_subscriptions(&self) -> Result<Vec<String>, Error>;
+
+    /// Whether any event was ever announced, which is not the case on the first synchronization
+    /// or after the database was reset.
+    async fn has_announced_events(&self) -> Result<bool, Error>;
+
+    /// Returns whether the reminder of the event was sent, or `None` if it was not announced.
+    async fn event_reminded(&self, event_id: i32) -> Result<Option<bool>, Error>;
+
+    async fn mark_event_announced(&self, event_id: i32) -> Result<(), Error>;
+
+    async fn mark_event_reminded(&self, event_id: i32) -> Result<(), Error>;
+}
+
+#[async_trait]
+pub trait PermissionRepository: Send + Sync {
+    /// Sets the rule of the user for the command in the chat, replacing the previous one.
+    async fn set_permission(&self, permission: &Permission) -> Result<(), Error>;
+
+    /// Removes the rule of the user for the command in the chat (empty for the rule applying in
+    /// every chat). Returns `false` if there was none.
+    async fn remove_permission(
+        &self,
+        user_id: &str,
+        chat_id: &str,
+        command: &str,
+    ) -> Result<bool, Error>;
+
+    /// Removes the rules of all the users in the chat (but not the ones applying in every chat),
+    /// and returns how many there were.
+    async fn remove_chat_permissions(&self, chat_id: &str) -> Result<u64, Error>;
+
+    /// Lists the rules of the user, in all the chats.
+    async fn list_user_permissions(&self, user_id: &str) -> Result<Vec<Permission>, Error>;
+
+    /// Lists the rules of all the users.
+    async fn list_permissions(&self) -> Result<Vec<Permission>, Error>;
+}
+
+#[async_trait]
+pub trait ChatRepository: Send + Sync {
+    /// Records the chat, or updates it if it is already known (keeping its join date if the new
+    /// one is not known).
+    async fn save_chat(&self, chat: &KnownChat) -> Result<(), Error>;
+
+    /// Forgets the chat. Returns `false` if it was not known.
+    async fn remove_chat(&self, chat_id: &str) -> Result<bool, Error>;
+
+    /// Lists the known chats, sorted by title.
+    async fn list_chats(&self) -> Result<Vec<KnownChat>, Error>;
+}
+
+/// Languages chosen with /lang. The preferences of the groups are stored under their chat id,
+/// the ones of the users under their telegram id (which is also the id of their private chat).
+#[async_trait]
+pub trait LanguageRepository: Send + Sync {
+    /// Returns the code of the language of the chat or user, if one was chosen.
+    async fn get_language(&self, id: &str) -> Result<Option<String>, Error>;
+
+    async fn set_language(&self, id: &str, language: &str) -> Result<(), Error>;
+
+    async fn list_languages(&self) -> Result<Vec<LanguagePreference>, Error>;
+}
+
+#[async_trait]
+pub trait BackupRepository: Send + Sync {
+    /// Writes a consistent copy of the whole database to `path`, while it is in use.
+    async fn backup(&self, path: &Path) -> Result<(), Error>;
+}
+
+/// Repositories used by the handlers, each injected as a dependency of the dispatcher.
+#[derive(Clone)]
+pub struct Repositories {
+    pub admins: Arc<dyn AdminRepository>,
+    pub authorizations: Arc<dyn AuthorizationRepository>,
+    pub permissions: Arc<dyn PermissionRepository>,
+    pub bureau: Arc<dyn BureauRepository>,
+    pub permanences: Arc<dyn PermanenceRepository>,
+    pub events: Arc<dyn EventRepository>,
+    pub languages: Arc<dyn LanguageRepository>,
+    pub chats: Arc<dyn ChatRepository>,
+    /// Only available with a SQLite database
+    pub backups: Option<Arc<dyn BackupRepository>>,
+}
+
+impl Repositories {
+    fn from_storage<S>(storage: Arc<S>) -> Self
+    where
+        S: AdminRepository
+            + AuthorizationRepository
+            + PermissionRepository
+            + BureauRepository
+            + PermanenceRepository
+            + EventRepository
+            + LanguageRepository
+            + ChatRepository
+            + 'static,
+    {
+        Self {
+            admins: storage.clone(),
+            authorizations: storage.clone(),
+            permissions: storage.clone(),
+            bureau: storage.clone(),
+            permanences: storage.clone(),
+            events: storage.clone(),
+            languages: storage.clone(),
+            chats: storage,
+            backups: None,
+        }
+    }
+}
+
+/// Creates the repositories of the backend selected in the config.
+pub async fn init_storage() -> Result<Repositories, Error> {
+    Ok(match config().storage {
+        StorageBackend::Database => database_repositories().await?,
+        StorageBackend::Directus => Repositories {
+            admins: Arc::new(DirectusStorage),
+            authorizations: Arc::new(DirectusStorage),
+            permissions: Arc::new(DirectusStorage),
+            ..database_repositories().await?
+        },
+        StorageBackend::Memory => Repositories::from_storage(Arc::new(MemoryStorage::default())),
+    })
+}
+
+/// Connects to the database, PostgreSQL or SQLite depending on the scheme of its url, and
+/// checks that it can be written to.
+async fn database_repositories() -> Result<Repositories, Error> {
+    let url = database_url();
+    Ok(if is_postgres_url(&url) {
+        let storage = PostgresStorage::connect(&url).await?;
+        storage.check_writable().await?;
+        Repositories::from_storage(Arc::new(storage))
+    } else {
+        let storage = Arc::new(SqliteStorage::connect(&url).await?);
+        storage.check_writable().await?;
+        Repositories {
+            backups: Some(storage.clone()),
+            ..Repositories::from_storage(storage)
+        }
+    })
+}
+
+/// Replaces the SQLite database by the backup, keeping a copy of the current database next to
+/// it. Returns the path of this copy, if there was a database. The bot must be stopped.
+pub async fn restore_database(backup: &Path) -> Result<Option<PathBuf>, Error> {
+    let url = database_url();
+    if is_postgres_url(&url) {
+        return Err(Error::Backup(
+            "only SQLite databases can be restored, use the tools of your database instead".into(),
+        ));
+    }
+
+    SqliteStorage::restore(&url, backup).await
+}
+
+fn is_postgres_url(url: &str) -> bool {
+    url.starts_with("postgres://") || url.starts_with("postgresql://")
+}
+
+fn database_url() -> String {
+    config()
+        .database_url
+        .clone()
+        .unwrap_or_else(|| format!("sqlite://{}/db.sqlite", config().data_dir))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    /// Repositories of the backends which need no server, an in-memory SQLite database standing
+    /// in for the file, and of PostgreSQL if `TEST_DATABASE_URL` is set.
+    async fn backends() -> Vec<(&'static str, Repositories)> {
+        let mut backends = vec![
+            (
+                "memory",
+                Repositories::from_storage(Arc::new(MemoryStorage::default())),
+            ),
+            (
+                "sqlite",
+                Repositories::from_storage(Arc::new(
+                    SqliteStorage::connect("sqlite::memory:").await.unwrap(),
+                )),
+            ),
+        ];
+        if let Ok(url) = std::env::var("TEST_DATABASE_URL") {
+            backends.push(("postgres", postgres(&url).await));
+        }
+
+        backends
+    }
+
+    /// Repositories in a new schema of the PostgreSQL database, so that the tests do not see each
+    /// other's data. The schemas are left in the database, which should be a throwaway one.
+    async fn postgres(url: &str) -> Repositories {
+        use sqlx::{Connection, PgConnection};
+
+        let schema = format!("roboclic_test_{}", rand::random::<u32>());
+        let mut conn = PgConnection::connect(url).await.unwrap();
+        sqlx::query(&format!("CREATE SCHEMA {schema}"))
+            .execute(&mut conn)
+            .await
+            .unwrap();
+
+        let separator = if url.contains('?') { '&' } else { '?' };
+        let url = format!("{url}{separator}options[search_path]={schema}");
+        Repositories::from_storage(Arc::new(PostgresStorage::connect(&url).await.unwrap()))
+    }
+
+    fn admin(telegram_id: &str) -> Admin {
+        Admin {
+            telegram_id: telegram_id.to_owned(),
+            name: format!("Admin {telegram_id}"),
+            username: None,
+            super_admin: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn refuses_to_remove_the_last_admin() {
+        for (backend, repositories) in backends().await {
+            let admins = repositories.admins;
+            admins.add_admin(&admin("1")).await.unwrap();
+            admins.add_admin(&admin("2")).await.unwrap();
+
+            assert_eq!(
+                admins.remove_admin("3").await.unwrap(),
+                AdminRemoval::NotAdmin,
+                "{backend}"
+            );
+            assert_eq!(
+                admins.remove_admin("1").await.unwrap(),
+                AdminRemoval::Removed,
+                "{backend}"
+            );
+            assert_eq!(
+                admins.remove_admin("2").await.unwrap(),
+                AdminRemoval::LastAdmin,
+                "{backend}"
+            );
+            assert!(admins.is_admin("2").await.unwrap(), "{backend}");
+        }
+    }
+
+    #[tokio::test]
+    async fn replaces_the_expiry_of_an_authorization() {
+        let now = Utc::now().timestamp();
+        for (backend, repositories) in backends().await {
+            let authorizations = repositories.authorizations;
+
+            assert!(
+                authorizations
+                    .authorize("1", "poll", Some(now - 60))
+                    .await
+                    .unwrap(),
+                "{backend}"
+            );
+            assert!(
+                !authorizations.is_authorized("1", "poll").await.unwrap(),
+                "{backend}"
+            );
+
+            // Already present, even though it expired
+            assert!(
+                !authorizations
+                    .authorize("1", "poll", Some(now + 60))
+                    .await
+                    .unwrap(),
+                "{backend}"
+            );
+            assert!(
+                authorizations.is_authorized("1", "poll").await.unwrap(),
+                "{backend}"
+            );
+
+            // A permanent authorization replaces a temporary one
+            authorizations.authorize("1", "poll", None).await.unwrap();
+            assert!(
+                authorizations
+                    .expire_authorizations(now + 120)
+                    .await
+                    .unwrap()
+                    .is_empty(),
+                "{backend}"
+            );
+            assert!(
+                authorizations.is_authorized("1", "poll").await.unwrap(),
+                "{backend}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn expires_the_authorizations() {
+        let now = Utc::now().timestamp();
+        for (backend, repositories) in backends().await {
+            let authorizations = repositories.authorizations;
+            authorizations
+                .authorize("1", "poll", Some(now - 60))
+                .await
+                .unwrap();
+            authorizations
+                .authorize("1", "stats", Some(now))
+                .await
+                .unwrap();
+            authorizations
+                .authorize("2", "poll", Some(now + 60))
+                .await
+                .unwrap();
+            authorizations.authorize("2", "stats", None).await.unwrap();
+
+            let mut expired = authorizations
+                .expire_authorizations(now)
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|a| (a.chat_id, a.command, a.expires_at))
+                .collect::<Vec<_>>();
+            expired.sort();
+            assert_eq!(
+                expired,
+                [
+                    ("1".into(), "poll".into(), Some(now - 60)),
+                    ("1".into(), "stats".into(), Some(now)),
+                ],
+                "{backend}"
+            );
+
+            let mut remaining = authorizations
+                .list_all_authorizations()
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|a| (a.chat_id, a.command))
+                .collect::<Vec<_>>();
+            remaining.sort();
+            assert_eq!(
+                remaining,
+                [("2".into(), "poll".into()), ("2".into(), "stats".into())],
+                "{backend}"
+            );
+            assert!(
+                authorizations
+                    .expire_authorizations(now)
+                    .await
+                    .unwrap()
+                    .is_empty(),
+                "{backend}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn toggles_the_signups() {
+        for (backend, repositories) in backends().await {
+            let permanences = repositories.permanences;
+            let slot = permanences.add_slot("1", 0, 12 * 60, 60).await.unwrap();
+            let assignees = || async {
+                permanences.list_slots(Some("1")).await.unwrap()[0]
+                    .assignees
+                    .iter()
+                    .map(|a| a.telegram_id.clone())
+                    .collect::<Vec<_>>()
+            };
+
+            assert_eq!(
+                permanences
+                    .toggle_signup("1", slot, "7", "Alex")
+                    .await
+                    .unwrap(),
+                Some(true),
+                "{backend}"
+            );
+            assert_eq!(assignees().await, ["7"], "{backend}");
+            assert_eq!(
+                permanences
+                    .toggle_signup("1", slot, "7", "Alex")
+                    .await
+                    .unwrap(),
+                Some(false),
+                "{backend}"
+            );
+            assert!(assignees().await.is_empty(), "{backend}");
+
+            // The slot belongs to another chat
+            assert_eq!(
+                permanences
+                    .toggle_signup("2", slot, "7", "Alex")
+                    .await
+                    .unwrap(),
+                None,
+                "{backend}"
+            );
+            assert_eq!(
+                permanences
+                    .toggle_signup("1", slot + 1, "7", "Alex")
+                    .await
+                    .unwrap(),
+                None,
+                "{backend}"
+            );
+        }
+    }
+}
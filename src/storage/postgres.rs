@@ -0,0 +1,707 @@
+use std::collections::BTreeMap;
+
+use async_trait::async_trait;
+use chrono::Utc;
+use sqlx::{migrate::MigrateDatabase, PgPool};
+
+use super::{
+    Admin, AdminRemoval, AdminRepository, Assignee, Authorization, AuthorizationRepository,
+    BureauAnswer, BureauRepository, ChatRepository, Error, EventRepository, KnownChat,
+    LanguagePreference, LanguageRepository, PermanenceRepository, PermanenceSlot, Permission,
+    PermissionRepository,
+};
+
+/// Stores everything in a PostgreSQL database. The queries are checked at runtime, as the
+/// offline query data of the macros only covers SQLite, and by the repository tests when
+/// `TEST_DATABASE_URL` is set.
+pub struct PostgresStorage {
+    db: PgPool,
+}
+
+impl PostgresStorage {
+    /// Connects to the database, creating it if needed, and runs the migrations.
+    pub async fn connect(database_url: &str) -> Result<Self, Error> {
+        if !sqlx::Postgres::database_exists(database_url).await? {
+            sqlx::Postgres::create_database(database_url).await?;
+        }
+
+        let db = PgPool::connect(database_url).await?;
+        sqlx::migrate!("migrations/postgres").run(&db).await?;
+
+        Ok(Self { db })
+    }
+
+    /// Checks that the database can be written to, with an insertion that is rolled back.
+    pub async fn check_writable(&self) -> Result<(), Error> {
+        let mut tx = self.db.begin().await?;
+        sqlx::query("INSERT INTO event_subscriptions(chat_id) VALUES('')")
+            .execute(tx.as_mut())
+            .await?;
+        tx.rollback().await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl AdminRepository for PostgresStorage {
+    async fn add_admin(&self, admin: &Admin) -> Result<(), Error> {
+        sqlx::query(
+            r#"INSERT INTO admins(telegram_id, "name", username, super_admin) VALUES($1, $2, $3, $4)"#,
+        )
+        .bind(&admin.telegram_id)
+        .bind(&admin.name)
+        .bind(&admin.username)
+        .bind(admin.super_admin)
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn list_admins(&self) -> Result<Vec<Admin>, Error> {
+        Ok(sqlx::query_as::<_, (String, String, Option<String>, bool)>(
+            r#"SELECT telegram_id, "name", username, super_admin FROM admins"#,
+        )
+        .fetch_all(&self.db)
+        .await?
+        .into_iter()
+        .map(|(telegram_id, name, username, super_admin)| Admin {
+            telegram_id,
+            name,
+            username,
+            super_admin,
+        })
+        .collect())
+    }
+
+    async fn is_admin(&self, telegram_id: &str) -> Result<bool, Error> {
+        Ok(
+            sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM admins WHERE telegram_id = $1)")
+                .bind(telegram_id)
+                .fetch_one(&self.db)
+                .await?,
+        )
+    }
+
+    async fn remove_admin(&self, telegram_id: &str) -> Result<AdminRemoval, Error> {
+        let mut tx = self.db.begin().await?;
+
+        // Concurrent removals would otherwise all see the other admins
+        sqlx::query("LOCK TABLE admins IN EXCLUSIVE MODE")
+            .execute(&mut *tx)
+            .await?;
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM admins")
+            .fetch_one(&mut *tx)
+            .await?;
+        let is_admin: bool =
+            sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM admins WHERE telegram_id = $1)")
+                .bind(telegram_id)
+                .fetch_one(&mut *tx)
+                .await?;
+
+        let removal = if !is_admin {
+            AdminRemoval::NotAdmin
+        } else if count <= 1 {
+            AdminRemoval::LastAdmin
+        } else {
+            sqlx::query("DELETE FROM admins WHERE telegram_id = $1")
+                .bind(telegram_id)
+                .execute(&mut *tx)
+                .await?;
+            AdminRemoval::Removed
+        };
+        tx.commit().await?;
+
+        Ok(removal)
+    }
+
+    async fn set_super_admin(&self, telegram_id: &str, super_admin: bool) -> Result<bool, Error> {
+        Ok(
+            sqlx::query("UPDATE admins SET super_admin = $1 WHERE telegram_id = $2")
+                .bind(super_admin)
+                .bind(telegram_id)
+                .execute(&self.db)
+                .await?
+                .rows_affected()
+                > 0,
+        )
+    }
+}
+
+#[async_trait]
+impl AuthorizationRepository for PostgresStorage {
+    async fn is_authorized(&self, chat_id: &str, command: &str) -> Result<bool, Error> {
+        Ok(sqlx::query_scalar(
+            "SELECT EXISTS(SELECT 1 FROM authorizations WHERE chat_id = $1 AND command = $2 \
+             AND (expires_at IS NULL OR expires_at > $3))",
+        )
+        .bind(chat_id)
+        .bind(command)
+        .bind(Utc::now().timestamp())
+        .fetch_one(&self.db)
+        .await?)
+    }
+
+    async fn authorize(
+        &self,
+        chat_id: &str,
+        command: &str,
+        expires_at: Option<i64>,
+    ) -> Result<bool, Error> {
+        let mut tx = self.db.begin().await?;
+
+        let updated = sqlx::query(
+            "UPDATE authorizations SET expires_at = $3 WHERE command = $1 AND chat_id = $2",
+        )
+        .bind(command)
+        .bind(chat_id)
+        .bind(expires_at)
+        .execute(tx.as_mut())
+        .await?
+        .rows_affected()
+            > 0;
+
+        if !updated {
+            sqlx::query(
+                "INSERT INTO authorizations(command, chat_id, expires_at) VALUES($1, $2, $3)",
+            )
+            .bind(command)
+            .bind(chat_id)
+            .bind(expires_at)
+            .execute(tx.as_mut())
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(!updated)
+    }
+
+    async fn unauthorize(&self, chat_id: &str, command: &str) -> Result<bool, Error> {
+        Ok(
+            sqlx::query("DELETE FROM authorizations WHERE command = $1 AND chat_id = $2")
+                .bind(command)
+                .bind(chat_id)
+                .execute(&self.db)
+                .await?
+                .rows_affected()
+                > 0,
+        )
+    }
+
+    async fn unauthorize_chat(&self, chat_id: &str) -> Result<u64, Error> {
+        Ok(sqlx::query("DELETE FROM authorizations WHERE chat_id = $1")
+            .bind(chat_id)
+            .execute(&self.db)
+            .await?
+            .rows_affected())
+    }
+
+    async fn list_authorizations(&self, chat_id: &str) -> Result<Vec<Authorization>, Error> {
+        Ok(sqlx::query_as::<_, (String, String, Option<i64>)>(
+            "SELECT chat_id, command, expires_at FROM authorizations WHERE chat_id = $1
+            ORDER BY command",
+        )
+        .bind(chat_id)
+        .fetch_all(&self.db)
+        .await?
+        .into_iter()
+        .map(authorization_from_row)
+        .collect())
+    }
+
+    async fn list_all_authorizations(&self) -> Result<Vec<Authorization>, Error> {
+        Ok(sqlx::query_as::<_, (String, String, Option<i64>)>(
+            "SELECT chat_id, command, expires_at FROM authorizations ORDER BY chat_id, command",
+        )
+        .fetch_all(&self.db)
+        .await?
+        .into_iter()
+        .map(authorization_from_row)
+        .collect())
+    }
+
+    async fn expire_authorizations(&self, now: i64) -> Result<Vec<Authorization>, Error> {
+        Ok(sqlx::query_as::<_, (String, String, Option<i64>)>(
+            "DELETE FROM authorizations WHERE expires_at <= $1
+            RETURNING chat_id, command, expires_at",
+        )
+        .bind(now)
+        .fetch_all(&self.db)
+        .await?
+        .into_iter()
+        .map(authorization_from_row)
+        .collect())
+    }
+}
+
+#[async_trait]
+impl PermissionRepository for PostgresStorage {
+    async fn set_permission(&self, permission: &Permission) -> Result<(), Error> {
+        sqlx::query(
+            "INSERT INTO permissions(user_id, chat_id, command, allowed) VALUES($1, $2, $3, $4)
+            ON CONFLICT(user_id, chat_id, command) DO UPDATE SET allowed = excluded.allowed",
+        )
+        .bind(&permission.user_id)
+        .bind(&permission.chat_id)
+        .bind(&permission.command)
+        .bind(permission.allowed)
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn remove_permission(
+        &self,
+        user_id: &str,
+        chat_id: &str,
+        command: &str,
+    ) -> Result<bool, Error> {
+        Ok(sqlx::query(
+            "DELETE FROM permissions WHERE user_id = $1 AND chat_id = $2 AND command = $3",
+        )
+        .bind(user_id)
+        .bind(chat_id)
+        .bind(command)
+        .execute(&self.db)
+        .await?
+        .rows_affected()
+            > 0)
+    }
+
+    async fn remove_chat_permissions(&self, chat_id: &str) -> Result<u64, Error> {
+        Ok(
+            sqlx::query("DELETE FROM permissions WHERE chat_id = $1 AND chat_id <> ''")
+                .bind(chat_id)
+                .execute(&self.db)
+                .await?
+                .rows_affected(),
+        )
+    }
+
+    async fn list_user_permissions(&self, user_id: &str) -> Result<Vec<Permission>, Error> {
+        Ok(sqlx::query_as::<_, (String, String, String, bool)>(
+            "SELECT user_id, chat_id, command, allowed FROM permissions WHERE user_id = $1
+            ORDER BY chat_id, command",
+        )
+        .bind(user_id)
+        .fetch_all(&self.db)
+        .await?
+        .into_iter()
+        .map(permission_from_row)
+        .collect())
+    }
+
+    async fn list_permissions(&self) -> Result<Vec<Permission>, Error> {
+        Ok(sqlx::query_as::<_, (String, String, String, bool)>(
+            "SELECT user_id, chat_id, command, allowed FROM permissions
+            ORDER BY user_id, chat_id, command",
+        )
+        .fetch_all(&self.db)
+        .await?
+        .into_iter()
+        .map(permission_from_row)
+        .collect())
+    }
+}
+
+fn authorization_from_row(
+    (chat_id, command, expires_at): (String, String, Option<i64>),
+) -> Authorization {
+    Authorization {
+        chat_id,
+        command,
+        expires_at,
+    }
+}
+
+fn permission_from_row(
+    (user_id, chat_id, command, allowed): (String, String, String, bool),
+) -> Permission {
+    Permission {
+        user_id,
+        chat_id,
+        command,
+        allowed,
+    }
+}
+
+#[async_trait]
+impl BureauRepository for PostgresStorage {
+    async fn add_bureau_poll(
+        &self,
+        poll_id: &str,
+        chat_id: &str,
+        sent_at: i64,
+    ) -> Result<(), Error> {
+        sqlx::query("INSERT INTO bureau_polls(poll_id, chat_id, sent_at) VALUES($1, $2, $3)")
+            .bind(poll_id)
+            .bind(chat_id)
+            .bind(sent_at)
+            .execute(&self.db)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn record_bureau_answer(
+        &self,
+        poll_id: &str,
+        telegram_id: &str,
+        name: &str,
+        option_id: Option<i64>,
+        answered_at: i64,
+    ) -> Result<(), Error> {
+        match option_id {
+            None => {
+                sqlx::query("DELETE FROM bureau_answers WHERE poll_id = $1 AND telegram_id = $2")
+                    .bind(poll_id)
+                    .bind(telegram_id)
+                    .execute(&self.db)
+                    .await?;
+            }
+            Some(option_id) => {
+                // Selecting from bureau_polls ignores the answers to unknown polls
+                sqlx::query(
+                    r#"INSERT INTO bureau_answers(poll_id, telegram_id, "name", option_id, answered_at)
+                    SELECT poll_id, $2, $3, $4, $5 FROM bureau_polls WHERE poll_id = $1
+                    ON CONFLICT(poll_id, telegram_id) DO UPDATE
+                    SET "name" = excluded."name", option_id = excluded.option_id, answered_at = excluded.answered_at"#,
+                )
+                .bind(poll_id)
+                .bind(telegram_id)
+                .bind(name)
+                .bind(option_id)
+                .bind(answered_at)
+                .execute(&self.db)
+                .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn list_bureau_answers(
+        &self,
+        chat_id: &str,
+        since: i64,
+    ) -> Result<Vec<BureauAnswer>, Error> {
+        Ok(sqlx::query_as::<_, (i64, String, String, i64, i64)>(
+            r#"SELECT p.sent_at, a.telegram_id, a."name", a.option_id, a.answered_at
+            FROM bureau_answers a JOIN bureau_polls p ON a.poll_id = p.poll_id
+            WHERE p.chat_id = $1 AND a.answered_at >= $2
+            ORDER BY a.answered_at"#,
+        )
+        .bind(chat_id)
+        .bind(since)
+        .fetch_all(&self.db)
+        .await?
+        .into_iter()
+        .map(
+            |(poll_sent_at, telegram_id, name, option_id, answered_at)| BureauAnswer {
+                poll_sent_at,
+                telegram_id,
+                name,
+                option_id,
+                answered_at,
+            },
+        )
+        .collect())
+    }
+}
+
+type SlotRow = (
+    i64,
+    String,
+    i64,
+    i64,
+    i64,
+    Option<i64>,
+    Option<String>,
+    Option<String>,
+);
+
+#[async_trait]
+impl PermanenceRepository for PostgresStorage {
+    async fn list_slots(&self, chat_id: Option<&str>) -> Result<Vec<PermanenceSlot>, Error> {
+        let rows = sqlx::query_as::<_, SlotRow>(
+            r#"SELECT s.id, s.chat_id, s.weekday, s.start_minute, s.duration, s.last_reminder,
+                u.telegram_id, u."name"
+            FROM permanence_slots s LEFT JOIN permanence_signups u ON s.id = u.slot_id
+            WHERE $1::VARCHAR IS NULL OR s.chat_id = $1
+            ORDER BY s.weekday, s.start_minute, s.id, u."name""#,
+        )
+        .bind(chat_id)
+        .fetch_all(&self.db)
+        .await?;
+
+        let mut slots = BTreeMap::<(i64, i64, i64), PermanenceSlot>::new();
+        for (id, chat_id, weekday, start_minute, duration, last_reminder, telegram_id, name) in rows
+        {
+            let slot = slots
+                .entry((weekday, start_minute, id))
+                .or_insert_with(|| PermanenceSlot {
+                    id,
+                    chat_id,
+                    weekday,
+                    start_minute,
+                    duration,
+                    last_reminder,
+                    assignees: vec![],
+                });
+
+            if let (Some(telegram_id), Some(name)) = (telegram_id, name) {
+                slot.assignees.push(Assignee { telegram_id, name });
+            }
+        }
+
+        Ok(slots.into_values().collect())
+    }
+
+    async fn add_slot(
+        &self,
+        chat_id: &str,
+        weekday: i64,
+        start_minute: i64,
+        duration: i64,
+    ) -> Result<i64, Error> {
+        Ok(sqlx::query_scalar(
+            "INSERT INTO permanence_slots(chat_id, weekday, start_minute, duration) VALUES($1, $2, $3, $4) RETURNING id",
+        )
+        .bind(chat_id)
+        .bind(weekday)
+        .bind(start_minute)
+        .bind(duration)
+        .fetch_one(&self.db)
+        .await?)
+    }
+
+    async fn remove_slot(&self, chat_id: &str, slot_id: i64) -> Result<bool, Error> {
+        Ok(
+            sqlx::query("DELETE FROM permanence_slots WHERE id = $1 AND chat_id = $2")
+                .bind(slot_id)
+                .bind(chat_id)
+                .execute(&self.db)
+                .await?
+                .rows_affected()
+                > 0,
+        )
+    }
+
+    async fn remove_chat_slots(&self, chat_id: &str) -> Result<u64, Error> {
+        // The signups are deleted in cascade
+        Ok(
+            sqlx::query("DELETE FROM permanence_slots WHERE chat_id = $1")
+                .bind(chat_id)
+                .execute(&self.db)
+                .await?
+                .rows_affected(),
+        )
+    }
+
+    async fn toggle_signup(
+        &self,
+        chat_id: &str,
+        slot_id: i64,
+        telegram_id: &str,
+        name: &str,
+    ) -> Result<Option<bool>, Error> {
+        let mut tx = self.db.begin().await?;
+
+        let slot_exists: bool = sqlx::query_scalar(
+            "SELECT EXISTS(SELECT 1 FROM permanence_slots WHERE id = $1 AND chat_id = $2)",
+        )
+        .bind(slot_id)
+        .bind(chat_id)
+        .fetch_one(tx.as_mut())
+        .await?;
+
+        if !slot_exists {
+            return Ok(None);
+        }
+
+        let registered =
+            sqlx::query("DELETE FROM permanence_signups WHERE slot_id = $1 AND telegram_id = $2")
+                .bind(slot_id)
+                .bind(telegram_id)
+                .execute(tx.as_mut())
+                .await?
+                .rows_affected()
+                > 0;
+
+        if !registered {
+            sqlx::query(
+                r#"INSERT INTO permanence_signups(slot_id, telegram_id, "name") VALUES($1, $2, $3)"#,
+            )
+            .bind(slot_id)
+            .bind(telegram_id)
+            .bind(name)
+            .execute(tx.as_mut())
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(Some(!registered))
+    }
+
+    async fn set_last_reminder(&self, slot_id: i64, timestamp: i64) -> Result<(), Error> {
+        sqlx::query("UPDATE permanence_slots SET last_reminder = $1 WHERE id = $2")
+            .bind(timestamp)
+            .bind(slot_id)
+            .execute(&self.db)
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl EventRepository for PostgresStorage {
+    async fn subscribe_events(&self, chat_id: &str) -> Result<bool, Error> {
+        Ok(sqlx::query(
+            "INSERT INTO event_subscriptions(chat_id) VALUES($1) ON CONFLICT DO NOTHING",
+        )
+        .bind(chat_id)
+        .execute(&self.db)
+        .await?
+        .rows_affected()
+            > 0)
+    }
+
+    async fn unsubscribe_events(&self, chat_id: &str) -> Result<bool, Error> {
+        Ok(
+            sqlx::query("DELETE FROM event_subscriptions WHERE chat_id = $1")
+                .bind(chat_id)
+                .execute(&self.db)
+                .await?
+                .rows_affected()
+                > 0,
+        )
+    }
+
+    async fn list_event_subscriptions(&self) -> Result<Vec<String>, Error> {
+        Ok(
+            sqlx::query_scalar("SELECT chat_id FROM event_subscriptions")
+                .fetch_all(&self.db)
+                .await?,
+        )
+    }
+
+    async fn has_announced_events(&self) -> Result<bool, Error> {
+        Ok(
+            sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM announced_events)")
+                .fetch_one(&self.db)
+                .await?,
+        )
+    }
+
+    async fn event_reminded(&self, event_id: i32) -> Result<Option<bool>, Error> {
+        Ok(
+            sqlx::query_scalar("SELECT reminded FROM announced_events WHERE event_id = $1")
+                .bind(event_id)
+                .fetch_optional(&self.db)
+                .await?,
+        )
+    }
+
+    async fn mark_event_announced(&self, event_id: i32) -> Result<(), Error> {
+        sqlx::query("INSERT INTO announced_events(event_id) VALUES($1) ON CONFLICT DO NOTHING")
+            .bind(event_id)
+            .execute(&self.db)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn mark_event_reminded(&self, event_id: i32) -> Result<(), Error> {
+        sqlx::query("UPDATE announced_events SET reminded = TRUE WHERE event_id = $1")
+            .bind(event_id)
+            .execute(&self.db)
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl LanguageRepository for PostgresStorage {
+    async fn get_language(&self, id: &str) -> Result<Option<String>, Error> {
+        Ok(
+            sqlx::query_scalar(r#"SELECT "language" FROM languages WHERE id = $1"#)
+                .bind(id)
+                .fetch_optional(&self.db)
+                .await?,
+        )
+    }
+
+    async fn set_language(&self, id: &str, language: &str) -> Result<(), Error> {
+        sqlx::query(
+            r#"INSERT INTO languages(id, "language") VALUES($1, $2)
+            ON CONFLICT(id) DO UPDATE SET "language" = excluded."language""#,
+        )
+        .bind(id)
+        .bind(language)
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn list_languages(&self) -> Result<Vec<LanguagePreference>, Error> {
+        Ok(sqlx::query_as::<_, (String, String)>(
+            r#"SELECT id, "language" FROM languages ORDER BY id"#,
+        )
+        .fetch_all(&self.db)
+        .await?
+        .into_iter()
+        .map(|(id, language)| LanguagePreference { id, language })
+        .collect())
+    }
+}
+
+#[async_trait]
+impl ChatRepository for PostgresStorage {
+    async fn save_chat(&self, chat: &KnownChat) -> Result<(), Error> {
+        sqlx::query(
+            "INSERT INTO chats(chat_id, title, kind, joined_at) VALUES($1, $2, $3, $4)
+            ON CONFLICT(chat_id) DO UPDATE SET title = excluded.title, kind = excluded.kind,
+            joined_at = COALESCE(excluded.joined_at, chats.joined_at)",
+        )
+        .bind(&chat.chat_id)
+        .bind(&chat.title)
+        .bind(&chat.kind)
+        .bind(chat.joined_at)
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn remove_chat(&self, chat_id: &str) -> Result<bool, Error> {
+        Ok(sqlx::query("DELETE FROM chats WHERE chat_id = $1")
+            .bind(chat_id)
+            .execute(&self.db)
+            .await?
+            .rows_affected()
+            > 0)
+    }
+
+    async fn list_chats(&self) -> Result<Vec<KnownChat>, Error> {
+        Ok(sqlx::query_as::<_, (String, String, String, Option<i64>)>(
+            "SELECT chat_id, title, kind, joined_at FROM chats ORDER BY title, chat_id",
+        )
+        .fetch_all(&self.db)
+        .await?
+        .into_iter()
+        .map(|(chat_id, title, kind, joined_at)| KnownChat {
+            chat_id,
+            title,
+            kind,
+            joined_at,
+        })
+        .collect())
+    }
+}
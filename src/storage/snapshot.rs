@@ -0,0 +1,194 @@
+use serde::{Deserialize, Serialize};
+
+use super::{
+    Admin, Assignee, Authorization, Error, KnownChat, LanguagePreference, Permission, Repositories,
+};
+
+/// Data of the bot that can be exported and imported, to move it between storage backends or
+/// instances. The attendance history of /bureau (exported as CSV by the command itself) and
+/// the announcements state of the events are not included.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Snapshot {
+    pub admins: Vec<Admin>,
+    pub authorizations: Vec<Authorization>,
+    pub permanence_slots: Vec<SlotSnapshot>,
+    pub event_subscriptions: Vec<String>,
+    pub languages: Vec<LanguagePreference>,
+    pub chats: Vec<KnownChat>,
+    pub permissions: Vec<Permission>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SlotSnapshot {
+    pub chat_id: String,
+    pub weekday: i64,
+    pub start_minute: i64,
+    pub duration: i64,
+    #[serde(default)]
+    pub assignees: Vec<Assignee>,
+}
+
+/// Number of entries added by an import, the entries already present are skipped.
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub admins: usize,
+    pub authorizations: usize,
+    pub permanence_slots: usize,
+    pub permanence_signups: usize,
+    pub event_subscriptions: usize,
+    pub languages: usize,
+    pub chats: usize,
+    pub permissions: usize,
+}
+
+impl Snapshot {
+    pub async fn export(repositories: &Repositories) -> Result<Self, Error> {
+        Ok(Self {
+            admins: repositories.admins.list_admins().await?,
+            authorizations: repositories
+                .authorizations
+                .list_all_authorizations()
+                .await?,
+            permanence_slots: repositories
+                .permanences
+                .list_slots(None)
+                .await?
+                .into_iter()
+                .map(|s| SlotSnapshot {
+                    chat_id: s.chat_id,
+                    weekday: s.weekday,
+                    start_minute: s.start_minute,
+                    duration: s.duration,
+                    assignees: s.assignees,
+                })
+                .collect(),
+            event_subscriptions: repositories.events.list_event_subscriptions().await?,
+            languages: repositories.languages.list_languages().await?,
+            chats: repositories.chats.list_chats().await?,
+            permissions: repositories.permissions.list_permissions().await?,
+        })
+    }
+
+    /// Adds the data of the snapshot to the repositories. Slots identical to an existing one (same
+    /// chat, day, time and duration) are merged with it.
+    pub async fn import(self, repositories: &Repositories) -> Result<ImportReport, Error> {
+        let mut report = ImportReport::default();
+
+        for admin in self.admins {
+            if !repositories.admins.is_admin(&admin.telegram_id).await? {
+                repositories.admins.add_admin(&admin).await?;
+                report.admins += 1;
+            }
+        }
+
+        let existing_authorizations = repositories
+            .authorizations
+            .list_all_authorizations()
+            .await?;
+        for authorization in self.authorizations {
+            if !existing_authorizations
+                .iter()
+                .any(|a| a.chat_id == authorization.chat_id && a.command == authorization.command)
+            {
+                repositories
+                    .authorizations
+                    .authorize(
+                        &authorization.chat_id,
+                        &authorization.command,
+                        authorization.expires_at,
+                    )
+                    .await?;
+                report.authorizations += 1;
+            }
+        }
+
+        let existing_slots = repositories.permanences.list_slots(None).await?;
+        for slot in self.permanence_slots {
+            let existing = existing_slots.iter().find(|s| {
+                s.chat_id == slot.chat_id
+                    && s.weekday == slot.weekday
+                    && s.start_minute == slot.start_minute
+                    && s.duration == slot.duration
+            });
+            let slot_id = match existing {
+                Some(existing) => existing.id,
+                None => {
+                    report.permanence_slots += 1;
+                    repositories
+                        .permanences
+                        .add_slot(
+                            &slot.chat_id,
+                            slot.weekday,
+                            slot.start_minute,
+                            slot.duration,
+                        )
+                        .await?
+                }
+            };
+
+            for assignee in slot.assignees {
+                if existing.is_some_and(|s| {
+                    s.assignees
+                        .iter()
+                        .any(|a| a.telegram_id == assignee.telegram_id)
+                }) {
+                    continue;
+                }
+                repositories
+                    .permanences
+                    .toggle_signup(
+                        &slot.chat_id,
+                        slot_id,
+                        &assignee.telegram_id,
+                        &assignee.name,
+                    )
+                    .await?;
+                report.permanence_signups += 1;
+            }
+        }
+
+        for chat_id in self.event_subscriptions {
+            if repositories.events.subscribe_events(&chat_id).await? {
+                report.event_subscriptions += 1;
+            }
+        }
+
+        for preference in self.languages {
+            if repositories
+                .languages
+                .get_language(&preference.id)
+                .await?
+                .is_none()
+            {
+                repositories
+                    .languages
+                    .set_language(&preference.id, &preference.language)
+                    .await?;
+                report.languages += 1;
+            }
+        }
+
+        let known_chats = repositories.chats.list_chats().await?;
+        for chat in self.chats {
+            if !known_chats.iter().any(|c| c.chat_id == chat.chat_id) {
+                repositories.chats.save_chat(&chat).await?;
+                report.chats += 1;
+            }
+        }
+
+        let known_permissions = repositories.permissions.list_permissions().await?;
+        for permission in self.permissions {
+            if !known_permissions.iter().any(|p| {
+                p.user_id == permission.user_id
+                    && p.chat_id == permission.chat_id
+                    && p.command == permission.command
+            }) {
+                repositories.permissions.set_permission(&permission).await?;
+                report.permissions += 1;
+            }
+        }
+
+        Ok(report)
+    }
+}
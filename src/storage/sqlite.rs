@@ -0,0 +1,825 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use async_trait::async_trait;
+use chrono::Utc;
+use sqlx::{
+    migrate::MigrateDatabase,
+    sqlite::{SqliteConnectOptions, SqlitePool},
+    ConnectOptions, Connection,
+};
+
+use super::{
+    Admin, AdminRemoval, AdminRepository, Assignee, Authorization, AuthorizationRepository,
+    BackupRepository, BureauAnswer, BureauRepository, ChatRepository, Error, EventRepository,
+    KnownChat, LanguagePreference, LanguageRepository, PermanenceRepository, PermanenceSlot,
+    Permission, PermissionRepository,
+};
+
+pub struct SqliteStorage {
+    db: SqlitePool,
+}
+
+impl SqliteStorage {
+    /// Connects to the database, creating it if needed, and runs the migrations.
+    pub async fn connect(database_url: &str) -> Result<Self, Error> {
+        if !sqlx::Sqlite::database_exists(database_url).await? {
+            sqlx::Sqlite::create_database(database_url).await?;
+        }
+
+        let db = SqlitePool::connect(database_url).await?;
+        sqlx::migrate!("migrations/sqlite").run(&db).await?;
+
+        Ok(Self { db })
+    }
+
+    /// Checks that the database can be written to, with an insertion that is rolled back.
+    pub async fn check_writable(&self) -> Result<(), Error> {
+        let mut tx = self.db.begin().await?;
+        sqlx::query!("INSERT INTO event_subscriptions(chat_id) VALUES('')")
+            .execute(tx.as_mut())
+            .await?;
+        tx.rollback().await?;
+
+        Ok(())
+    }
+
+    /// Replaces the database by the backup, after checking it. The current database is first
+    /// copied next to it, and the path of this copy is returned.
+    pub async fn restore(database_url: &str, backup: &Path) -> Result<Option<PathBuf>, Error> {
+        if !backup.is_file() {
+            return Err(Error::Backup(format!(
+                "{} does not exist",
+                backup.display()
+            )));
+        }
+
+        let mut conn = SqliteConnectOptions::new()
+            .filename(backup)
+            .read_only(true)
+            .connect()
+            .await?;
+        let integrity = sqlx::query_scalar::<_, String>("PRAGMA integrity_check")
+            .fetch_one(&mut conn)
+            .await?;
+        if integrity != "ok" {
+            return Err(Error::Backup(format!(
+                "the backup is corrupted: {integrity}"
+            )));
+        }
+        let migrated = sqlx::query_scalar::<_, i64>(
+            "SELECT COUNT(*) FROM sqlite_master WHERE name = '_sqlx_migrations'",
+        )
+        .fetch_one(&mut conn)
+        .await?
+            > 0;
+        if !migrated {
+            return Err(Error::Backup(
+                "the file is not a backup of the database of the bot".into(),
+            ));
+        }
+        conn.close().await?;
+
+        let options = SqliteConnectOptions::from_str(database_url)?;
+        let path = options.clone().get_filename().to_path_buf();
+        let previous = if path.exists() {
+            let mut name = path.file_name().unwrap_or_default().to_owned();
+            name.push(
+                Utc::now()
+                    .format(".before-restore-%Y%m%d-%H%M%S")
+                    .to_string(),
+            );
+            let previous = path.with_file_name(name);
+
+            let current = Self {
+                db: SqlitePool::connect_with(options).await?,
+            };
+            current.backup(&previous).await?;
+            current.db.close().await;
+
+            Some(previous)
+        } else {
+            None
+        };
+
+        // Leftovers of the write-ahead log of the current database would corrupt the backup
+        for suffix in ["-wal", "-shm"] {
+            let mut name = path.clone().into_os_string();
+            name.push(suffix);
+            match std::fs::remove_file(name) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+
+        let mut restoring = path.clone().into_os_string();
+        restoring.push(".restoring");
+        std::fs::copy(backup, &restoring)?;
+        std::fs::rename(&restoring, &path)?;
+
+        Ok(previous)
+    }
+}
+
+#[async_trait]
+impl BackupRepository for SqliteStorage {
+    async fn backup(&self, path: &Path) -> Result<(), Error> {
+        let path = path
+            .to_str()
+            .ok_or_else(|| Error::Backup(format!("invalid backup path: {}", path.display())))?;
+        sqlx::query!("VACUUM INTO $1", path)
+            .execute(&self.db)
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl AdminRepository for SqliteStorage {
+    async fn add_admin(&self, admin: &Admin) -> Result<(), Error> {
+        sqlx::query!(
+            r#"INSERT INTO admins(telegram_id, "name", username, super_admin) VALUES($1, $2, $3, $4)"#,
+            admin.telegram_id,
+            admin.name,
+            admin.username,
+            admin.super_admin
+        )
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn list_admins(&self) -> Result<Vec<Admin>, Error> {
+        Ok(sqlx::query!(
+            r#"SELECT telegram_id AS "telegram_id!", "name", username, super_admin FROM admins"#
+        )
+        .fetch_all(&self.db)
+        .await?
+        .into_iter()
+        .map(|r| Admin {
+            telegram_id: r.telegram_id,
+            name: r.name,
+            username: r.username,
+            super_admin: r.super_admin,
+        })
+        .collect())
+    }
+
+    async fn is_admin(&self, telegram_id: &str) -> Result<bool, Error> {
+        Ok(sqlx::query!(
+            "SELECT COUNT(*) AS is_admin FROM admins WHERE telegram_id = $1",
+            telegram_id
+        )
+        .fetch_one(&self.db)
+        .await?
+        .is_admin
+            > 0)
+    }
+
+    async fn remove_admin(&self, telegram_id: &str) -> Result<AdminRemoval, Error> {
+        // A single statement, which SQLite runs atomically
+        let removed = sqlx::query!(
+            "DELETE FROM admins WHERE telegram_id = $1 AND (SELECT COUNT(*) FROM admins) > 1",
+            telegram_id
+        )
+        .execute(&self.db)
+        .await?
+        .rows_affected()
+            > 0;
+
+        Ok(if removed {
+            AdminRemoval::Removed
+        } else if self.is_admin(telegram_id).await? {
+            AdminRemoval::LastAdmin
+        } else {
+            AdminRemoval::NotAdmin
+        })
+    }
+
+    async fn set_super_admin(&self, telegram_id: &str, super_admin: bool) -> Result<bool, Error> {
+        Ok(sqlx::query!(
+            "UPDATE admins SET super_admin = $1 WHERE telegram_id = $2",
+            super_admin,
+            telegram_id
+        )
+        .execute(&self.db)
+        .await?
+        .rows_affected()
+            > 0)
+    }
+}
+
+#[async_trait]
+impl AuthorizationRepository for SqliteStorage {
+    async fn is_authorized(&self, chat_id: &str, command: &str) -> Result<bool, Error> {
+        let now = Utc::now().timestamp();
+        Ok(sqlx::query!(
+            r#"SELECT COUNT(*) AS count FROM authorizations
+            WHERE chat_id = $1 AND command = $2 AND (expires_at IS NULL OR expires_at > $3)"#,
+            chat_id,
+            command,
+            now
+        )
+        .fetch_one(&self.db)
+        .await?
+        .count
+            > 0)
+    }
+
+    async fn authorize(
+        &self,
+        chat_id: &str,
+        command: &str,
+        expires_at: Option<i64>,
+    ) -> Result<bool, Error> {
+        let mut tx = self.db.begin().await?;
+
+        let already_authorized = sqlx::query!(
+            r#"UPDATE authorizations SET expires_at = $1 WHERE chat_id = $2 AND command = $3"#,
+            expires_at,
+            chat_id,
+            command
+        )
+        .execute(tx.as_mut())
+        .await?
+        .rows_affected()
+            > 0;
+
+        if !already_authorized {
+            sqlx::query!(
+                r#"INSERT INTO authorizations(command, chat_id, expires_at) VALUES($1, $2, $3)"#,
+                command,
+                chat_id,
+                expires_at
+            )
+            .execute(tx.as_mut())
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(!already_authorized)
+    }
+
+    async fn unauthorize(&self, chat_id: &str, command: &str) -> Result<bool, Error> {
+        Ok(sqlx::query!(
+            r#"DELETE FROM authorizations WHERE command = $1 AND chat_id = $2"#,
+            command,
+            chat_id
+        )
+        .execute(&self.db)
+        .await?
+        .rows_affected()
+            > 0)
+    }
+
+    async fn unauthorize_chat(&self, chat_id: &str) -> Result<u64, Error> {
+        Ok(
+            sqlx::query!("DELETE FROM authorizations WHERE chat_id = $1", chat_id)
+                .execute(&self.db)
+                .await?
+                .rows_affected(),
+        )
+    }
+
+    async fn list_authorizations(&self, chat_id: &str) -> Result<Vec<Authorization>, Error> {
+        Ok(sqlx::query!(
+            r#"SELECT chat_id, command, expires_at FROM authorizations WHERE chat_id = $1
+            ORDER BY command"#,
+            chat_id
+        )
+        .fetch_all(&self.db)
+        .await?
+        .into_iter()
+        .map(|r| Authorization {
+            chat_id: r.chat_id,
+            command: r.command,
+            expires_at: r.expires_at,
+        })
+        .collect())
+    }
+
+    async fn list_all_authorizations(&self) -> Result<Vec<Authorization>, Error> {
+        Ok(sqlx::query!(
+            "SELECT chat_id, command, expires_at FROM authorizations ORDER BY chat_id, command"
+        )
+        .fetch_all(&self.db)
+        .await?
+        .into_iter()
+        .map(|r| Authorization {
+            chat_id: r.chat_id,
+            command: r.command,
+            expires_at: r.expires_at,
+        })
+        .collect())
+    }
+
+    async fn expire_authorizations(&self, now: i64) -> Result<Vec<Authorization>, Error> {
+        Ok(sqlx::query!(
+            r#"DELETE FROM authorizations WHERE expires_at <= $1
+            RETURNING chat_id AS "chat_id!", command AS "command!", expires_at"#,
+            now
+        )
+        .fetch_all(&self.db)
+        .await?
+        .into_iter()
+        .map(|r| Authorization {
+            chat_id: r.chat_id,
+            command: r.command,
+            expires_at: r.expires_at,
+        })
+        .collect())
+    }
+}
+
+#[async_trait]
+impl PermissionRepository for SqliteStorage {
+    async fn set_permission(&self, permission: &Permission) -> Result<(), Error> {
+        sqlx::query!(
+            "INSERT INTO permissions(user_id, chat_id, command, allowed) VALUES($1, $2, $3, $4)
+            ON CONFLICT(user_id, chat_id, command) DO UPDATE SET allowed = excluded.allowed",
+            permission.user_id,
+            permission.chat_id,
+            permission.command,
+            permission.allowed
+        )
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn remove_permission(
+        &self,
+        user_id: &str,
+        chat_id: &str,
+        command: &str,
+    ) -> Result<bool, Error> {
+        Ok(sqlx::query!(
+            "DELETE FROM permissions WHERE user_id = $1 AND chat_id = $2 AND command = $3",
+            user_id,
+            chat_id,
+            command
+        )
+        .execute(&self.db)
+        .await?
+        .rows_affected()
+            > 0)
+    }
+
+    async fn remove_chat_permissions(&self, chat_id: &str) -> Result<u64, Error> {
+        Ok(sqlx::query!(
+            "DELETE FROM permissions WHERE chat_id = $1 AND chat_id <> ''",
+            chat_id
+        )
+        .execute(&self.db)
+        .await?
+        .rows_affected())
+    }
+
+    async fn list_user_permissions(&self, user_id: &str) -> Result<Vec<Permission>, Error> {
+        Ok(sqlx::query!(
+            "SELECT user_id, chat_id, command, allowed FROM permissions WHERE user_id = $1
+            ORDER BY chat_id, command",
+            user_id
+        )
+        .fetch_all(&self.db)
+        .await?
+        .into_iter()
+        .map(|r| Permission {
+            user_id: r.user_id,
+            chat_id: r.chat_id,
+            command: r.command,
+            allowed: r.allowed,
+        })
+        .collect())
+    }
+
+    async fn list_permissions(&self) -> Result<Vec<Permission>, Error> {
+        Ok(sqlx::query!(
+            "SELECT user_id, chat_id, command, allowed FROM permissions
+            ORDER BY user_id, chat_id, command"
+        )
+        .fetch_all(&self.db)
+        .await?
+        .into_iter()
+        .map(|r| Permission {
+            user_id: r.user_id,
+            chat_id: r.chat_id,
+            command: r.command,
+            allowed: r.allowed,
+        })
+        .collect())
+    }
+}
+
+#[async_trait]
+impl BureauRepository for SqliteStorage {
+    async fn add_bureau_poll(
+        &self,
+        poll_id: &str,
+        chat_id: &str,
+        sent_at: i64,
+    ) -> Result<(), Error> {
+        sqlx::query!(
+            "INSERT INTO bureau_polls(poll_id, chat_id, sent_at) VALUES($1, $2, $3)",
+            poll_id,
+            chat_id,
+            sent_at
+        )
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn record_bureau_answer(
+        &self,
+        poll_id: &str,
+        telegram_id: &str,
+        name: &str,
+        option_id: Option<i64>,
+        answered_at: i64,
+    ) -> Result<(), Error> {
+        let mut tx = self.db.begin().await?;
+
+        if sqlx::query!(
+            "SELECT COUNT(*) AS count FROM bureau_polls WHERE poll_id = $1",
+            poll_id
+        )
+        .fetch_one(tx.as_mut())
+        .await?
+        .count
+            == 0
+        {
+            return Ok(());
+        }
+
+        match option_id {
+            None => {
+                sqlx::query!(
+                    "DELETE FROM bureau_answers WHERE poll_id = $1 AND telegram_id = $2",
+                    poll_id,
+                    telegram_id
+                )
+                .execute(tx.as_mut())
+                .await?;
+            }
+            Some(option_id) => {
+                sqlx::query!(
+                    r#"INSERT INTO bureau_answers(poll_id, telegram_id, "name", option_id, answered_at)
+                    VALUES($1, $2, $3, $4, $5)
+                    ON CONFLICT(poll_id, telegram_id) DO UPDATE
+                    SET "name" = excluded."name", option_id = excluded.option_id, answered_at = excluded.answered_at"#,
+                    poll_id,
+                    telegram_id,
+                    name,
+                    option_id,
+                    answered_at
+                )
+                .execute(tx.as_mut())
+                .await?;
+            }
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn list_bureau_answers(
+        &self,
+        chat_id: &str,
+        since: i64,
+    ) -> Result<Vec<BureauAnswer>, Error> {
+        Ok(sqlx::query!(
+            r#"SELECT p.sent_at, a.telegram_id, a."name", a.option_id, a.answered_at
+            FROM bureau_answers a JOIN bureau_polls p ON a.poll_id = p.poll_id
+            WHERE p.chat_id = $1 AND a.answered_at >= $2
+            ORDER BY a.answered_at"#,
+            chat_id,
+            since
+        )
+        .fetch_all(&self.db)
+        .await?
+        .into_iter()
+        .map(|r| BureauAnswer {
+            poll_sent_at: r.sent_at,
+            telegram_id: r.telegram_id,
+            name: r.name,
+            option_id: r.option_id,
+            answered_at: r.answered_at,
+        })
+        .collect())
+    }
+}
+
+#[async_trait]
+impl PermanenceRepository for SqliteStorage {
+    async fn list_slots(&self, chat_id: Option<&str>) -> Result<Vec<PermanenceSlot>, Error> {
+        let rows = sqlx::query!(
+            r#"SELECT s.id, s.chat_id, s.weekday, s.start_minute, s.duration, s.last_reminder,
+                u.telegram_id AS "telegram_id?", u."name" AS "name?"
+            FROM permanence_slots s LEFT JOIN permanence_signups u ON s.id = u.slot_id
+            WHERE $1 IS NULL OR s.chat_id = $1
+            ORDER BY s.weekday, s.start_minute, s.id, u."name""#,
+            chat_id
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        let mut slots = BTreeMap::<(i64, i64, i64), PermanenceSlot>::new();
+        for row in rows {
+            let slot = slots
+                .entry((row.weekday, row.start_minute, row.id))
+                .or_insert_with(|| PermanenceSlot {
+                    id: row.id,
+                    chat_id: row.chat_id,
+                    weekday: row.weekday,
+                    start_minute: row.start_minute,
+                    duration: row.duration,
+                    last_reminder: row.last_reminder,
+                    assignees: vec![],
+                });
+
+            if let (Some(telegram_id), Some(name)) = (row.telegram_id, row.name) {
+                slot.assignees.push(Assignee { telegram_id, name });
+            }
+        }
+
+        Ok(slots.into_values().collect())
+    }
+
+    async fn add_slot(
+        &self,
+        chat_id: &str,
+        weekday: i64,
+        start_minute: i64,
+        duration: i64,
+    ) -> Result<i64, Error> {
+        Ok(sqlx::query!(
+            "INSERT INTO permanence_slots(chat_id, weekday, start_minute, duration) VALUES($1, $2, $3, $4)",
+            chat_id,
+            weekday,
+            start_minute,
+            duration
+        )
+        .execute(&self.db)
+        .await?
+        .last_insert_rowid())
+    }
+
+    async fn remove_slot(&self, chat_id: &str, slot_id: i64) -> Result<bool, Error> {
+        Ok(sqlx::query!(
+            "DELETE FROM permanence_slots WHERE id = $1 AND chat_id = $2",
+            slot_id,
+            chat_id
+        )
+        .execute(&self.db)
+        .await?
+        .rows_affected()
+            > 0)
+    }
+
+    async fn remove_chat_slots(&self, chat_id: &str) -> Result<u64, Error> {
+        // The signups are deleted in cascade
+        Ok(
+            sqlx::query!("DELETE FROM permanence_slots WHERE chat_id = $1", chat_id)
+                .execute(&self.db)
+                .await?
+                .rows_affected(),
+        )
+    }
+
+    async fn toggle_signup(
+        &self,
+        chat_id: &str,
+        slot_id: i64,
+        telegram_id: &str,
+        name: &str,
+    ) -> Result<Option<bool>, Error> {
+        let mut tx = self.db.begin().await?;
+
+        if sqlx::query!(
+            "SELECT COUNT(*) AS count FROM permanence_slots WHERE id = $1 AND chat_id = $2",
+            slot_id,
+            chat_id
+        )
+        .fetch_one(tx.as_mut())
+        .await?
+        .count
+            == 0
+        {
+            return Ok(None);
+        }
+
+        let registered = sqlx::query!(
+            "SELECT COUNT(*) AS count FROM permanence_signups WHERE slot_id = $1 AND telegram_id = $2",
+            slot_id,
+            telegram_id
+        )
+        .fetch_one(tx.as_mut())
+        .await?
+        .count
+            > 0;
+
+        if registered {
+            sqlx::query!(
+                "DELETE FROM permanence_signups WHERE slot_id = $1 AND telegram_id = $2",
+                slot_id,
+                telegram_id
+            )
+            .execute(tx.as_mut())
+            .await?;
+        } else {
+            sqlx::query!(
+                r#"INSERT INTO permanence_signups(slot_id, telegram_id, "name") VALUES($1, $2, $3)"#,
+                slot_id,
+                telegram_id,
+                name
+            )
+            .execute(tx.as_mut())
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(Some(!registered))
+    }
+
+    async fn set_last_reminder(&self, slot_id: i64, timestamp: i64) -> Result<(), Error> {
+        sqlx::query!(
+            "UPDATE permanence_slots SET last_reminder = $1 WHERE id = $2",
+            timestamp,
+            slot_id
+        )
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl EventRepository for SqliteStorage {
+    async fn subscribe_events(&self, chat_id: &str) -> Result<bool, Error> {
+        Ok(sqlx::query!(
+            "INSERT INTO event_subscriptions(chat_id) VALUES($1) ON CONFLICT DO NOTHING",
+            chat_id
+        )
+        .execute(&self.db)
+        .await?
+        .rows_affected()
+            > 0)
+    }
+
+    async fn unsubscribe_events(&self, chat_id: &str) -> Result<bool, Error> {
+        Ok(sqlx::query!(
+            "DELETE FROM event_subscriptions WHERE chat_id = $1",
+            chat_id
+        )
+        .execute(&self.db)
+        .await?
+        .rows_affected()
+            > 0)
+    }
+
+    async fn list_event_subscriptions(&self) -> Result<Vec<String>, Error> {
+        Ok(sqlx::query!("SELECT chat_id FROM event_subscriptions")
+            .fetch_all(&self.db)
+            .await?
+            .into_iter()
+            .map(|r| r.chat_id)
+            .collect())
+    }
+
+    async fn has_announced_events(&self) -> Result<bool, Error> {
+        Ok(
+            sqlx::query!(r#"SELECT EXISTS(SELECT 1 FROM announced_events) AS "exists!: bool""#)
+                .fetch_one(&self.db)
+                .await?
+                .exists,
+        )
+    }
+
+    async fn event_reminded(&self, event_id: i32) -> Result<Option<bool>, Error> {
+        Ok(sqlx::query!(
+            "SELECT reminded FROM announced_events WHERE event_id = $1",
+            event_id
+        )
+        .fetch_optional(&self.db)
+        .await?
+        .map(|r| r.reminded))
+    }
+
+    async fn mark_event_announced(&self, event_id: i32) -> Result<(), Error> {
+        sqlx::query!(
+            "INSERT INTO announced_events(event_id) VALUES($1) ON CONFLICT DO NOTHING",
+            event_id
+        )
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn mark_event_reminded(&self, event_id: i32) -> Result<(), Error> {
+        sqlx::query!(
+            "UPDATE announced_events SET reminded = TRUE WHERE event_id = $1",
+            event_id
+        )
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl LanguageRepository for SqliteStorage {
+    async fn get_language(&self, id: &str) -> Result<Option<String>, Error> {
+        Ok(
+            sqlx::query!(r#"SELECT "language" FROM languages WHERE id = $1"#, id)
+                .fetch_optional(&self.db)
+                .await?
+                .map(|r| r.language),
+        )
+    }
+
+    async fn set_language(&self, id: &str, language: &str) -> Result<(), Error> {
+        sqlx::query!(
+            r#"INSERT INTO languages(id, "language") VALUES($1, $2)
+            ON CONFLICT(id) DO UPDATE SET "language" = excluded."language""#,
+            id,
+            language
+        )
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn list_languages(&self) -> Result<Vec<LanguagePreference>, Error> {
+        Ok(
+            sqlx::query!(r#"SELECT id, "language" FROM languages ORDER BY id"#)
+                .fetch_all(&self.db)
+                .await?
+                .into_iter()
+                .map(|r| LanguagePreference {
+                    id: r.id,
+                    language: r.language,
+                })
+                .collect(),
+        )
+    }
+}
+
+#[async_trait]
+impl ChatRepository for SqliteStorage {
+    async fn save_chat(&self, chat: &KnownChat) -> Result<(), Error> {
+        sqlx::query!(
+            "INSERT INTO chats(chat_id, title, kind, joined_at) VALUES($1, $2, $3, $4)
+            ON CONFLICT(chat_id) DO UPDATE SET title = excluded.title, kind = excluded.kind,
+            joined_at = COALESCE(excluded.joined_at, chats.joined_at)",
+            chat.chat_id,
+            chat.title,
+            chat.kind,
+            chat.joined_at
+        )
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn remove_chat(&self, chat_id: &str) -> Result<bool, Error> {
+        Ok(
+            sqlx::query!("DELETE FROM chats WHERE chat_id = $1", chat_id)
+                .execute(&self.db)
+                .await?
+                .rows_affected()
+                > 0,
+        )
+    }
+
+    async fn list_chats(&self) -> Result<Vec<KnownChat>, Error> {
+        Ok(sqlx::query!(
+            "SELECT chat_id, title, kind, joined_at FROM chats ORDER BY title, chat_id"
+        )
+        .fetch_all(&self.db)
+        .await?
+        .into_iter()
+        .map(|r| KnownChat {
+            chat_id: r.chat_id,
+            title: r.title,
+            kind: r.kind,
+            joined_at: r.joined_at,
+        })
+        .collect())
+    }
+}
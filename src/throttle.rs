@@ -0,0 +1,47 @@
+use std::{
+    collections::BTreeMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Limits how often something is done for a given key, e.g. answering the rejected commands of a
+/// chat. The keys of a throttle can have different intervals.
+#[derive(Default)]
+pub struct Throttle {
+    /// Maps the keys to the time until which they are not allowed again.
+    until: Mutex<BTreeMap<String, Instant>>,
+}
+
+impl Throttle {
+    pub const fn new() -> Self {
+        Self {
+            until: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Returns whether the key is allowed, and if so records that it is not allowed again during
+    /// `interval`.
+    pub fn allow(&self, key: &str, interval: Duration) -> bool {
+        let mut until = self.until.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+
+        // The expired entries are dropped so that the map does not grow indefinitely
+        until.retain(|_, until| *until > now);
+        if until.contains_key(key) {
+            return false;
+        }
+
+        until.insert(key.to_owned(), now + interval);
+        true
+    }
+
+    /// Returns how long the key is still not allowed, `None` if it is allowed (without recording
+    /// it, unlike [`Throttle::allow`]).
+    pub fn remaining(&self, key: &str) -> Option<Duration> {
+        let until = self.until.lock().unwrap_or_else(|e| e.into_inner());
+        until
+            .get(key)
+            .map(|until| until.saturating_duration_since(Instant::now()))
+            .filter(|remaining| !remaining.is_zero())
+    }
+}
@@ -0,0 +1,217 @@
+use std::sync::{Arc, Mutex};
+
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    routing::post,
+    Router,
+};
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+use teloxide::{requests::Requester, Bot};
+use tokio::net::TcpListener;
+
+use crate::{
+    config::config,
+    directus::{invalidate_committee, refresh_committee, Committee},
+    i18n::chat_language,
+    storage::LanguageRepository,
+    t,
+};
+
+/// Header containing the HMAC-SHA256 of the body, as `sha256=<hex digest>`.
+const SIGNATURE_HEADER: &str = "X-Signature-256";
+/// Collections whose changes affect the committee.
+const COMMITTEE_COLLECTIONS: [&str; 2] = ["association_memberships", "members"];
+
+/// Committee as of the last notification, to which the next one is compared. Unlike the cache of
+/// [`crate::directus::get_committee`], it is never invalidated.
+static LAST_SEEN: Mutex<Option<Vec<Committee>>> = Mutex::new(None);
+
+/// Replaces the last seen committee, and returns the previous one.
+fn replace_last_seen(committee: Vec<Committee>) -> Option<Vec<Committee>> {
+    LAST_SEEN
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .replace(committee)
+}
+
+/// Body sent by the Directus Flow. Only the collection is used, since the committee is
+/// fetched again anyway.
+#[derive(Deserialize, Debug)]
+struct Notification {
+    collection: Option<String>,
+}
+
+/// Runs the HTTP server receiving the notifications of Directus, if `WEBHOOK_ADDRESS` and
+/// `WEBHOOK_SECRET` are set.
+pub async fn serve_webhook(bot: Bot, languages: Arc<dyn LanguageRepository>) {
+    let Some(address) = config().webhook_address else {
+        log::info!("WEBHOOK_ADDRESS is not set, the Directus webhook receiver is disabled");
+        return;
+    };
+    if config().webhook_secret.is_none() {
+        log::error!("WEBHOOK_SECRET must be set to enable the Directus webhook receiver");
+        return;
+    }
+
+    // So that the first change can be compared against it
+    match refresh_committee().await {
+        Ok(committee) => {
+            replace_last_seen(committee);
+        }
+        Err(e) => log::error!("Could not fetch committee: {e}"),
+    }
+
+    let app = Router::new()
+        .route("/directus/webhook", post(receive_notification))
+        .with_state((bot, languages));
+
+    let listener = match TcpListener::bind(address).await {
+        Ok(l) => l,
+        Err(e) => {
+            log::error!("Could not bind the webhook receiver to {address}: {e}");
+            return;
+        }
+    };
+
+    log::info!("Listening for Directus notifications on {address}");
+    if let Err(e) = axum::serve(listener, app).await {
+        log::error!("Webhook receiver stopped: {e}");
+    }
+}
+
+async fn receive_notification(
+    State((bot, languages)): State<(Bot, Arc<dyn LanguageRepository>)>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> StatusCode {
+    if !verify_signature(&headers, &body) {
+        log::warn!("Received a Directus notification with an invalid signature");
+        return StatusCode::UNAUTHORIZED;
+    }
+
+    let notification = match serde_json::from_slice::<Notification>(&body) {
+        Ok(n) => n,
+        Err(e) => {
+            log::warn!("Received an invalid Directus notification: {e}");
+            return StatusCode::BAD_REQUEST;
+        }
+    };
+
+    log::debug!("Received Directus notification: {notification:?}");
+    if !notification
+        .collection
+        .is_some_and(|c| COMMITTEE_COLLECTIONS.contains(&c.as_str()))
+    {
+        return StatusCode::NO_CONTENT;
+    }
+
+    let current = match refresh_committee().await {
+        Ok(c) => c,
+        Err(e) => {
+            log::error!("Could not fetch committee after a notification: {e}");
+            invalidate_committee();
+            return StatusCode::BAD_GATEWAY;
+        }
+    };
+
+    match replace_last_seen(current.clone()) {
+        Some(previous) => announce_changes(&bot, languages.as_ref(), &previous, &current).await,
+        None => log::warn!(
+            "The committee could not be fetched on startup, its changes will be announced from \
+             the next notification"
+        ),
+    }
+
+    StatusCode::NO_CONTENT
+}
+
+fn verify_signature(headers: &HeaderMap, body: &[u8]) -> bool {
+    let Some(secret) = &config().webhook_secret else {
+        return false;
+    };
+    let Some(signature) = headers
+        .get(SIGNATURE_HEADER)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("sha256="))
+        .and_then(|h| hex::decode(h).ok())
+    else {
+        return false;
+    };
+
+    let Ok(mut mac) = Hmac::<Sha256>::new_from_slice(secret.as_bytes()) else {
+        return false;
+    };
+    mac.update(body);
+    mac.verify_slice(&signature).is_ok()
+}
+
+/// Announces the new members and the departures in the chats of `COMMITTEE_ANNOUNCE_CHATS`.
+async fn announce_changes(
+    bot: &Bot,
+    languages: &dyn LanguageRepository,
+    previous: &[Committee],
+    current: &[Committee],
+) {
+    let arrivals = current
+        .iter()
+        .filter(|c| !previous.iter().any(|p| p.id == c.id))
+        .map(|c| ("committee.arrival", &c.name));
+    let departures = previous
+        .iter()
+        .filter(|p| !current.iter().any(|c| c.id == p.id))
+        .map(|p| ("committee.departure", &p.name));
+    let changes = arrivals.chain(departures).collect::<Vec<_>>();
+
+    if changes.is_empty() {
+        return;
+    }
+
+    for &chat in &config().committee_announce_chats {
+        let lang = chat_language(languages, chat).await;
+        for (key, name) in &changes {
+            let message = t!(lang, key, name = name);
+            if let Err(e) = bot.send_message(chat, message).await {
+                log::error!("Could not announce committee change in chat {chat}: {e}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::load_test_config;
+
+    fn headers(signature: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(SIGNATURE_HEADER, signature.parse().unwrap());
+        headers
+    }
+
+    fn sign(secret: &str, body: &[u8]) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(body);
+        format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+    }
+
+    #[test]
+    fn verifies_signatures() {
+        load_test_config();
+        let body = br#"{"collection":"committee"}"#;
+        let signature = sign("secret", body);
+
+        assert!(verify_signature(&headers(&signature), body));
+        assert!(!verify_signature(&headers(&signature), b"{}"));
+        assert!(!verify_signature(&headers(&sign("other", body)), body));
+        assert!(!verify_signature(
+            &headers(signature.trim_start_matches("sha256=")),
+            body
+        ));
+        assert!(!verify_signature(&headers("sha256=not hex"), body));
+        assert!(!verify_signature(&HeaderMap::new(), body));
+    }
+}